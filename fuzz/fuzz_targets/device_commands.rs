@@ -0,0 +1,33 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use virtusdev::bill_emulator::PollEvent;
+use virtusdev::essp_protocol::*;
+use virtusdev::serial_bridge::default_devices;
+
+// Host traffic against the default kiosk devices, the way the serial bridge
+// dispatches it
+fuzz_target!(|data: &[u8]| {
+    let mut devices = default_devices();
+    let mut scanner = FrameScanner::new();
+
+    for item in scanner.push(data) {
+        let ScanItem::Frame(packet, _) = item else { continue };
+        let Some(device) = devices.get_mut(&(packet.sequence & 0x7F)) else { continue };
+
+        let flag = packet.sequence & 0x80 != 0;
+        // A retransmission is answered with the reply to the previous command
+        let retransmission = device.is_retransmission(flag, &packet.data);
+        let (status, reply) = device.handle_sequenced_command(flag, &packet.data);
+        if !retransmission && packet.data.first() == Some(&CMD_POLL) && status == RESPONSE_OK {
+            assert!(PollEvent::parse_poll_data(&reply).is_some(), "malformed POLL reply {:02X?}", reply);
+        }
+
+        // Every reply must fit in a frame and read back unchanged
+        let response = build_response(packet.sequence, status, &reply);
+        let (parsed, _) = EsspPacket::from_bytes(&response.to_bytes()).expect("reply is not a valid frame");
+        assert_eq!(parsed.data, response.data);
+
+        device.status();
+    }
+});
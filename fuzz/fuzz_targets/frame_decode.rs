@@ -0,0 +1,46 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use virtusdev::capture::{Direction, FrameDecoder};
+use virtusdev::essp_protocol::*;
+use virtusdev::sniffer::EventRewriter;
+
+// Bytes from the serial line, as read by the bridge, the sniffer and
+// virtusdev-decode
+fuzz_target!(|data: &[u8]| {
+    if let Ok((packet, consumed)) = EsspPacket::from_bytes(data) {
+        assert!(consumed <= data.len());
+        assert!(!EsspPacket::is_incomplete(&data[..consumed]));
+        // Stuffing is unambiguous, a frame has exactly one encoding
+        assert_eq!(packet.to_bytes(), &data[..consumed]);
+    }
+
+    // Deliver the stream in two reads split where the first byte says
+    let split = data.first().map_or(0, |&b| b as usize).min(data.len());
+    let mut scanner = FrameScanner::new();
+    let mut items = scanner.push(&data[..split]);
+    items.extend(scanner.push(&data[split..]));
+
+    let mut decoder = FrameDecoder::new();
+    let mut rewriter = EventRewriter::new(vec![
+        "credit->rejected".parse().unwrap(),
+        "inject:jammed@0x00".parse().unwrap(),
+    ]);
+    let mut scanned = 0;
+    for (index, item) in items.iter().enumerate() {
+        match item {
+            ScanItem::Frame(packet, raw) => {
+                scanned += raw.len();
+                // Alternate directions so that replies are decoded against commands
+                let direction = if index % 2 == 0 { Direction::HostToDevice } else { Direction::DeviceToHost };
+                assert!(decoder.decode(direction, raw, 0).is_some());
+                if let Some(rewritten) = rewriter.rewrite(Some(CMD_POLL), packet) {
+                    let _ = rewritten.to_bytes();
+                }
+            }
+            ScanItem::Garbage(bytes) => scanned += bytes.len(),
+        }
+    }
+    // Every byte is reported exactly once or still waiting for the rest of a frame
+    assert_eq!(scanned + scanner.pending().len(), data.len());
+});
@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use virtusdev::essp_protocol::*;
+
+fuzz_target!(|data: &[u8]| {
+    let stuffed = stuff_bytes(data);
+    assert_eq!(unstuff_bytes(&stuffed).unwrap(), data);
+    let _ = unstuff_bytes(data);
+
+    // First byte is SEQ/ADDR, the rest the frame data
+    if let Some((&sequence, payload)) = data.split_first() {
+        // LEN is a single byte counting the unstuffed data
+        if payload.len() <= 255 {
+            let bytes = EsspPacket::new(sequence, payload.to_vec()).to_bytes();
+            let (packet, consumed) = EsspPacket::from_bytes(&bytes).unwrap();
+            assert_eq!(consumed, bytes.len());
+            assert_eq!(packet.sequence, sequence);
+            assert_eq!(packet.data, payload);
+            // Only the STX starts a frame, every other 0x7F is doubled
+            assert!(!EsspPacket::is_incomplete(&bytes));
+            assert!(unstuff_bytes(&bytes[1..]).is_ok());
+        }
+    }
+});
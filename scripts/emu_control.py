@@ -0,0 +1,71 @@
+#!/usr/bin/env python3
+# -*- coding: utf-8 -*-
+"""
+Minimal client for the bill emulator control socket (JSON-RPC 2.0)
+
+Start the emulator with:
+    ./target/release/bill_emulator --control /tmp/virtusdev.sock
+
+Usage from a test:
+    from emu_control import EmulatorControl
+    emu = EmulatorControl("/tmp/virtusdev.sock")
+    emu.call("set_enabled", address=0x00, enabled=True)
+    emu.call("insert_note", address=0x00, value=2000)
+"""
+
+import json
+import socket
+import sys
+
+
+class EmulatorError(Exception):
+    pass
+
+
+class EmulatorControl:
+    def __init__(self, path):
+        self.sock = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+        self.sock.connect(path)
+        self.file = self.sock.makefile("rw")
+        self.next_id = 1
+        self.pending_events = []
+
+    def call(self, method, **params):
+        request_id = self.next_id
+        self.next_id += 1
+        self.file.write(json.dumps({"jsonrpc": "2.0", "id": request_id,
+                                    "method": method, "params": params}) + "\n")
+        self.file.flush()
+
+        while True:
+            message = json.loads(self.file.readline())
+            if message.get("method") == "event":
+                # Notifications can arrive before our response
+                self.pending_events.append(message["params"])
+                continue
+            if "error" in message:
+                raise EmulatorError(message["error"]["message"])
+            return message["result"]
+
+    def events(self):
+        """Yield captured frames after call("subscribe")"""
+        while self.pending_events:
+            yield self.pending_events.pop(0)
+        for line in self.file:
+            yield json.loads(line)["params"]
+
+
+if __name__ == "__main__":
+    if len(sys.argv) < 3:
+        print("Usage: emu_control.py SOCKET METHOD [key=value ...]")
+        sys.exit(1)
+
+    params = {}
+    for arg in sys.argv[3:]:
+        key, value = arg.split("=", 1)
+        try:
+            params[key] = json.loads(value)
+        except ValueError:
+            params[key] = value  # plain strings such as 0x10 or jam
+
+    print(json.dumps(EmulatorControl(sys.argv[1]).call(sys.argv[2], **params), indent=2))
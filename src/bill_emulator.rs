@@ -1,7 +1,6 @@
 use std::collections::{HashMap, VecDeque};
+use serde::Serialize;
 use crate::essp_protocol::*;
-use std::fs::OpenOptions;
-use std::io::Write;
 
 // Device types
 pub const UNIT_TYPE_NV200: u8 = 0x06;  // Note validator
@@ -78,6 +77,55 @@ impl PollEvent {
         }
         bytes
     }
+
+    /// Number of data bytes following the event code (mirrors `to_bytes`)
+    pub fn data_len(event_code: u8) -> usize {
+        match event_code {
+            EVENT_READ | EVENT_CREDIT => 1,
+            EVENT_COINS_VALUE_ADDED | EVENT_DISPENSING | EVENT_DISPENSED => 7,
+            _ => 0,
+        }
+    }
+
+    /// Parse one event, returns the event and number of bytes consumed
+    pub fn from_bytes(bytes: &[u8]) -> Option<(Self, usize)> {
+        let event_code = *bytes.first()?;
+        let len = Self::data_len(event_code);
+        let data = bytes.get(1..1 + len)?;
+
+        let mut event = Self::new(event_code, 0);
+        match len {
+            1 => event.data1 = data[0] as u32,
+            7 => {
+                event.data1 = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+                event.currency.copy_from_slice(&data[4..7]);
+            }
+            _ => {}
+        }
+        Some((event, 1 + len))
+    }
+
+    /// Parse the data of a POLL response (after the status byte): count followed by events
+    pub fn parse_poll_data(data: &[u8]) -> Option<Vec<Self>> {
+        let (&count, mut rest) = data.split_first()?;
+        let mut events = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let (event, consumed) = Self::from_bytes(rest)?;
+            events.push(event);
+            rest = &rest[consumed..];
+        }
+        Some(events)
+    }
+
+    /// Short description such as "CREDIT(ch 2)" or "DISPENSED(500 BRL)"
+    pub fn describe(&self) -> String {
+        let name = event_name(self.event_code);
+        match Self::data_len(self.event_code) {
+            1 => format!("{}(ch {})", name, self.data1),
+            7 => format!("{}({} {})", name, self.data1, String::from_utf8_lossy(&self.currency)),
+            _ => name.to_string(),
+        }
+    }
 }
 
 /// Device state for a single validator
@@ -94,6 +142,10 @@ pub struct DeviceState {
     pub balance: HashMap<u32, u16>,  // value in cents -> count
     pub event_queue: VecDeque<PollEvent>,
     pub channels: Vec<ChannelData>,
+    /// Sequence flag of the last command executed (None until the first command)
+    pub last_sequence_flag: Option<bool>,
+    /// Response to the last command, resent when the host retransmits
+    pub last_response: Option<(u8, Vec<u8>)>,
 }
 
 #[derive(Debug, Clone)]
@@ -141,6 +193,8 @@ impl DeviceState {
             balance,
             event_queue,
             channels,
+            last_sequence_flag: None,
+            last_response: None,
         }
     }
 
@@ -179,24 +233,56 @@ impl DeviceState {
             balance,
             event_queue,
             channels,
+            last_sequence_flag: None,
+            last_response: None,
         }
     }
 
 // ...
 
+    /// True when a command with this sequence flag is a retransmission of the
+    /// last one. SYNC always executes since it resets the sequence.
+    pub fn is_retransmission(&self, sequence_flag: bool, cmd: &[u8]) -> bool {
+        cmd.first() != Some(&CMD_SYNC)
+            && self.last_response.is_some()
+            && self.last_sequence_flag == Some(sequence_flag)
+    }
+
+    /// Handle a command received with the given sequence flag.
+    ///
+    /// A real eSSP slave remembers the flag of the last command. If the host sends
+    /// the same flag again it missed our reply, so the cached response is resent
+    /// without executing the command a second time (a retried PAYOUT must not pay twice).
+    pub fn handle_sequenced_command(&mut self, sequence_flag: bool, cmd: &[u8]) -> (u8, Vec<u8>) {
+        if self.is_retransmission(sequence_flag, cmd) {
+            if let Some(response) = &self.last_response {
+                return response.clone();
+            }
+        }
+
+        let response = self.handle_command(cmd);
+        self.last_sequence_flag = Some(sequence_flag);
+        self.last_response = Some(response.clone());
+        response
+    }
+
     /// Handle command and return (status, response_data)
     pub fn handle_command(&mut self, cmd: &[u8]) -> (u8, Vec<u8>) {
         if cmd.is_empty() {
             return (RESPONSE_OK, vec![]);
         }
 
-        // Log command for debugging
-        if let Ok(mut file) = OpenOptions::new().create(true).append(true).open("/tmp/emu_cmd.log") {
-            let _ = writeln!(file, "CMD: 0x{:02X} (len={})", cmd[0], cmd.len());
-        }
-
         match cmd[0] {
             CMD_SYNC => (RESPONSE_OK, vec![]),  // Just OK
+
+            CMD_RESET => {
+                // Device reboots: comes back disabled and reports RESET
+                self.enabled = false;
+                self.payout_enabled = false;
+                self.event_queue.clear();
+                self.event_queue.push_back(PollEvent::new(EVENT_RESET, 0));
+                (RESPONSE_OK, vec![])
+            }
             
             CMD_HOST_PROTOCOL => {
                 // cmd[1] should be 0x06 for protocol version 6
@@ -370,11 +456,19 @@ impl DeviceState {
         response
     }
 
+    /// Event carrying a value in the currency of this device
+    fn value_event(&self, event_code: u8, value: u32) -> PollEvent {
+        PollEvent {
+            currency: self.currency_code,
+            ..PollEvent::new(event_code, value)
+        }
+    }
+
     fn handle_payout(&mut self, amount: u32) {
         // Simulate payout by reducing balance
         // Generate dispensing and dispensed events
-        self.event_queue.push_back(PollEvent::new(EVENT_DISPENSING, amount));
-        self.event_queue.push_back(PollEvent::new(EVENT_DISPENSED, amount));
+        self.event_queue.push_back(self.value_event(EVENT_DISPENSING, amount));
+        self.event_queue.push_back(self.value_event(EVENT_DISPENSED, amount));
         
         // Actually reduce balance (simplified - just reduce largest denomination)
         if let Some((_value, count)) = self.balance.iter_mut().max_by_key(|(k, _)| *k) {
@@ -384,8 +478,8 @@ impl DeviceState {
         }
     }
 
-    /// Insert a bill (for GUI simulation)
-    pub fn insert_note(&mut self, value: u32) {
+    /// Insert a bill (for GUI simulation). Returns false if the note was not accepted.
+    pub fn insert_note(&mut self, value: u32) -> bool {
         if self.enabled {
             // Find channel for this value
             let mut channel_idx = 0;
@@ -405,19 +499,160 @@ impl DeviceState {
                 if self.balance.contains_key(&value) {
                     *self.balance.get_mut(&value).unwrap() += 1;
                 }
+                return true;
             }
         }
+        false
     }
 
-    /// Insert coins (for GUI simulation)
-    pub fn insert_coins(&mut self, value: u32) {
+    /// Insert coins (for GUI simulation). Returns false if the device is disabled.
+    pub fn insert_coins(&mut self, value: u32) -> bool {
         if self.enabled {
-            self.event_queue.push_back(PollEvent::new(EVENT_COINS_VALUE_ADDED, value));
+            self.event_queue.push_back(self.value_event(EVENT_COINS_VALUE_ADDED, value));
             
             // Add to balance
             if self.balance.contains_key(&value) {
                 *self.balance.get_mut(&value).unwrap() += 1;
             }
+            return true;
+        }
+        false
+    }
+
+    /// Queue the poll events a real device reports for `fault`
+    pub fn inject_fault(&mut self, fault: DeviceFault) {
+        let events: &[u8] = match fault {
+            DeviceFault::Jam => &[EVENT_JAMMED],
+            DeviceFault::CashboxRemoved => &[EVENT_CASHBOX_REMOVED],
+            DeviceFault::Reject => &[EVENT_REJECTING, EVENT_REJECTED],
+            DeviceFault::Reset => &[EVENT_RESET],
+        };
+        for &event in events {
+            self.event_queue.push_back(PollEvent::new(event, 0));
+        }
+    }
+
+    /// Set the stored count of a denomination (value in cents)
+    pub fn set_level(&mut self, value: u32, count: u16) {
+        self.balance.insert(value, count);
+    }
+
+    /// Enable or disable acceptance as if the host had sent ENABLE / DISABLE
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if self.enabled && !enabled {
+            self.event_queue.push_back(PollEvent::new(EVENT_DISABLED, 0));
+        }
+        self.enabled = enabled;
+    }
+
+    /// Snapshot of the state for control interfaces
+    pub fn status(&self) -> DeviceStatus {
+        let mut levels: Vec<Level> = self
+            .balance
+            .iter()
+            .map(|(&value, &count)| Level { value, count })
+            .collect();
+        levels.sort_by_key(|level| level.value);
+
+        DeviceStatus {
+            address: self.address,
+            unit_type: self.unit_type,
+            currency: String::from_utf8_lossy(&self.currency_code).into_owned(),
+            enabled: self.enabled,
+            payout_enabled: self.payout_enabled,
+            inhibits: [self.inhibit_mask_low, self.inhibit_mask_high],
+            channels: self.channels.iter().map(|c| c.value).collect(),
+            levels,
+            queued_events: self.event_queue.iter().map(PollEvent::describe).collect(),
+        }
+    }
+}
+
+/// Faults that can be injected into a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceFault {
+    Jam,
+    CashboxRemoved,
+    Reject,
+    Reset,
+}
+
+impl std::str::FromStr for DeviceFault {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "jam" | "jammed" => Ok(DeviceFault::Jam),
+            "cashbox" | "cashbox_removed" | "cashbox-removed" => Ok(DeviceFault::CashboxRemoved),
+            "reject" | "rejected" => Ok(DeviceFault::Reject),
+            "reset" => Ok(DeviceFault::Reset),
+            _ => Err(anyhow::anyhow!(
+                "Unknown fault '{}' (expected jam, cashbox_removed, reject or reset)",
+                s
+            )),
         }
     }
 }
+
+/// Stored count of one denomination
+#[derive(Debug, Clone, Serialize)]
+pub struct Level {
+    pub value: u32,
+    pub count: u16,
+}
+
+/// Serializable snapshot of a `DeviceState`
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceStatus {
+    pub address: u8,
+    pub unit_type: u8,
+    pub currency: String,
+    pub enabled: bool,
+    pub payout_enabled: bool,
+    pub inhibits: [u8; 2],
+    /// Channel values in cents, channel 1 first
+    pub channels: Vec<u32>,
+    pub levels: Vec<Level>,
+    pub queued_events: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retransmitted_poll_keeps_events() {
+        let mut device = DeviceState::new_note_device(0x00);
+        device.enabled = true;
+        device.insert_note(500);
+
+        let first = device.handle_sequenced_command(true, &[CMD_POLL]);
+        let retry = device.handle_sequenced_command(true, &[CMD_POLL]);
+        assert_eq!(first, retry);
+
+        // Next poll with the toggled flag has nothing left to report
+        let (_, next) = device.handle_sequenced_command(false, &[CMD_POLL]);
+        assert_eq!(next, vec![1, EVENT_DISABLED]);
+    }
+
+    #[test]
+    fn test_retransmitted_payout_pays_once() {
+        let mut device = DeviceState::new_coin_device(0x10);
+        let payout = [CMD_PAYOUT, 0x64, 0x00, 0x00, 0x00, b'U', b'S', b'D'];
+
+        device.handle_sequenced_command(false, &payout);
+        device.handle_sequenced_command(false, &payout);
+        assert_eq!(device.event_queue.len(), 3); // RESET + DISPENSING + DISPENSED
+
+        device.handle_sequenced_command(true, &payout);
+        assert_eq!(device.event_queue.len(), 5);
+    }
+
+    #[test]
+    fn test_sync_always_executes() {
+        let mut device = DeviceState::new_note_device(0x00);
+        device.handle_sequenced_command(true, &[CMD_SYNC]);
+        assert!(!device.is_retransmission(true, &[CMD_SYNC]));
+        assert!(device.is_retransmission(true, &[CMD_POLL]));
+    }
+}
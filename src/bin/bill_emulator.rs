@@ -1,14 +1,326 @@
-use virtusdev::serial_bridge::SerialBridge;
-use std::io::{self, Write};
+use virtusdev::bridge_manager::BridgeManager;
+use virtusdev::capture::{create_capture, read_capture, CaptureBroadcast};
+use virtusdev::control::{ControlServer, Devices};
+use virtusdev::http_api::HttpServer;
+use virtusdev::line_noise::{FaultRule, LineNoise};
+use virtusdev::replay::{describe_divergence, replay_host, ReplayDevice};
+use virtusdev::repl::{self, ReplCommand};
+use virtusdev::scenario::Scenario;
+use virtusdev::event_loop::ShutdownHandle;
+use virtusdev::serial_bridge::{default_devices, SerialBridge};
+use anyhow::{anyhow, Context};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Editor, Helper};
+use std::path::{Path, PathBuf};
 use std::thread;
 
+const USAGE: &str = "\
+Usage: bill_emulator [OPTIONS]
+
+Options:
+  --fault RULE   Inject a line/protocol error into responses (repeatable)
+                   KIND:PROBABILITY        e.g. corrupt-crc:0.05
+                   KIND@[COMMAND#]N        e.g. drop@poll#5, duplicate@3
+                 Kinds: corrupt-crc, drop, duplicate, truncate, stray,
+                        wrong-seq, wrong-addr
+  --seed N       Seed for probabilistic faults (printed at startup)
+  --capture FILE Record all traffic to FILE (.pcapng or JSONL, repeatable)
+  --replay-device FILE
+                 Answer the host with the responses recorded in FILE
+  --replay-host FILE
+                 Send the commands recorded in FILE to the emulated devices,
+                 report where responses diverge and exit
+  --control PATH Serve the JSON-RPC control API on the Unix socket PATH
+  --http ADDR    Serve the REST/WebSocket API on ADDR (e.g. 127.0.0.1:8080)
+  --buses FILE   Emulate the independent buses described in the JSON file
+                 FILE, each with its own PTY and devices (see README)
+  --scenario FILE
+                 Run the scripted test scenario in FILE instead of reading
+                 stdin, exit with 0 if it passes and 1 if it fails
+  -h, --help     Show this help";
+
+struct Options {
+    faults: Vec<FaultRule>,
+    seed: Option<u64>,
+    captures: Vec<PathBuf>,
+    replay_device: Option<PathBuf>,
+    replay_host: Option<PathBuf>,
+    control: Option<PathBuf>,
+    http: Option<String>,
+    scenario: Option<PathBuf>,
+    buses: Option<PathBuf>,
+}
+
+fn parse_args() -> anyhow::Result<Options> {
+    let mut options = Options {
+        faults: Vec::new(),
+        seed: None,
+        captures: Vec::new(),
+        replay_device: None,
+        replay_host: None,
+        control: None,
+        http: None,
+        scenario: None,
+        buses: None,
+    };
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fault" => {
+                let rule = args.next().ok_or_else(|| anyhow!("--fault needs a rule"))?;
+                options.faults.push(rule.parse()?);
+            }
+            "--seed" => {
+                let seed = args.next().ok_or_else(|| anyhow!("--seed needs a value"))?;
+                options.seed = Some(seed.parse().context("Invalid --seed value")?);
+            }
+            "--capture" => {
+                let path = args.next().ok_or_else(|| anyhow!("--capture needs a file"))?;
+                options.captures.push(PathBuf::from(path));
+            }
+            "--replay-device" => {
+                let path = args.next().ok_or_else(|| anyhow!("--replay-device needs a file"))?;
+                options.replay_device = Some(PathBuf::from(path));
+            }
+            "--replay-host" => {
+                let path = args.next().ok_or_else(|| anyhow!("--replay-host needs a file"))?;
+                options.replay_host = Some(PathBuf::from(path));
+            }
+            "--control" => {
+                let path = args.next().ok_or_else(|| anyhow!("--control needs a socket path"))?;
+                options.control = Some(PathBuf::from(path));
+            }
+            "--http" => {
+                options.http = Some(args.next().ok_or_else(|| anyhow!("--http needs an address"))?);
+            }
+            "--buses" => {
+                let path = args.next().ok_or_else(|| anyhow!("--buses needs a file"))?;
+                options.buses = Some(PathBuf::from(path));
+            }
+            "--scenario" => {
+                let path = args.next().ok_or_else(|| anyhow!("--scenario needs a file"))?;
+                options.scenario = Some(PathBuf::from(path));
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ => return Err(anyhow!("Unknown argument: {}\n\n{}", arg, USAGE)),
+        }
+    }
+
+    Ok(options)
+}
+
+/// Replay a capture as the host against fresh emulated devices
+fn run_replay_host(path: &Path) -> anyhow::Result<()> {
+    let records = read_capture(path)?;
+    let mut devices = default_devices();
+    let report = replay_host(&records, &mut devices);
+
+    for divergence in &report.divergences {
+        println!("✗ {}", describe_divergence(divergence));
+    }
+    println!(
+        "\n{} transactions, {} diverged, {} without recorded response, {} for unknown addresses",
+        report.transactions,
+        report.divergences.len(),
+        report.unanswered,
+        report.unknown_address
+    );
+
+    if !report.passed() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Run the bridge in the background and drive the devices from a scenario file
+fn run_scenario(bridge: SerialBridge, scenario: &Scenario, events: &CaptureBroadcast) -> anyhow::Result<()> {
+    let devices = bridge.get_devices();
+    let frames = events.subscribe();
+    thread::spawn(move || {
+        let mut bridge = bridge;
+        if let Err(e) = bridge.run() {
+            eprintln!("Serial bridge stopped: {}", e);
+        }
+    });
+
+    let result = scenario.run(&devices, &frames);
+    match &result.failure {
+        None => println!("\n✅ Scenario passed ({} steps)", result.passed),
+        Some(failure) => {
+            println!("\n❌ Scenario failed after {} steps: {}", result.passed, failure);
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+/// Tab completion for the REPL from the current device tables
+struct ReplHelper {
+    devices: Devices,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &rustyline::Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(repl::complete(&self.devices.lock().unwrap(), &line[..pos]))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+impl Validator for ReplHelper {}
+impl Helper for ReplHelper {}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| Path::new(&home).join(".bill_emulator_history"))
+}
+
+fn run_repl(devices: Devices, shutdown: ShutdownHandle) -> anyhow::Result<()> {
+    let mut editor = Editor::<ReplHelper, DefaultHistory>::new()?;
+    editor.set_helper(Some(ReplHelper { devices: devices.clone() }));
+    let history = history_path();
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+    println!("Type 'help' for commands, Tab completes commands, addresses and denominations");
+
+    loop {
+        let line = match editor.readline("💵 > ") {
+            Ok(line) => line,
+            // Ctrl+C in the line editor stops the emulator like it does without one
+            Err(ReadlineError::Interrupted) => {
+                shutdown.shutdown();
+                return Ok(());
+            }
+            // stdin closed: keep serving the host without a command line
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line.as_str());
+        if let Some(path) = &history {
+            let _ = editor.save_history(path);
+        }
+
+        match ReplCommand::parse(&line) {
+            Ok(ReplCommand::Quit) => {
+                shutdown.shutdown();
+                return Ok(());
+            }
+            Ok(command) => match repl::execute(&devices, &command) {
+                Ok(output) if output.is_empty() => {}
+                Ok(output) => println!("{}", output),
+                Err(e) => println!("❌ {}", e),
+            },
+            Err(e) => println!("❌ {}", e),
+        }
+    }
+}
+
+/// Serve several buses from one bus file until Ctrl+C or SIGTERM
+fn run_buses(path: &Path, options: &Options) -> anyhow::Result<()> {
+    if !options.faults.is_empty()
+        || options.replay_device.is_some()
+        || options.control.is_some()
+        || options.http.is_some()
+        || options.scenario.is_some()
+    {
+        return Err(anyhow!(
+            "--buses only combines with --capture; set a control socket per bus in the bus file"
+        ));
+    }
+
+    let mut manager = BridgeManager::load(path)?;
+    for path in &options.captures {
+        manager.add_capture(create_capture(path)?);
+        println!("📼 Capturing traffic of all buses to {}", path.display());
+    }
+
+    println!("\n📌 Buses:");
+    for status in manager.status() {
+        let bus = manager.bus(&status.name).expect("status lists configured buses");
+        let link = bus.link.as_ref().map(|l| format!(" → {}", l.display())).unwrap_or_default();
+        println!("   {:<16} {}{}", status.name, status.port, link);
+        for device in &status.devices {
+            println!("      0x{:02X} unit 0x{:02X} {}", device.address, device.unit_type, device.currency);
+        }
+    }
+    println!("\nPress Ctrl+C to stop the emulator\n");
+
+    manager.shutdown_handle().shutdown_on_signals()?;
+    manager.run()
+}
+
 fn main() -> anyhow::Result<()> {
+    let options = parse_args()?;
+
+    if let Some(path) = &options.replay_host {
+        return run_replay_host(path);
+    }
+    if let Some(path) = &options.buses {
+        return run_buses(path, &options);
+    }
+    // Parse up front so a typo fails before the PTY is created
+    let scenario = options.scenario.as_deref().map(Scenario::load).transpose()?;
+
     println!("===========================================");
     println!("  VirtusDev - Bill Validator Emulator");
     println!("===========================================\n");
 
     // Create serial bridge
     let mut bridge = SerialBridge::new()?;
+
+    if !options.faults.is_empty() {
+        let line_noise = match options.seed {
+            Some(seed) => LineNoise::new(options.faults, seed),
+            None => LineNoise::with_random_seed(options.faults),
+        };
+        bridge.set_line_noise(line_noise);
+    }
+
+    if let Some(path) = &options.replay_device {
+        bridge.set_replay(ReplayDevice::from_records(&read_capture(path)?));
+    }
+
+    for path in &options.captures {
+        bridge.add_capture(create_capture(path)?);
+        println!("📼 Capturing traffic to {}", path.display());
+    }
+
+    // Protocol events for the control interfaces
+    let events = CaptureBroadcast::new();
+    if options.control.is_some() || options.http.is_some() || scenario.is_some() {
+        bridge.add_capture(Box::new(events.clone()));
+    }
+
+    // Keep the servers alive for the lifetime of the bridge
+    let _control = match &options.control {
+        Some(path) => {
+            let server = ControlServer::start(path, bridge.get_devices(), events.clone())?;
+            println!("🎛  Control socket listening on {}", server.path().display());
+            Some(server)
+        }
+        None => None,
+    };
+    if let Some(addr) = &options.http {
+        let server = HttpServer::start(addr, bridge.get_devices(), events.clone())?;
+        println!("🌐 HTTP API on http://{}/api/devices (WebSocket: ws://{}/ws)", server.addr(), server.addr());
+    }
     
     println!("\n📌 Next steps:");
     println!("   1. Configure payment system:");
@@ -20,73 +332,22 @@ fn main() -> anyhow::Result<()> {
     println!("Press Ctrl+C to stop the emulator\n");
     println!("===========================================\n");
 
-    // Clone devices for control thread
-    let devices = bridge.get_devices();
+    bridge.shutdown_handle().shutdown_on_signals()?;
+
+    if let Some(scenario) = &scenario {
+        return run_scenario(bridge, scenario, &events);
+    }
 
-    // Spawn control thread for inserting bills/coins via stdin
+    // Interactive command line for inserting cash and driving the devices
+    let devices = bridge.get_devices();
+    let shutdown = bridge.shutdown_handle();
     thread::spawn(move || {
-        loop {
-            print!("\n💵 Insert bill [1/2/5/10/20] or coin [0.01/0.05/0.10/0.25/0.50/1.00]: ");
-            io::stdout().flush().unwrap();
-
-            let mut input = String::new();
-            match io::stdin().read_line(&mut input) {
-                Ok(0) => {
-                    // EOF - stop reading stdin to prevent infinite loop
-                    thread::sleep(std::time::Duration::from_secs(1));
-                }
-                Ok(_) => {
-                    let input = input.trim();
-                    if input.is_empty() {
-                        continue;
-                    }
-                    
-                    let value_cents = match input {
-                        // Bills
-                        "1" => Some(100),
-                        "2" => Some(200),
-                        "5" => Some(500),
-                        "10" => Some(1000),
-                        "20" => Some(2000),
-                        // Coins
-                        "0.01" => Some(1),
-                        "0.05" => Some(5),
-                        "0.10" => Some(10),
-                        "0.25" => Some(25),
-                        "0.50" => Some(50),
-                        "1.00" | "1" => Some(100),
-                        _ => None,
-                    };
-
-                    if let Some(value) = value_cents {
-                        let mut devs = devices.lock().unwrap();
-                        
-                        if value >= 100 {
-                            // It's a bill
-                            if let Some(note_device) = devs.get_mut(&0x00) {
-                                note_device.insert_note(value);
-                                println!("✓ Inserted ${:.2} bill", value as f64 / 100.0);
-                            }
-                        } else {
-                            // It's a coin
-                            if let Some(coin_device) = devs.get_mut(&0x10) {
-                                coin_device.insert_coins(value);
-                                println!("✓ Inserted ${:.2} coin", value as f64 / 100.0);
-                            }
-                        }
-                    } else {
-                        println!("❌ Invalid value. Use: 1, 2, 5, 10, 20 (bills) or 0.01, 0.05, 0.10, 0.25, 0.50, 1.00 (coins)");
-                    }
-                }
-                Err(error) => {
-                    eprintln!("Error reading stdin: {}", error);
-                    thread::sleep(std::time::Duration::from_secs(1));
-                }
-            }
+        if let Err(e) = run_repl(devices, shutdown) {
+            eprintln!("Command line stopped: {}", e);
         }
     });
 
-    // Run serial bridge (blocking)
+    // Run serial bridge until Ctrl+C, SIGTERM or `quit`
     bridge.run()?;
 
     Ok(())
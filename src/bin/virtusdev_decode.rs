@@ -0,0 +1,105 @@
+use anyhow::anyhow;
+use std::path::PathBuf;
+use virtusdev::capture::{read_capture, CaptureRecord, Direction};
+use virtusdev::essp_protocol::parse_address;
+
+const USAGE: &str = "\
+Usage: virtusdev-decode [OPTIONS] CAPTURE
+
+Pretty-print an eSSP capture (.jsonl or .pcapng) recorded by bill_emulator.
+
+Options:
+  --address ADDR   Only show frames for this slave address (e.g. 0x10)
+  --raw            Show the raw wire bytes instead of the unstuffed data
+  -h, --help       Show this help";
+
+struct Options {
+    path: PathBuf,
+    address: Option<u8>,
+    raw: bool,
+}
+
+fn parse_args() -> anyhow::Result<Options> {
+    let mut path = None;
+    let mut address = None;
+    let mut raw = false;
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--address" => {
+                let value = args.next().ok_or_else(|| anyhow!("--address needs a value"))?;
+                address = Some(parse_address(&value)?);
+            }
+            "--raw" => raw = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(PathBuf::from(arg)),
+            _ => return Err(anyhow!("Unknown argument: {}\n\n{}", arg, USAGE)),
+        }
+    }
+
+    let path = path.ok_or_else(|| anyhow!("Missing capture file\n\n{}", USAGE))?;
+    Ok(Options { path, address, raw })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn print_record(record: &CaptureRecord, start_us: u64, raw: bool) {
+    let elapsed = record.timestamp_us.saturating_sub(start_us) as f64 / 1_000_000.0;
+    let address = match record.undecodable {
+        true => "????".to_string(),
+        false => format!("0x{:02X}", record.address),
+    };
+    let route = match record.direction {
+        Direction::HostToDevice => format!("HOST → {}", address),
+        Direction::DeviceToHost => format!("{} → HOST", address),
+    };
+    let route = match &record.bus {
+        Some(bus) => format!("{} {}", bus, route),
+        None => route,
+    };
+    let name = match &record.command {
+        Some(command) => format!("{} ({})", record.name, command),
+        None => record.name.clone(),
+    };
+    let bytes = if raw { &record.frame } else { &record.data };
+
+    println!(
+        "{:>12.6}  {}  seq={}  {:<28} {}",
+        elapsed,
+        route,
+        record.sequence_flag as u8,
+        name,
+        hex(bytes)
+    );
+    if !record.events.is_empty() {
+        println!("{:>12}  events: {}", "", record.events.join(", "));
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let options = parse_args()?;
+    let records = read_capture(&options.path)?;
+
+    let start_us = records.first().map(|r| r.timestamp_us).unwrap_or(0);
+    let mut shown = 0;
+    for record in &records {
+        if options.address.is_some_and(|a| record.undecodable || a != record.address) {
+            continue;
+        }
+        print_record(record, start_us, options.raw);
+        shown += 1;
+    }
+
+    println!("\n{} frames ({} in capture)", shown, records.len());
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use anyhow::{anyhow, Context};
+use std::path::PathBuf;
+use virtusdev::capture::create_capture;
+use virtusdev::sniffer::{RewriteRule, Sniffer, SnifferConfig};
+
+const USAGE: &str = "\
+Usage: virtusdev-sniff --host PORT --device PORT [OPTIONS]
+
+Forward eSSP traffic between a real host and a real validator, decoding
+every frame on the way.
+
+Options:
+  --host PORT      Serial port connected to the host (payment system)
+  --device PORT    Serial port connected to the validator
+  --baud N         Line speed for both ports (default 9600)
+  --capture FILE   Record all traffic to FILE (.pcapng or JSONL, repeatable)
+  --rule RULE      Rewrite device responses (repeatable)
+                     EVENT->EVENT        e.g. credit->rejected
+                     inject:EVENT@ADDR   e.g. inject:jammed@0x00
+  -h, --help       Show this help";
+
+fn main() -> anyhow::Result<()> {
+    let mut host_port = None;
+    let mut device_port = None;
+    let mut baud = 9600;
+    let mut captures = Vec::new();
+    let mut rules: Vec<RewriteRule> = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| anyhow!("{} needs a value", name));
+        match arg.as_str() {
+            "--host" => host_port = Some(value("--host")?),
+            "--device" => device_port = Some(value("--device")?),
+            "--baud" => baud = value("--baud")?.parse().context("Invalid --baud value")?,
+            "--capture" => captures.push(PathBuf::from(value("--capture")?)),
+            "--rule" => rules.push(value("--rule")?.parse()?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ => return Err(anyhow!("Unknown argument: {}\n\n{}", arg, USAGE)),
+        }
+    }
+
+    let config = SnifferConfig {
+        host_port: host_port.ok_or_else(|| anyhow!("Missing --host\n\n{}", USAGE))?,
+        device_port: device_port.ok_or_else(|| anyhow!("Missing --device\n\n{}", USAGE))?,
+        baud,
+        rules,
+    };
+
+    let mut sniffer = Sniffer::new(config)?;
+    for path in &captures {
+        sniffer.add_capture(create_capture(path)?);
+        println!("📼 Capturing traffic to {}", path.display());
+    }
+
+    sniffer.run()
+}
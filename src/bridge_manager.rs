@@ -0,0 +1,362 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::bill_emulator::{DeviceState, DeviceStatus};
+use crate::capture::{CaptureBroadcast, CaptureRecord, CaptureSink};
+use crate::control::{ControlServer, Devices};
+use crate::event_loop::{self, EventSource, ShutdownHandle};
+use crate::serial_bridge::SerialBridge;
+
+/// Kind of emulated device on a bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceKind {
+    #[serde(alias = "nv200")]
+    NoteValidator,
+    #[serde(alias = "hopper")]
+    SmartHopper,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceConfig {
+    pub kind: DeviceKind,
+    pub address: u8,
+}
+
+/// One emulated serial bus
+#[derive(Debug, Clone, Deserialize)]
+pub struct BusConfig {
+    pub name: String,
+    /// Stable symlink to the PTY, e.g. /tmp/kiosk1-notes
+    #[serde(default)]
+    pub link: Option<PathBuf>,
+    /// JSON-RPC control socket for the devices of this bus
+    #[serde(default)]
+    pub control: Option<PathBuf>,
+    pub devices: Vec<DeviceConfig>,
+}
+
+/// Buses file, e.g.
+///
+/// ```json
+/// { "buses": [
+///     { "name": "kiosk1-notes", "link": "/tmp/kiosk1-notes",
+///       "devices": [{ "kind": "note_validator", "address": 0 }] },
+///     { "name": "kiosk1-coins", "control": "/tmp/kiosk1-coins.sock",
+///       "devices": [{ "kind": "smart_hopper", "address": 16 }] }
+/// ] }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct ManagerConfig {
+    pub buses: Vec<BusConfig>,
+}
+
+impl ManagerConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read bus config {}", path.display()))?;
+        let config: Self = serde_json::from_str(&text)
+            .with_context(|| format!("Invalid bus config {}", path.display()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.buses.is_empty() {
+            return Err(anyhow!("No buses configured"));
+        }
+        let mut names = HashSet::new();
+        for bus in &self.buses {
+            if !names.insert(&bus.name) {
+                return Err(anyhow!("Duplicate bus name '{}'", bus.name));
+            }
+            let mut addresses = HashSet::new();
+            for device in &bus.devices {
+                if device.address > 0x7F {
+                    return Err(anyhow!("Bus '{}': address 0x{:02X} is not a 7-bit address", bus.name, device.address));
+                }
+                if !addresses.insert(device.address) {
+                    return Err(anyhow!("Bus '{}': two devices at address 0x{:02X}", bus.name, device.address));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn build_devices(configs: &[DeviceConfig]) -> HashMap<u8, DeviceState> {
+    configs
+        .iter()
+        .map(|config| {
+            let device = match config.kind {
+                DeviceKind::NoteValidator => DeviceState::new_note_device(config.address),
+                DeviceKind::SmartHopper => DeviceState::new_coin_device(config.address),
+            };
+            (config.address, device)
+        })
+        .collect()
+}
+
+/// A capture sink shared by the bridges of all buses, for a combined log
+#[derive(Clone)]
+struct SharedSink(Arc<Mutex<Box<dyn CaptureSink>>>);
+
+impl CaptureSink for SharedSink {
+    fn record(&mut self, record: &CaptureRecord) -> Result<()> {
+        self.0.lock().unwrap().record(record)
+    }
+}
+
+/// A running bus
+pub struct Bus {
+    pub name: String,
+    pub slave_path: String,
+    pub link: Option<PathBuf>,
+    pub devices: Devices,
+    /// Frames of this bus only; its control socket streams these
+    pub events: CaptureBroadcast,
+    _control: Option<ControlServer>,
+}
+
+/// Combined status of one bus
+#[derive(Debug, Clone, Serialize)]
+pub struct BusStatus {
+    pub name: String,
+    pub port: String,
+    pub devices: Vec<DeviceStatus>,
+}
+
+/// Hosts several independent buses, each with its own PTY and device set,
+/// served from one event loop thread
+pub struct BridgeManager {
+    bridges: Vec<SerialBridge>,
+    buses: Vec<Bus>,
+    events: CaptureBroadcast,
+    shutdown: ShutdownHandle,
+}
+
+impl BridgeManager {
+    pub fn new(config: &ManagerConfig) -> Result<Self> {
+        config.validate()?;
+        let events = CaptureBroadcast::new();
+        let mut bridges = Vec::new();
+        let mut buses = Vec::new();
+
+        for bus_config in &config.buses {
+            let mut bridge = SerialBridge::with_devices(build_devices(&bus_config.devices))?;
+            bridge.set_name(&bus_config.name);
+            let bus_events = CaptureBroadcast::new();
+            bridge.add_capture(Box::new(events.clone()));
+            bridge.add_capture(Box::new(bus_events.clone()));
+
+            let control = match &bus_config.control {
+                Some(path) => Some(ControlServer::start(path, bridge.get_devices(), bus_events.clone())?),
+                None => None,
+            };
+
+            buses.push(Bus {
+                name: bus_config.name.clone(),
+                slave_path: bridge.slave_path().to_string(),
+                link: bus_config.link.clone(),
+                devices: bridge.get_devices(),
+                events: bus_events,
+                _control: control,
+            });
+            bridges.push(bridge);
+        }
+
+        // Links go last so that a failing bus leaves none behind
+        let mut created: Vec<&Path> = Vec::new();
+        for bus in &buses {
+            let Some(link) = &bus.link else { continue };
+            if let Err(e) = create_link(&bus.slave_path, link) {
+                for path in created {
+                    let _ = std::fs::remove_file(path);
+                }
+                return Err(e);
+            }
+            created.push(link);
+        }
+
+        Ok(Self {
+            bridges,
+            buses,
+            events,
+            shutdown: ShutdownHandle::new()?,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::new(&ManagerConfig::load(path)?)
+    }
+
+    pub fn buses(&self) -> &[Bus] {
+        &self.buses
+    }
+
+    pub fn bus(&self, name: &str) -> Option<&Bus> {
+        self.buses.iter().find(|bus| bus.name == name)
+    }
+
+    /// Record the frames of every bus to one sink; records carry the bus name
+    pub fn add_capture(&mut self, sink: Box<dyn CaptureSink>) {
+        let shared = SharedSink(Arc::new(Mutex::new(sink)));
+        for bridge in &mut self.bridges {
+            bridge.add_capture(Box::new(shared.clone()));
+        }
+    }
+
+    /// Frames of all buses, for live logs and control interfaces
+    pub fn events(&self) -> CaptureBroadcast {
+        self.events.clone()
+    }
+
+    /// State of every device on every bus
+    pub fn status(&self) -> Vec<BusStatus> {
+        self.buses
+            .iter()
+            .map(|bus| {
+                let devices = bus.devices.lock().unwrap();
+                let mut states: Vec<DeviceStatus> = devices.values().map(DeviceState::status).collect();
+                states.sort_by_key(|state| state.address);
+                BusStatus {
+                    name: bus.name.clone(),
+                    port: bus.slave_path.clone(),
+                    devices: states,
+                }
+            })
+            .collect()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Serve every bus from the calling thread until shut down
+    pub fn run(&mut self) -> Result<()> {
+        println!("🔄 Serving {} buses, waiting for connections...\n", self.bridges.len());
+        let mut sources: Vec<&mut dyn EventSource> = self
+            .bridges
+            .iter_mut()
+            .map(|bridge| bridge as &mut dyn EventSource)
+            .collect();
+        event_loop::run(&mut sources, &self.shutdown)
+    }
+}
+
+fn create_link(slave_path: &str, link: &Path) -> Result<()> {
+    // Replace a link left over from a previous run, never a regular file
+    if link.symlink_metadata().is_ok_and(|m| m.file_type().is_symlink()) {
+        std::fs::remove_file(link)?;
+    }
+    std::os::unix::fs::symlink(slave_path, link).with_context(|| format!("Failed to create link {}", link.display()))
+}
+
+impl Drop for BridgeManager {
+    fn drop(&mut self) {
+        for bus in &self.buses {
+            if let Some(link) = &bus.link {
+                let _ = std::fs::remove_file(link);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::essp_protocol::{EsspPacket, CMD_SYNC};
+    use crate::sniffer::open_serial_port;
+    use std::io::Write;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_config_parsing_and_validation() {
+        let config: ManagerConfig = serde_json::from_str(
+            r#"{ "buses": [
+                { "name": "notes", "devices": [{ "kind": "nv200", "address": 0 }] },
+                { "name": "coins", "devices": [{ "kind": "smart_hopper", "address": 16 }] }
+            ] }"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.buses[1].devices[0].kind, DeviceKind::SmartHopper);
+
+        let duplicate: ManagerConfig = serde_json::from_str(
+            r#"{ "buses": [{ "name": "a", "devices": [
+                { "kind": "nv200", "address": 0 }, { "kind": "hopper", "address": 0 }
+            ] }] }"#,
+        )
+        .unwrap();
+        assert!(duplicate.validate().is_err());
+    }
+
+    #[test]
+    fn test_buses_have_independent_devices() {
+        let config: ManagerConfig = serde_json::from_str(
+            r#"{ "buses": [
+                { "name": "kiosk1", "devices": [{ "kind": "nv200", "address": 0 }] },
+                { "name": "kiosk2", "devices": [{ "kind": "nv200", "address": 0 }, { "kind": "hopper", "address": 16 }] }
+            ] }"#,
+        )
+        .unwrap();
+        let manager = BridgeManager::new(&config).unwrap();
+        assert_ne!(manager.buses()[0].slave_path, manager.buses()[1].slave_path);
+
+        manager.bus("kiosk1").unwrap().devices.lock().unwrap().get_mut(&0).unwrap().set_enabled(true);
+        let status = manager.status();
+        assert_eq!(status[0].devices.len(), 1);
+        assert_eq!(status[1].devices.len(), 2);
+        assert!(status[0].devices[0].enabled);
+        assert!(!status[1].devices[0].enabled);
+    }
+
+    #[test]
+    fn test_failed_link_leaves_no_links_behind() {
+        let dir = std::env::temp_dir().join(format!("virtusdev-links-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let first = dir.join("kiosk1");
+        let config: ManagerConfig = serde_json::from_value(serde_json::json!({ "buses": [
+            { "name": "kiosk1", "devices": [{ "kind": "nv200", "address": 0 }], "link": first },
+            { "name": "kiosk2", "devices": [{ "kind": "nv200", "address": 0 }], "link": dir.join("missing/kiosk2") }
+        ] }))
+        .unwrap();
+        assert!(BridgeManager::new(&config).is_err());
+        assert!(first.symlink_metadata().is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_bus_events_carry_only_their_own_frames() {
+        let config: ManagerConfig = serde_json::from_str(
+            r#"{ "buses": [
+                { "name": "kiosk1", "devices": [{ "kind": "nv200", "address": 0 }] },
+                { "name": "kiosk2", "devices": [{ "kind": "nv200", "address": 0 }] }
+            ] }"#,
+        )
+        .unwrap();
+        let mut manager = BridgeManager::new(&config).unwrap();
+        let all = manager.events().subscribe();
+        let kiosk1 = manager.bus("kiosk1").unwrap().events.subscribe();
+        let kiosk2 = manager.bus("kiosk2").unwrap().events.subscribe();
+
+        let mut host = open_serial_port(&manager.buses()[1].slave_path, 9600).unwrap();
+        host.write_all(&EsspPacket::new(0x80, vec![CMD_SYNC]).to_bytes()).unwrap();
+        // The SYNC and its reply arrive once the bridge has read the whole frame
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let mut frames: Vec<CaptureRecord> = Vec::new();
+        while frames.len() < 2 && Instant::now() < deadline {
+            manager.bridges[1].on_readable().unwrap();
+            frames.extend(kiosk2.try_iter());
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(frames.len(), 2);
+        assert!(frames.iter().all(|record| record.bus.as_deref() == Some("kiosk2")));
+        assert_eq!(all.try_iter().count(), 2);
+        assert!(kiosk1.try_recv().is_err());
+    }
+}
@@ -0,0 +1,604 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::bill_emulator::PollEvent;
+use crate::essp_protocol::*;
+
+/// pcapng link type used for eSSP captures (LINKTYPE_USER0).
+///
+/// Each packet starts with a one byte pseudo-header holding the direction
+/// (0 = host to device, 1 = device to host) followed by the raw frame as seen on the wire.
+pub const LINKTYPE_ESSP: u16 = 147;
+
+const PCAPNG_SHB: u32 = 0x0A0D_0D0A;
+const PCAPNG_IDB: u32 = 0x0000_0001;
+const PCAPNG_EPB: u32 = 0x0000_0006;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const PCAPNG_OPT_END: u16 = 0;
+const PCAPNG_IF_NAME: u16 = 2;
+
+/// Which side of the link sent a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    HostToDevice,
+    DeviceToHost,
+}
+
+impl Direction {
+    fn pseudo_header(self) -> u8 {
+        match self {
+            Direction::HostToDevice => 0,
+            Direction::DeviceToHost => 1,
+        }
+    }
+
+    /// epb_flags direction bits, seen from the device: host frames are inbound
+    fn epb_flags(self) -> u32 {
+        match self {
+            Direction::HostToDevice => 0b01,
+            Direction::DeviceToHost => 0b10,
+        }
+    }
+}
+
+/// One captured eSSP frame
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    /// Microseconds since the UNIX epoch
+    pub timestamp_us: u64,
+    /// Name of the bus the frame was seen on, when several buses are emulated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bus: Option<String>,
+    pub direction: Direction,
+    pub address: u8,
+    pub sequence_flag: bool,
+    /// Command name for host frames, status name for device frames
+    pub name: String,
+    /// For device frames, the command being answered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Decoded poll events of a POLL response
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<String>,
+    /// Unstuffed packet data (command or status byte first)
+    #[serde(with = "hex_bytes")]
+    pub data: Vec<u8>,
+    /// Raw bytes as sent on the line
+    #[serde(with = "hex_bytes")]
+    pub frame: Vec<u8>,
+    /// The bytes are not a valid eSSP frame (bad CRC, truncated, stray
+    /// bytes): only `direction`, `timestamp_us` and `frame` are meaningful
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub undecodable: bool,
+}
+
+impl CaptureRecord {
+    /// Record for bytes that do not decode as an eSSP frame
+    pub fn undecodable(direction: Direction, frame: &[u8], timestamp_us: u64) -> Self {
+        Self {
+            timestamp_us,
+            bus: None,
+            direction,
+            address: 0,
+            sequence_flag: false,
+            name: "UNDECODABLE".to_string(),
+            command: None,
+            events: Vec::new(),
+            data: Vec::new(),
+            frame: frame.to_vec(),
+            undecodable: true,
+        }
+    }
+}
+
+/// Current time in microseconds since the UNIX epoch
+pub fn timestamp_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+/// Turns frames into capture records, remembering the last command sent to
+/// each address so that responses can be named after what they answer.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    last_command: HashMap<u8, u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode a complete frame. Returns `None` if it is not a valid eSSP frame.
+    pub fn decode(&mut self, direction: Direction, frame: &[u8], timestamp_us: u64) -> Option<CaptureRecord> {
+        let (packet, _) = EsspPacket::from_bytes(frame).ok()?;
+        Some(self.decode_packet(direction, &packet, frame, timestamp_us))
+    }
+
+    /// Records for bytes as written to the line: one per valid frame, and
+    /// undecodable records for anything else, such as a corrupt or
+    /// truncated frame
+    pub fn decode_stream(&mut self, direction: Direction, bytes: &[u8], timestamp_us: u64) -> Vec<CaptureRecord> {
+        let mut scanner = FrameScanner::new();
+        let mut items = scanner.push(bytes);
+        if !scanner.pending().is_empty() {
+            items.push(ScanItem::Garbage(scanner.pending().to_vec()));
+        }
+        items
+            .into_iter()
+            .map(|item| match item {
+                ScanItem::Frame(packet, frame) => self.decode_packet(direction, &packet, &frame, timestamp_us),
+                ScanItem::Garbage(garbage) => CaptureRecord::undecodable(direction, &garbage, timestamp_us),
+            })
+            .collect()
+    }
+
+    /// Build a record for an already parsed packet
+    pub fn decode_packet(
+        &mut self,
+        direction: Direction,
+        packet: &EsspPacket,
+        frame: &[u8],
+        timestamp_us: u64,
+    ) -> CaptureRecord {
+        let address = packet.sequence & 0x7F;
+        let code = packet.data.first().copied();
+
+        let (name, command, events) = match direction {
+            Direction::HostToDevice => {
+                if let Some(code) = code {
+                    self.last_command.insert(address, code);
+                }
+                (code.map(command_name).unwrap_or("EMPTY").to_string(), None, Vec::new())
+            }
+            Direction::DeviceToHost => {
+                let answered = self.last_command.get(&address).copied();
+                let events = match (answered, code) {
+                    (Some(CMD_POLL), Some(RESPONSE_OK)) => PollEvent::parse_poll_data(&packet.data[1..])
+                        .map(|events| events.iter().map(PollEvent::describe).collect())
+                        .unwrap_or_default(),
+                    _ => Vec::new(),
+                };
+                (
+                    code.map(status_name).unwrap_or("EMPTY").to_string(),
+                    answered.map(|c| command_name(c).to_string()),
+                    events,
+                )
+            }
+        };
+
+        CaptureRecord {
+            timestamp_us,
+            bus: None,
+            direction,
+            address,
+            sequence_flag: packet.sequence & 0x80 != 0,
+            name,
+            command,
+            events,
+            data: packet.data.clone(),
+            frame: frame.to_vec(),
+            undecodable: false,
+        }
+    }
+}
+
+/// Destination for captured frames
+pub trait CaptureSink: Send {
+    fn record(&mut self, record: &CaptureRecord) -> Result<()>;
+}
+
+/// Fans records out to any number of in-process subscribers.
+/// Clones share the same subscriber list.
+#[derive(Clone, Default)]
+pub struct CaptureBroadcast {
+    subscribers: Arc<Mutex<Vec<Sender<CaptureRecord>>>>,
+}
+
+impl CaptureBroadcast {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Receive every record captured from now on
+    pub fn subscribe(&self) -> Receiver<CaptureRecord> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Number of live subscribers; gone ones are pruned on the next record
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.lock().unwrap().len()
+    }
+}
+
+impl CaptureSink for CaptureBroadcast {
+    fn record(&mut self, record: &CaptureRecord) -> Result<()> {
+        // Drop subscribers whose receiver has gone away
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.send(record.clone()).is_ok());
+        Ok(())
+    }
+}
+
+/// Writes one JSON object per line
+pub struct JsonlWriter<W: Write + Send> {
+    out: W,
+}
+
+impl<W: Write + Send> JsonlWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: Write + Send> CaptureSink for JsonlWriter<W> {
+    fn record(&mut self, record: &CaptureRecord) -> Result<()> {
+        serde_json::to_writer(&mut self.out, record)?;
+        self.out.write_all(b"\n")?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes a pcapng file with interfaces of link type `LINKTYPE_ESSP`.
+///
+/// Interface 0 carries records without a bus; every named bus gets its own
+/// interface, declared with `if_name` the first time the bus is seen.
+pub struct PcapngWriter<W: Write + Send> {
+    out: W,
+    interfaces: Vec<Option<String>>,
+}
+
+impl<W: Write + Send> PcapngWriter<W> {
+    pub fn new(mut out: W) -> Result<Self> {
+        // Section Header Block
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        shb.extend_from_slice(&1u16.to_le_bytes()); // major version
+        shb.extend_from_slice(&0u16.to_le_bytes()); // minor version
+        shb.extend_from_slice(&(-1i64).to_le_bytes()); // section length unknown
+        write_block(&mut out, PCAPNG_SHB, &shb)?;
+
+        write_interface(&mut out, None)?;
+        out.flush()?;
+        Ok(Self { out, interfaces: vec![None] })
+    }
+
+    /// Interface id for `bus`, declaring a new interface on first use
+    fn interface_id(&mut self, bus: Option<&str>) -> Result<u32> {
+        let id = match self.interfaces.iter().position(|name| name.as_deref() == bus) {
+            Some(id) => id,
+            None => {
+                write_interface(&mut self.out, bus)?;
+                self.interfaces.push(bus.map(str::to_string));
+                self.interfaces.len() - 1
+            }
+        };
+        Ok(id as u32)
+    }
+}
+
+/// Interface Description Block (default microsecond resolution)
+fn write_interface<W: Write>(out: &mut W, name: Option<&str>) -> Result<()> {
+    let mut idb = Vec::new();
+    idb.extend_from_slice(&LINKTYPE_ESSP.to_le_bytes());
+    idb.extend_from_slice(&0u16.to_le_bytes()); // reserved
+    idb.extend_from_slice(&0u32.to_le_bytes()); // no snap length
+    if let Some(name) = name {
+        push_option(&mut idb, PCAPNG_IF_NAME, name.as_bytes());
+        push_option(&mut idb, PCAPNG_OPT_END, &[]);
+    }
+    write_block(out, PCAPNG_IDB, &idb)
+}
+
+fn push_option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value);
+    pad_to_32_bits(buf);
+}
+
+impl<W: Write + Send> CaptureSink for PcapngWriter<W> {
+    fn record(&mut self, record: &CaptureRecord) -> Result<()> {
+        let interface_id = self.interface_id(record.bus.as_deref())?;
+        let mut packet = Vec::with_capacity(record.frame.len() + 1);
+        packet.push(record.direction.pseudo_header());
+        packet.extend_from_slice(&record.frame);
+
+        let mut epb = Vec::new();
+        epb.extend_from_slice(&interface_id.to_le_bytes());
+        epb.extend_from_slice(&((record.timestamp_us >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(record.timestamp_us as u32).to_le_bytes());
+        epb.extend_from_slice(&(packet.len() as u32).to_le_bytes()); // captured length
+        epb.extend_from_slice(&(packet.len() as u32).to_le_bytes()); // original length
+        epb.extend_from_slice(&packet);
+        pad_to_32_bits(&mut epb);
+
+        // epb_flags option, then end of options
+        push_option(&mut epb, 2, &record.direction.epb_flags().to_le_bytes());
+        push_option(&mut epb, PCAPNG_OPT_END, &[]);
+
+        write_block(&mut self.out, PCAPNG_EPB, &epb)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+fn pad_to_32_bits(buf: &mut Vec<u8>) {
+    while !buf.len().is_multiple_of(4) {
+        buf.push(0);
+    }
+}
+
+fn write_block<W: Write>(out: &mut W, block_type: u32, body: &[u8]) -> Result<()> {
+    let total_len = (body.len() + 12) as u32;
+    out.write_all(&block_type.to_le_bytes())?;
+    out.write_all(&total_len.to_le_bytes())?;
+    out.write_all(body)?;
+    out.write_all(&total_len.to_le_bytes())?;
+    Ok(())
+}
+
+/// Only `.pcapng`: a `.pcap` path would promise the classic pcap format
+fn is_pcapng(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()) == Some("pcapng")
+}
+
+/// Create a capture file, pcapng for `.pcapng` paths and JSONL otherwise
+pub fn create_capture(path: &Path) -> Result<Box<dyn CaptureSink>> {
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create capture file {}", path.display()))?;
+    let out = BufWriter::new(file);
+    if is_pcapng(path) {
+        Ok(Box::new(PcapngWriter::new(out)?))
+    } else {
+        Ok(Box::new(JsonlWriter::new(out)))
+    }
+}
+
+/// Load every record from a JSONL or pcapng capture
+pub fn read_capture(path: &Path) -> Result<Vec<CaptureRecord>> {
+    let mut file = File::open(path)
+        .with_context(|| format!("Failed to open capture file {}", path.display()))?;
+
+    let mut magic = [0u8; 4];
+    let is_pcapng = file.read_exact(&mut magic).is_ok() && u32::from_le_bytes(magic) == PCAPNG_SHB;
+    let file = File::open(path)?;
+
+    if is_pcapng {
+        let mut bytes = Vec::new();
+        BufReader::new(file).read_to_end(&mut bytes)?;
+        parse_pcapng(&bytes)
+    } else {
+        let mut records = Vec::new();
+        for (line_no, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = serde_json::from_str(&line)
+                .with_context(|| format!("{}:{}: invalid capture record", path.display(), line_no + 1))?;
+            records.push(record);
+        }
+        Ok(records)
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| anyhow!("Truncated pcapng block at offset {}", offset))
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| anyhow!("Truncated pcapng option at offset {}", offset))
+}
+
+/// Value of the `if_name` option of an interface description block body
+fn interface_name(body: &[u8]) -> Result<Option<String>> {
+    let mut offset = 8;
+    while offset + 4 <= body.len() {
+        let code = read_u16(body, offset)?;
+        let len = read_u16(body, offset + 2)? as usize;
+        let value = body
+            .get(offset + 4..offset + 4 + len)
+            .ok_or_else(|| anyhow!("Truncated pcapng option at offset {}", offset))?;
+        match code {
+            PCAPNG_OPT_END => break,
+            PCAPNG_IF_NAME => return Ok(Some(String::from_utf8_lossy(value).into_owned())),
+            _ => {}
+        }
+        offset += 4 + len.next_multiple_of(4);
+    }
+    Ok(None)
+}
+
+/// Parse a little-endian pcapng capture written by `PcapngWriter`.
+/// Each interface is decoded separately and named by its bus.
+pub fn parse_pcapng(bytes: &[u8]) -> Result<Vec<CaptureRecord>> {
+    // (bus, decoder) per interface id
+    let mut interfaces: Vec<(Option<String>, FrameDecoder)> = Vec::new();
+    let mut records = Vec::new();
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let block_type = read_u32(bytes, offset)?;
+        let total_len = read_u32(bytes, offset + 4)? as usize;
+        if total_len < 12 || offset + total_len > bytes.len() {
+            return Err(anyhow!("Invalid pcapng block length {} at offset {}", total_len, offset));
+        }
+        let body = &bytes[offset + 8..offset + total_len - 4];
+
+        match block_type {
+            PCAPNG_SHB if read_u32(body, 0)? != PCAPNG_BYTE_ORDER_MAGIC => {
+                return Err(anyhow!("Only little-endian pcapng captures are supported"));
+            }
+            PCAPNG_IDB => {
+                let link_type = body
+                    .get(0..2)
+                    .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                    .ok_or_else(|| anyhow!("Truncated interface description block"))?;
+                if link_type != LINKTYPE_ESSP {
+                    return Err(anyhow!("Unsupported pcapng link type {}", link_type));
+                }
+                interfaces.push((interface_name(body)?, FrameDecoder::new()));
+            }
+            PCAPNG_EPB => {
+                let interface_id = read_u32(body, 0)? as usize;
+                let (bus, decoder) = interfaces
+                    .get_mut(interface_id)
+                    .ok_or_else(|| anyhow!("Packet for undeclared interface {} at offset {}", interface_id, offset))?;
+                let timestamp_us = ((read_u32(body, 4)? as u64) << 32) | read_u32(body, 8)? as u64;
+                let captured_len = read_u32(body, 12)? as usize;
+                let packet = body
+                    .get(20..20 + captured_len)
+                    .ok_or_else(|| anyhow!("Truncated enhanced packet block at offset {}", offset))?;
+                if let Some((&pseudo_header, frame)) = packet.split_first() {
+                    let direction = if pseudo_header == 0 {
+                        Direction::HostToDevice
+                    } else {
+                        Direction::DeviceToHost
+                    };
+                    let mut record = decoder
+                        .decode(direction, frame, timestamp_us)
+                        .unwrap_or_else(|| CaptureRecord::undecodable(direction, frame, timestamp_us));
+                    record.bus = bus.clone();
+                    records.push(record);
+                }
+            }
+            _ => {} // Skip blocks we do not use
+        }
+
+        offset += total_len;
+    }
+
+    Ok(records)
+}
+
+/// Serialize byte vectors as space separated hex ("7F 80 01 11")
+mod hex_bytes {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        serializer.serialize_str(&hex.join(" "))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.split_whitespace()
+            .map(|b| u8::from_str_radix(b, 16).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_records() -> Vec<CaptureRecord> {
+        let mut decoder = FrameDecoder::new();
+        let poll = EsspPacket::new(0x80, vec![CMD_POLL]).to_bytes();
+        let reply = build_response(0x80, RESPONSE_OK, &[1, EVENT_CREDIT, 2]).to_bytes();
+        vec![
+            decoder.decode(Direction::HostToDevice, &poll, 1_000).unwrap(),
+            decoder.decode(Direction::DeviceToHost, &reply, 2_500).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn test_decode_names_response_after_command() {
+        let records = sample_records();
+        assert_eq!(records[0].name, "POLL");
+        assert_eq!(records[1].name, "OK");
+        assert_eq!(records[1].command.as_deref(), Some("POLL"));
+        assert_eq!(records[1].events, vec!["CREDIT(ch 2)".to_string()]);
+    }
+
+    #[test]
+    fn test_decode_stream_marks_undecodable_bytes() {
+        let mut decoder = FrameDecoder::new();
+        let reply = build_response(0x80, RESPONSE_OK, &[]).to_bytes();
+        let mut bytes = reply.clone();
+        bytes.extend_from_slice(&reply[..3]);
+        let records = decoder.decode_stream(Direction::DeviceToHost, &bytes, 0);
+        assert_eq!(records.len(), 2);
+        assert!(!records[0].undecodable);
+        assert_eq!(records[0].frame, reply);
+        assert!(records[1].undecodable);
+        assert_eq!(records[1].frame, &reply[..3]);
+        assert!(records[1].data.is_empty());
+    }
+
+    #[test]
+    fn test_jsonl_roundtrip() {
+        let records = sample_records();
+        let mut out = Vec::new();
+        {
+            let mut writer = JsonlWriter::new(&mut out);
+            for record in &records {
+                writer.record(record).unwrap();
+            }
+        }
+        let parsed: Vec<CaptureRecord> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(parsed, records);
+    }
+
+    #[test]
+    fn test_pcapng_keeps_buses_apart() {
+        let mut records = sample_records();
+        records.extend(sample_records());
+        records[0].bus = Some("notes".to_string());
+        records[1].bus = Some("notes".to_string());
+        records[2].bus = Some("coins".to_string());
+        records[3].bus = Some("coins".to_string());
+        // Interleave the buses; each keeps its own command/response pairing
+        records.swap(1, 2);
+
+        let mut out = Vec::new();
+        {
+            let mut writer = PcapngWriter::new(&mut out).unwrap();
+            for record in &records {
+                writer.record(record).unwrap();
+            }
+        }
+        assert_eq!(parse_pcapng(&out).unwrap(), records);
+    }
+
+    #[test]
+    fn test_pcapng_roundtrip() {
+        let records = sample_records();
+        let mut out = Vec::new();
+        {
+            let mut writer = PcapngWriter::new(&mut out).unwrap();
+            for record in &records {
+                writer.record(record).unwrap();
+            }
+        }
+        assert_eq!(parse_pcapng(&out).unwrap(), records);
+        assert!(is_pcapng(Path::new("session.pcapng")));
+        assert!(!is_pcapng(Path::new("session.pcap")));
+    }
+}
@@ -0,0 +1,361 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::bill_emulator::{DeviceFault, DeviceState};
+use crate::capture::CaptureBroadcast;
+use crate::essp_protocol::parse_address;
+
+/// Devices shared with the serial bridge
+pub type Devices = Arc<Mutex<HashMap<u8, DeviceState>>>;
+
+// JSON-RPC 2.0 error codes
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// The request was valid but the emulator refused it (unknown device, device disabled...)
+pub const REQUEST_FAILED: i64 = -32000;
+
+/// Error returned by a control method
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+
+    fn invalid_params(message: impl Into<String>) -> Self {
+        Self::new(INVALID_PARAMS, message)
+    }
+
+    fn failed(message: impl Into<String>) -> Self {
+        Self::new(REQUEST_FAILED, message)
+    }
+}
+
+/// Parse an address given as a number or as a string like "0x10"
+fn address_param(params: &Value) -> Result<u8, RpcError> {
+    let address = params
+        .get("address")
+        .ok_or_else(|| RpcError::invalid_params("missing 'address'"))?;
+    let parsed = match address {
+        Value::Number(n) => n.as_u64().and_then(|n| u8::try_from(n).ok()),
+        Value::String(s) => parse_address(s).ok(),
+        _ => None,
+    };
+    parsed.ok_or_else(|| RpcError::invalid_params(format!("invalid address: {}", address)))
+}
+
+fn u64_param(params: &Value, name: &str) -> Result<u64, RpcError> {
+    params
+        .get(name)
+        .and_then(Value::as_u64)
+        .ok_or_else(|| RpcError::invalid_params(format!("missing or invalid '{}'", name)))
+}
+
+fn u32_param(params: &Value, name: &str) -> Result<u32, RpcError> {
+    u32::try_from(u64_param(params, name)?)
+        .map_err(|_| RpcError::invalid_params(format!("{} out of range", name)))
+}
+
+fn with_device<T>(
+    devices: &Devices,
+    address: u8,
+    f: impl FnOnce(&mut DeviceState) -> Result<T, RpcError>,
+) -> Result<T, RpcError> {
+    let mut devices = devices.lock().unwrap();
+    let device = devices
+        .get_mut(&address)
+        .ok_or_else(|| RpcError::failed(format!("no device at address 0x{:02X}", address)))?;
+    f(device)
+}
+
+/// Execute a control method against the emulated devices.
+///
+/// Methods (values are in cents):
+///   list_devices
+///   get_state     {address}
+///   insert_note   {address, value}
+///   insert_coin   {address, value}
+///   inject_fault  {address, fault: jam | cashbox_removed | reject | reset}
+///   set_level     {address, value, count}
+///   set_enabled   {address, enabled}
+pub fn execute(devices: &Devices, method: &str, params: &Value) -> Result<Value, RpcError> {
+    match method {
+        "list_devices" => {
+            let devices = devices.lock().unwrap();
+            let mut states: Vec<_> = devices.values().map(DeviceState::status).collect();
+            states.sort_by_key(|state| state.address);
+            Ok(json!(states))
+        }
+        "get_state" => {
+            let address = address_param(params)?;
+            with_device(devices, address, |device| Ok(json!(device.status())))
+        }
+        "insert_note" => {
+            let address = address_param(params)?;
+            let value = u32_param(params, "value")?;
+            with_device(devices, address, |device| {
+                if !device.channels.iter().any(|channel| channel.value == value) {
+                    return Err(RpcError::invalid_params(format!(
+                        "device 0x{:02X} has no channel for {} cents",
+                        address, value
+                    )));
+                }
+                if !device.insert_note(value) {
+                    return Err(RpcError::failed(format!("device 0x{:02X} is disabled", address)));
+                }
+                Ok(json!({ "accepted": true }))
+            })
+        }
+        "insert_coin" => {
+            let address = address_param(params)?;
+            let value = u32_param(params, "value")?;
+            with_device(devices, address, |device| {
+                if !device.insert_coins(value) {
+                    return Err(RpcError::failed(format!("device 0x{:02X} is disabled", address)));
+                }
+                Ok(json!({ "accepted": true }))
+            })
+        }
+        "inject_fault" => {
+            let address = address_param(params)?;
+            let fault: DeviceFault = params
+                .get("fault")
+                .and_then(Value::as_str)
+                .ok_or_else(|| RpcError::invalid_params("missing 'fault'"))?
+                .parse()
+                .map_err(|e: anyhow::Error| RpcError::invalid_params(e.to_string()))?;
+            with_device(devices, address, |device| {
+                device.inject_fault(fault);
+                Ok(json!({ "queued": true }))
+            })
+        }
+        "set_level" => {
+            let address = address_param(params)?;
+            let value = u32_param(params, "value")?;
+            let count = u16::try_from(u64_param(params, "count")?)
+                .map_err(|_| RpcError::invalid_params("count out of range"))?;
+            with_device(devices, address, |device| {
+                device.set_level(value, count);
+                Ok(json!(device.status()))
+            })
+        }
+        "set_enabled" => {
+            let address = address_param(params)?;
+            let enabled = params
+                .get("enabled")
+                .and_then(Value::as_bool)
+                .ok_or_else(|| RpcError::invalid_params("missing or invalid 'enabled'"))?;
+            with_device(devices, address, |device| {
+                device.set_enabled(enabled);
+                Ok(json!(device.status()))
+            })
+        }
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("unknown method '{}'", method))),
+    }
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": error.code, "message": error.message },
+        }),
+    }
+}
+
+/// JSON-RPC 2.0 server on a Unix socket, one request or response per line.
+///
+/// Besides the methods of `execute`, `subscribe` turns the connection into a
+/// stream of `event` notifications carrying every captured frame.
+pub struct ControlServer {
+    path: PathBuf,
+    stop: Arc<AtomicBool>,
+    accept_thread: Option<JoinHandle<()>>,
+}
+
+impl ControlServer {
+    /// Bind `path` and serve clients on background threads
+    pub fn start(path: &Path, devices: Devices, events: CaptureBroadcast) -> Result<Self> {
+        // A stale socket from a previous run would make bind fail
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Failed to bind control socket {}", path.display()))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopping = Arc::clone(&stop);
+        let accept_thread = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stopping.load(Ordering::Relaxed) {
+                    break;
+                }
+                match stream {
+                    Ok(stream) => {
+                        let devices = Arc::clone(&devices);
+                        let events = events.clone();
+                        thread::spawn(move || {
+                            if let Err(e) = serve_client(stream, devices, events) {
+                                eprintln!("Control client error: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => eprintln!("Control socket accept failed: {}", e),
+                }
+            }
+        });
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            stop,
+            accept_thread: Some(accept_thread),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        // Wake the blocked accept() with a connection of our own so the thread
+        // sees the stop flag and exits
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(accept_thread) = self.accept_thread.take() {
+            if UnixStream::connect(&self.path).is_ok() {
+                let _ = accept_thread.join();
+            }
+        }
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn send_line(writer: &Mutex<UnixStream>, message: &Value) -> std::io::Result<()> {
+    let mut line = message.to_string();
+    line.push('\n');
+    writer.lock().unwrap().write_all(line.as_bytes())
+}
+
+fn serve_client(stream: UnixStream, devices: Devices, events: CaptureBroadcast) -> Result<()> {
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    let mut subscribed = false;
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request: Value = match serde_json::from_str(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                send_line(&writer, &response(Value::Null, Err(RpcError::new(PARSE_ERROR, e.to_string()))))?;
+                continue;
+            }
+        };
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            send_line(&writer, &response(id, Err(RpcError::new(INVALID_REQUEST, "missing 'method'"))))?;
+            continue;
+        };
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+        let result = if method == "subscribe" {
+            if !subscribed {
+                subscribed = true;
+                let receiver = events.subscribe();
+                let writer = Arc::clone(&writer);
+                thread::spawn(move || {
+                    for record in receiver {
+                        let notification = json!({ "jsonrpc": "2.0", "method": "event", "params": record });
+                        if send_line(&writer, &notification).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            Ok(json!({ "subscribed": true }))
+        } else {
+            execute(&devices, method, &params)
+        };
+
+        // Requests without an id are notifications and get no response
+        if request.get("id").is_some() {
+            send_line(&writer, &response(id, result))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial_bridge::default_devices;
+
+    fn devices() -> Devices {
+        Arc::new(Mutex::new(default_devices()))
+    }
+
+    #[test]
+    fn test_insert_note_requires_enabled_device() {
+        let devices = devices();
+        let params = json!({ "address": "0x00", "value": 500 });
+
+        let error = execute(&devices, "insert_note", &params).unwrap_err();
+        assert_eq!(error.code, REQUEST_FAILED);
+
+        execute(&devices, "set_enabled", &json!({ "address": 0, "enabled": true })).unwrap();
+        execute(&devices, "insert_note", &params).unwrap();
+
+        let state = execute(&devices, "get_state", &json!({ "address": 0 })).unwrap();
+        assert_eq!(state["queued_events"], json!(["RESET", "READ(ch 2)", "CREDIT(ch 2)"]));
+    }
+
+    #[test]
+    fn test_drop_stops_accept_thread() {
+        let path = std::env::temp_dir().join(format!("virtusdev-control-{}.sock", std::process::id()));
+        let devices = devices();
+        let server = ControlServer::start(&path, Arc::clone(&devices), CaptureBroadcast::new()).unwrap();
+        assert_eq!(Arc::strong_count(&devices), 2);
+
+        drop(server);
+        // The accept thread has exited and released its handle on the devices
+        assert_eq!(Arc::strong_count(&devices), 1);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_invalid_requests() {
+        let devices = devices();
+        assert_eq!(
+            execute(&devices, "insert_note", &json!({ "address": 0, "value": 300 })).unwrap_err().code,
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            execute(&devices, "get_state", &json!({ "address": 0x22 })).unwrap_err().code,
+            REQUEST_FAILED
+        );
+        assert_eq!(execute(&devices, "explode", &Value::Null).unwrap_err().code, METHOD_NOT_FOUND);
+
+        // 2^32 + 100 must not wrap around to a 100 cent note
+        execute(&devices, "set_enabled", &json!({ "address": 0, "enabled": true })).unwrap();
+        let error = execute(&devices, "insert_note", &json!({ "address": 0, "value": 4294967396u64 })).unwrap_err();
+        assert_eq!(error.code, INVALID_PARAMS);
+        assert_eq!(error.message, "value out of range");
+    }
+}
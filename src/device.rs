@@ -1,32 +1,126 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use evdev::{
     uinput::{VirtualDevice, VirtualDeviceBuilder},
-    AttributeSet, EventType, InputEvent, Key,
+    AttributeSet, BusType, EventType, InputEvent, Key,
 };
+use serde::{Deserialize, Serialize};
 use std::thread;
 use std::time::{Duration, Instant};
 
+use crate::keyboard_layout::{KeyStroke, KeyboardLayout};
+use crate::scanner_config::{ControlChars, ScannerConfig, Terminator};
+use crate::symbology::Symbology;
+use crate::timing::{self, Jitter, KeyTiming};
+
 // Device configuration constants
+/// Baud rate of the default timing profile
 pub const BAUDRATE: u32 = 115200;
 pub const DEVICE_NAME: &str = "Virtual Keyboard 115200";
 pub const VENDOR_ID: u16 = 0x1234;
 pub const PRODUCT_ID: u16 = 0x5678;
 pub const DEVICE_VERSION: u16 = 0x0001;
 
-const BITS_PER_BYTE: u32 = 10;
-const BIT_TIME_US: u32 = 1_000_000 / BAUDRATE;
-const CHAR_TIME_US: u32 = BIT_TIME_US * BITS_PER_BYTE;
-const KEY_PRESS_DELAY: Duration = Duration::from_micros(CHAR_TIME_US as u64);
-const KEY_RELEASE_DELAY: Duration = Duration::from_micros(CHAR_TIME_US as u64);
-const INTER_KEY_DELAY: Duration = Duration::from_micros((CHAR_TIME_US * 2) as u64);
+/// uinput device names are limited to 80 bytes including the terminating NUL
+const MAX_NAME_LEN: usize = 79;
+
+/// Bus reported in the input id of the virtual device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BusKind {
+    #[default]
+    Usb,
+    Bluetooth,
+    /// PS/2 keyboard port
+    I8042,
+    Rs232,
+    Virtual,
+}
+
+impl BusKind {
+    pub const ALL: [Self; 5] = [Self::Usb, Self::Bluetooth, Self::I8042, Self::Rs232, Self::Virtual];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Usb => "USB",
+            Self::Bluetooth => "Bluetooth",
+            Self::I8042 => "PS/2 (i8042)",
+            Self::Rs232 => "RS-232",
+            Self::Virtual => "Virtual",
+        }
+    }
+
+    pub(crate) fn bus_type(self) -> BusType {
+        match self {
+            Self::Usb => BusType::BUS_USB,
+            Self::Bluetooth => BusType::BUS_BLUETOOTH,
+            Self::I8042 => BusType::BUS_I8042,
+            Self::Rs232 => BusType::BUS_RS232,
+            Self::Virtual => BusType::BUS_VIRTUAL,
+        }
+    }
+}
+
+/// What applications see of the virtual scanner: the name used in
+/// /dev/input/by-id links and the input id (bus, VID:PID, version)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceIdentity {
+    pub name: String,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub bus: BusKind,
+    pub version: u16,
+}
+
+impl Default for DeviceIdentity {
+    fn default() -> Self {
+        Self {
+            name: DEVICE_NAME.to_string(),
+            vendor_id: VENDOR_ID,
+            product_id: PRODUCT_ID,
+            bus: BusKind::Usb,
+            version: DEVICE_VERSION,
+        }
+    }
+}
+
+impl DeviceIdentity {
+    pub fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            return Err(anyhow!("Device name is empty"));
+        }
+        if self.name.len() > MAX_NAME_LEN || self.name.contains('\0') {
+            return Err(anyhow!("Device name must be at most {} bytes without NUL", MAX_NAME_LEN));
+        }
+        Ok(())
+    }
+
+    /// e.g. "Virtual Keyboard 115200 (1234:5678)"
+    pub fn describe(&self) -> String {
+        format!("{} ({:04x}:{:04x})", self.name, self.vendor_id, self.product_id)
+    }
+}
+
+/// Parse a VID, PID or version written in hex, with or without 0x
+pub fn parse_hex_u16(text: &str) -> Result<u16> {
+    let text = text.trim();
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| anyhow!("'{}' is not a 16-bit hex number", text))
+}
 
 pub struct VirtualKeyboard {
     device: VirtualDevice,
+    identity: DeviceIdentity,
     event_path: String,
+    layout: KeyboardLayout,
+    unsupported: UnsupportedChars,
+    config: ScannerConfig,
 }
 
 impl VirtualKeyboard {
-    pub fn new() -> Result<Self> {
+    pub fn new(identity: &DeviceIdentity) -> Result<Self> {
+        identity.validate()?;
+
         // Create attribute set for all keys
         let mut keys = AttributeSet::<Key>::new();
         for code in 0..256 {
@@ -34,12 +128,12 @@ impl VirtualKeyboard {
         }
 
         let mut device = VirtualDeviceBuilder::new()?
-            .name(DEVICE_NAME)
+            .name(&identity.name)
             .input_id(evdev::InputId::new(
-                evdev::BusType::BUS_USB,
-                VENDOR_ID,
-                PRODUCT_ID,
-                DEVICE_VERSION,
+                identity.bus.bus_type(),
+                identity.vendor_id,
+                identity.product_id,
+                identity.version,
             ))
             .with_keys(&keys)?
             .build()
@@ -58,7 +152,11 @@ impl VirtualKeyboard {
 
         Ok(Self {
             device,
+            identity: identity.clone(),
             event_path,
+            layout: KeyboardLayout::default(),
+            unsupported: UnsupportedChars::default(),
+            config: ScannerConfig::default(),
         })
     }
 
@@ -66,46 +164,70 @@ impl VirtualKeyboard {
         &self.event_path
     }
 
-    pub fn send_barcode(&mut self, barcode: &str) -> Result<Duration> {
-        let start = Instant::now();
+    pub fn identity(&self) -> &DeviceIdentity {
+        &self.identity
+    }
 
-        for c in barcode.chars() {
-            self.send_key(c)?;
-        }
+    /// Layout of the host receiving the keys, US QWERTY by default
+    pub fn layout(&self) -> &KeyboardLayout {
+        &self.layout
+    }
 
-        // Send Enter key
-        self.send_key('\n')?;
+    pub fn set_layout(&mut self, layout: KeyboardLayout) {
+        self.layout = layout;
+    }
 
-        Ok(start.elapsed())
+    /// What to do with characters the layout cannot type, Skip by default
+    pub fn unsupported_chars(&self) -> UnsupportedChars {
+        self.unsupported
     }
 
-    fn send_key(&mut self, c: char) -> Result<()> {
-        let keycode = get_keycode(c);
-        if keycode.is_none() {
-            return Ok(()); // Skip unsupported characters
-        }
+    pub fn set_unsupported_chars(&mut self, unsupported: UnsupportedChars) {
+        self.unsupported = unsupported;
+    }
 
-        let key = keycode.unwrap();
-        let needs_shift = needs_shift(c);
+    /// Prefix, suffix, terminator and focus key
+    pub fn config(&self) -> &ScannerConfig {
+        &self.config
+    }
 
-        if needs_shift {
-            self.emit(Key::KEY_LEFTSHIFT, 1)?;
-            thread::sleep(KEY_PRESS_DELAY);
-        }
+    pub fn set_config(&mut self, config: ScannerConfig) -> Result<()> {
+        config.focus_key()?;
+        self.config = config;
+        Ok(())
+    }
 
-        self.emit(key, 1)?;
-        thread::sleep(KEY_PRESS_DELAY);
+    /// Type `barcode` framed by the configured prefix, suffix and terminator.
+    /// The report lists the characters the layout could not type; in strict
+    /// mode nothing is sent if there are any.
+    pub fn send_barcode(&mut self, barcode: &str) -> Result<ScanReport> {
+        self.send_scan(barcode, None)
+    }
 
-        self.emit(key, 0)?;
-        thread::sleep(KEY_RELEASE_DELAY);
+    /// Like `send_barcode`, for a barcode of a known symbology: the data is
+    /// validated first and the configured code ID is sent before it
+    pub fn send_scan(&mut self, barcode: &str, symbology: Option<Symbology>) -> Result<ScanReport> {
+        let (actions, mut report) = plan_scan(&self.layout, self.unsupported, &self.config, symbology, barcode)?;
+        let mut jitter = Jitter::with_random_seed(self.config.jitter_percent);
+        let (events, target) = schedule(&actions, &self.config.timing.timing(), &mut jitter);
 
-        if needs_shift {
-            self.emit(Key::KEY_LEFTSHIFT, 0)?;
-            thread::sleep(KEY_RELEASE_DELAY);
+        // Events are emitted at deadlines from the start, so a late wake-up
+        // does not delay the rest of the scan
+        let start = Instant::now();
+        let mut total_lateness = Duration::ZERO;
+        for &(key, value, offset) in &events {
+            timing::wait_until(start + offset);
+            let lateness = start.elapsed().saturating_sub(offset);
+            self.emit(key, value)?;
+            total_lateness += lateness;
+            report.max_lateness = report.max_lateness.max(lateness);
         }
+        timing::wait_until(start + target);
 
-        thread::sleep(INTER_KEY_DELAY);
-        Ok(())
+        report.duration = start.elapsed();
+        report.target = target;
+        report.mean_lateness = total_lateness / events.len().max(1) as u32;
+        Ok(report)
     }
 
     fn emit(&mut self, key: Key, value: i32) -> Result<()> {
@@ -120,63 +242,527 @@ impl VirtualKeyboard {
     }
 }
 
-fn get_keycode(c: char) -> Option<Key> {
-    match c {
-        'a'..='z' => {
-            let offset = (c as u8) - b'a';
-            Some(Key::new(Key::KEY_A.code() + offset as u16))
-        }
-        'A'..='Z' => {
-            let offset = (c as u8) - b'A';
-            Some(Key::new(Key::KEY_A.code() + offset as u16))
-        }
-        '0' => Some(Key::KEY_0),
-        '1'..='9' => {
-            let offset = (c as u8) - b'1';
-            Some(Key::new(Key::KEY_1.code() + offset as u16))
-        }
-        ' ' => Some(Key::KEY_SPACE),
-        '\n' => Some(Key::KEY_ENTER),
-        '\t' => Some(Key::KEY_TAB),
-        '-' | '_' => Some(Key::KEY_MINUS),
-        '=' | '+' => Some(Key::KEY_EQUAL),
-        '[' | '{' => Some(Key::KEY_LEFTBRACE),
-        ']' | '}' => Some(Key::KEY_RIGHTBRACE),
-        ';' | ':' => Some(Key::KEY_SEMICOLON),
-        '\'' | '"' => Some(Key::KEY_APOSTROPHE),
-        '`' | '~' => Some(Key::KEY_GRAVE),
-        '\\' | '|' => Some(Key::KEY_BACKSLASH),
-        ',' | '<' => Some(Key::KEY_COMMA),
-        '.' | '>' => Some(Key::KEY_DOT),
-        '/' | '?' => Some(Key::KEY_SLASH),
-        _ => None,
-    }
-}
-
-fn needs_shift(c: char) -> bool {
-    matches!(
-        c,
-        'A'..='Z'
-            | '!'
-            | '@'
-            | '#'
-            | '$'
-            | '%'
-            | '^'
-            | '&'
-            | '*'
-            | '('
-            | ')'
-            | '_'
-            | '+'
-            | '{'
-            | '}'
-            | '|'
-            | ':'
-            | '"'
-            | '<'
-            | '>'
-            | '?'
-            | '~'
-    )
+/// Handling of characters the host keyboard layout cannot type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnsupportedChars {
+    /// Leave them out and list them in the scan report
+    #[default]
+    Skip,
+    /// Refuse to send a barcode containing any of them
+    Strict,
+    /// Ctrl+Shift+U, the hex code point, then space (GTK and IBus on Linux)
+    UnicodeHex,
+    /// Alt held while typing 0 and the decimal Windows-1252 code on the keypad
+    /// (Windows); the leading 0 selects the ANSI code page. Characters outside
+    /// Windows-1252 are skipped and reported.
+    AltKeypad,
+}
+
+impl UnsupportedChars {
+    pub const ALL: [Self; 4] = [Self::Skip, Self::Strict, Self::UnicodeHex, Self::AltKeypad];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Skip => "Skip and report",
+            Self::Strict => "Refuse to send",
+            Self::UnicodeHex => "Unicode entry (Ctrl+Shift+U)",
+            Self::AltKeypad => "Alt + keypad code",
+        }
+    }
+}
+
+/// Outcome of a scan: characters are identified by their position in the
+/// typed text, which starts with the prefix
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanReport {
+    /// Measured from the first key event to the end of the last delay
+    pub duration: Duration,
+    /// Duration the timing profile asked for, jitter included
+    pub target: Duration,
+    /// How much later than scheduled the key events were emitted
+    pub max_lateness: Duration,
+    pub mean_lateness: Duration,
+    /// Characters left out
+    pub skipped: Vec<(usize, char)>,
+    /// Characters typed through the Unicode fallback instead of a key
+    pub substituted: Vec<(usize, char)>,
+}
+
+impl ScanReport {
+    /// True if every character was typed with its own key
+    pub fn is_exact(&self) -> bool {
+        self.skipped.is_empty() && self.substituted.is_empty()
+    }
+
+    /// Measured against target timing, e.g. "12.4ms, target 12.2ms, max late 0.03ms"
+    pub fn timing_summary(&self) -> String {
+        format!(
+            "{:.1}ms, target {:.1}ms, max late {:.2}ms",
+            self.duration.as_secs_f64() * 1000.0,
+            self.target.as_secs_f64() * 1000.0,
+            self.max_lateness.as_secs_f64() * 1000.0
+        )
+    }
+
+    /// Human readable list of the skipped and substituted characters
+    pub fn warnings(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if !self.skipped.is_empty() {
+            parts.push(format!("skipped {}", describe_chars(&self.skipped)));
+        }
+        if !self.substituted.is_empty() {
+            parts.push(format!("code-point entry for {}", describe_chars(&self.substituted)));
+        }
+        (!parts.is_empty()).then(|| parts.join("; "))
+    }
+}
+
+fn describe_chars(chars: &[(usize, char)]) -> String {
+    chars
+        .iter()
+        .map(|(position, c)| format!("{:?} at {}", c, position))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// One step of typing a text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyAction {
+    Press(Key),
+    Release(Key),
+    /// Gap between two characters
+    NextChar,
+}
+
+/// Key events with their time from the start of the scan, and the end of the
+/// scan after the last delay
+fn schedule(actions: &[KeyAction], timing: &KeyTiming, jitter: &mut Jitter) -> (Vec<(Key, i32, Duration)>, Duration) {
+    let mut events = Vec::new();
+    let mut offset = Duration::ZERO;
+    for action in actions {
+        match *action {
+            KeyAction::Press(key) => {
+                events.push((key, 1, offset));
+                offset += jitter.apply(timing.press);
+            }
+            KeyAction::Release(key) => {
+                events.push((key, 0, offset));
+                offset += jitter.apply(timing.release);
+            }
+            KeyAction::NextChar => offset += jitter.apply(timing.inter_char),
+        }
+    }
+    (events, offset)
+}
+
+/// Key events of a whole scan: focus key, prefix, code ID, barcode, suffix,
+/// terminator
+fn plan_scan(
+    layout: &KeyboardLayout,
+    unsupported: UnsupportedChars,
+    config: &ScannerConfig,
+    symbology: Option<Symbology>,
+    barcode: &str,
+) -> Result<(Vec<KeyAction>, ScanReport)> {
+    let barcode = match symbology {
+        Some(symbology) => symbology.encode(barcode)?,
+        None => barcode.to_string(),
+    };
+    let code_id = symbology.map(|symbology| symbology.code_id(config.code_id)).unwrap_or_default();
+
+    let mut actions = Vec::new();
+    if let Some(key) = config.focus_key()? {
+        push_stroke(&mut actions, KeyStroke::new(key));
+        actions.push(KeyAction::NextChar);
+    }
+
+    let text = format!("{}{}{}{}", config.prefix_text(), code_id, barcode, config.suffix_text());
+    let (typed, report) = plan_keys(layout, unsupported, &config.control_chars, &text)?;
+    actions.extend(typed);
+
+    match config.terminator {
+        Terminator::None => {}
+        Terminator::Enter => push_stroke(&mut actions, KeyStroke::new(Key::KEY_ENTER)),
+        Terminator::KeypadEnter => push_stroke(&mut actions, KeyStroke::new(Key::KEY_KPENTER)),
+        Terminator::Tab => push_stroke(&mut actions, KeyStroke::new(Key::KEY_TAB)),
+        Terminator::CrLf => {
+            push_control(&mut actions, layout, 'm')?;
+            actions.push(KeyAction::NextChar);
+            push_control(&mut actions, layout, 'j')?;
+        }
+    }
+    if config.terminator != Terminator::None {
+        actions.push(KeyAction::NextChar);
+    }
+    Ok((actions, report))
+}
+
+/// Key events typing `text` on `layout`, without touching the device so that
+/// strict mode can refuse before anything is sent
+fn plan_keys(
+    layout: &KeyboardLayout,
+    unsupported: UnsupportedChars,
+    control: &ControlChars,
+    text: &str,
+) -> Result<(Vec<KeyAction>, ScanReport)> {
+    let missing: Vec<(usize, char)> = text
+        .chars()
+        .enumerate()
+        .filter(|(_, c)| layout.keystrokes(*c).is_none() && !c.is_ascii_control())
+        .collect();
+    if unsupported == UnsupportedChars::Strict && !missing.is_empty() {
+        return Err(anyhow!(
+            "Layout {} cannot type {}, nothing sent",
+            layout.name(),
+            describe_chars(&missing)
+        ));
+    }
+
+    let mut actions = Vec::new();
+    let mut report = ScanReport::default();
+    for (position, c) in text.chars().enumerate() {
+        match (layout.keystrokes(c), unsupported) {
+            (Some(strokes), _) => {
+                for &stroke in strokes {
+                    push_stroke(&mut actions, stroke);
+                }
+            }
+            (None, _) if c.is_ascii_control() => push_control_char(&mut actions, layout, control, c)?,
+            (None, UnsupportedChars::Skip | UnsupportedChars::Strict) => {
+                report.skipped.push((position, c));
+                continue;
+            }
+            (None, UnsupportedChars::AltKeypad) => match windows_1252(c) {
+                Some(code) => {
+                    push_alt_keypad(&mut actions, code);
+                    report.substituted.push((position, c));
+                }
+                None => {
+                    report.skipped.push((position, c));
+                    continue;
+                }
+            },
+            (None, UnsupportedChars::UnicodeHex) => {
+                push_unicode_hex(&mut actions, layout, c)?;
+                report.substituted.push((position, c));
+            }
+        }
+        actions.push(KeyAction::NextChar);
+    }
+    Ok((actions, report))
+}
+
+/// Press the modifiers, tap the key, release the modifiers in reverse order
+fn push_stroke(actions: &mut Vec<KeyAction>, stroke: KeyStroke) {
+    let modifiers: Vec<Key> = [(stroke.shift, Key::KEY_LEFTSHIFT), (stroke.altgr, Key::KEY_RIGHTALT)]
+        .into_iter()
+        .filter_map(|(held, key)| held.then_some(key))
+        .collect();
+
+    actions.extend(modifiers.iter().map(|&key| KeyAction::Press(key)));
+    actions.push(KeyAction::Press(stroke.key));
+    actions.push(KeyAction::Release(stroke.key));
+    actions.extend(modifiers.iter().rev().map(|&key| KeyAction::Release(key)));
+}
+
+/// ASCII control character typed as configured, e.g. GS in GS1 data
+fn push_control_char(actions: &mut Vec<KeyAction>, layout: &KeyboardLayout, control: &ControlChars, c: char) -> Result<()> {
+    if let Some(text) = control.replacement(c) {
+        for r in text.chars() {
+            let strokes = layout
+                .keystrokes(r)
+                .ok_or_else(|| anyhow!("Layout {} cannot type {:?} in the replacement of {:?}", layout.name(), r, c))?;
+            for &stroke in strokes {
+                push_stroke(actions, stroke);
+            }
+        }
+        return Ok(());
+    }
+    match control {
+        ControlChars::AltKeypad => {
+            push_alt_keypad(actions, c as u8);
+            Ok(())
+        }
+        // 0x00-0x1F are Ctrl with @, A-Z, [, \, ], ^ and _; DEL is Ctrl+?
+        _ => push_control(actions, layout, ((c as u8) ^ 0x40).to_ascii_lowercase() as char),
+    }
+}
+
+/// Ctrl held around the key of `letter`
+fn push_control(actions: &mut Vec<KeyAction>, layout: &KeyboardLayout, letter: char) -> Result<()> {
+    let stroke = layout
+        .keystrokes(letter)
+        .and_then(|strokes| strokes.first())
+        .ok_or_else(|| anyhow!("Layout {} cannot type Ctrl+{}", layout.name(), letter))?;
+    actions.push(KeyAction::Press(Key::KEY_LEFTCTRL));
+    push_stroke(actions, *stroke);
+    actions.push(KeyAction::Release(Key::KEY_LEFTCTRL));
+    Ok(())
+}
+
+fn push_unicode_hex(actions: &mut Vec<KeyAction>, layout: &KeyboardLayout, c: char) -> Result<()> {
+    let stroke = |c: char| {
+        layout
+            .keystrokes(c)
+            .ok_or_else(|| anyhow!("Layout {} cannot type {:?} for Unicode entry", layout.name(), c))
+    };
+    let u = stroke('u')?[0];
+    actions.extend([
+        KeyAction::Press(Key::KEY_LEFTCTRL),
+        KeyAction::Press(Key::KEY_LEFTSHIFT),
+        KeyAction::Press(u.key),
+        KeyAction::Release(u.key),
+        KeyAction::Release(Key::KEY_LEFTSHIFT),
+        KeyAction::Release(Key::KEY_LEFTCTRL),
+    ]);
+    for digit in format!("{:x}", c as u32).chars().chain([' ']) {
+        for &s in stroke(digit)? {
+            push_stroke(actions, s);
+        }
+    }
+    Ok(())
+}
+
+/// Code of `c` in Windows-1252, the ANSI code page Alt+0NNN types from.
+/// Without the leading 0 Windows would use the OEM code page, and values
+/// above 255 wrap around modulo 256, so nothing else can be typed this way.
+fn windows_1252(c: char) -> Option<u8> {
+    // 0x80-0x9F, where Windows-1252 differs from Latin-1
+    const HIGH: [char; 32] = [
+        '€', '\0', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\0', 'Ž', '\0',
+        '\0', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\0', 'ž', 'Ÿ',
+    ];
+    match c as u32 {
+        0x01..=0x7F | 0xA0..=0xFF => Some(c as u8),
+        _ => HIGH.iter().position(|&h| h == c && h != '\0').map(|i| 0x80 + i as u8),
+    }
+}
+
+fn push_alt_keypad(actions: &mut Vec<KeyAction>, code: u8) {
+    const KEYPAD: [Key; 10] = [
+        Key::KEY_KP0, Key::KEY_KP1, Key::KEY_KP2, Key::KEY_KP3, Key::KEY_KP4,
+        Key::KEY_KP5, Key::KEY_KP6, Key::KEY_KP7, Key::KEY_KP8, Key::KEY_KP9,
+    ];
+    let digits = format!("0{}", code);
+
+    actions.push(KeyAction::Press(Key::KEY_LEFTALT));
+    for digit in digits.bytes() {
+        let key = KEYPAD[(digit - b'0') as usize];
+        actions.extend([KeyAction::Press(key), KeyAction::Release(key)]);
+    }
+    actions.push(KeyAction::Release(Key::KEY_LEFTALT));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbology::CodeId;
+    use crate::timing::TimingProfile;
+    use KeyAction::{NextChar, Press, Release};
+
+    fn pressed(actions: &[KeyAction]) -> Vec<Key> {
+        actions
+            .iter()
+            .filter_map(|action| match action {
+                Press(key) => Some(*key),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_identity() {
+        assert_eq!(parse_hex_u16("0x0C2E").unwrap(), 0x0C2E);
+        assert_eq!(parse_hex_u16(" 05e0 ").unwrap(), 0x05E0);
+        assert!(parse_hex_u16("0x10000").is_err());
+        assert!(parse_hex_u16("zebra").is_err());
+
+        let identity: DeviceIdentity =
+            serde_json::from_str(r#"{ "name": "Honeywell Scanner", "vendor_id": 3118, "bus": "bluetooth" }"#).unwrap();
+        assert_eq!(identity.describe(), "Honeywell Scanner (0c2e:5678)");
+        assert_eq!(identity.bus, BusKind::Bluetooth);
+        assert!(identity.validate().is_ok());
+        assert!(DeviceIdentity { name: " ".to_string(), ..Default::default() }.validate().is_err());
+        assert!(DeviceIdentity { name: "x".repeat(80), ..Default::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn test_plan_shifted_char() {
+        let (actions, report) = plan_keys(&KeyboardLayout::default(), UnsupportedChars::Skip, &ControlChars::default(), "!").unwrap();
+        assert_eq!(
+            actions,
+            vec![
+                Press(Key::KEY_LEFTSHIFT),
+                Press(Key::KEY_1),
+                Release(Key::KEY_1),
+                Release(Key::KEY_LEFTSHIFT),
+                NextChar,
+            ]
+        );
+        assert!(report.is_exact());
+    }
+
+    #[test]
+    fn test_schedule() {
+        let (actions, _) = plan_keys(&KeyboardLayout::default(), UnsupportedChars::Skip, &ControlChars::default(), "!a").unwrap();
+        let timing = KeyTiming {
+            press: Duration::from_millis(2),
+            release: Duration::from_millis(1),
+            inter_char: Duration::from_millis(10),
+        };
+        let (events, end) = schedule(&actions, &timing, &mut Jitter::new(0, 0));
+        let ms = |ms| Duration::from_millis(ms);
+        assert_eq!(
+            events,
+            vec![
+                (Key::KEY_LEFTSHIFT, 1, ms(0)),
+                (Key::KEY_1, 1, ms(2)),
+                (Key::KEY_1, 0, ms(4)),
+                (Key::KEY_LEFTSHIFT, 0, ms(5)),
+                (Key::KEY_A, 1, ms(16)),
+                (Key::KEY_A, 0, ms(18)),
+            ]
+        );
+        assert_eq!(end, ms(29));
+
+        let (_, end) = schedule(&actions, &TimingProfile::Instant.timing(), &mut Jitter::new(50, 1));
+        assert_eq!(end, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_unsupported_chars_are_reported() {
+        let layout = KeyboardLayout::default();
+        let control = ControlChars::default();
+        let (actions, report) = plan_keys(&layout, UnsupportedChars::Skip, &control, "A€1ß2").unwrap();
+        assert_eq!(report.skipped, vec![(1, '€'), (3, 'ß')]);
+        assert_eq!(pressed(&actions), vec![Key::KEY_LEFTSHIFT, Key::KEY_A, Key::KEY_1, Key::KEY_2]);
+        assert!(report.warnings().unwrap().contains("'€' at 1"));
+
+        let error = plan_keys(&layout, UnsupportedChars::Strict, &control, "A€1").unwrap_err();
+        assert!(error.to_string().contains("'€' at 1"), "{}", error);
+        assert!(plan_keys(&layout, UnsupportedChars::Strict, &control, "A1").is_ok());
+    }
+
+    #[test]
+    fn test_scan_framing() {
+        let layout = KeyboardLayout::default();
+        let (actions, _) = plan_scan(&layout, UnsupportedChars::Skip, &ScannerConfig::default(), None, "1").unwrap();
+        assert_eq!(pressed(&actions), vec![Key::KEY_1, Key::KEY_ENTER]);
+
+        let config = ScannerConfig {
+            prefix: "a".to_string(),
+            suffix: "b".to_string(),
+            terminator: Terminator::CrLf,
+            focus_key: Some("KEY_F12".to_string()),
+            ..Default::default()
+        };
+        let (actions, report) = plan_scan(&layout, UnsupportedChars::Skip, &config, None, "1").unwrap();
+        assert_eq!(
+            pressed(&actions),
+            vec![Key::KEY_F12, Key::KEY_A, Key::KEY_1, Key::KEY_B, Key::KEY_LEFTCTRL, Key::KEY_M, Key::KEY_LEFTCTRL, Key::KEY_J]
+        );
+        assert!(report.is_exact());
+
+        let config = ScannerConfig { terminator: Terminator::None, ..Default::default() };
+        let (actions, _) = plan_scan(&layout, UnsupportedChars::Skip, &config, None, "1").unwrap();
+        assert_eq!(actions.last(), Some(&NextChar));
+        assert_eq!(pressed(&actions), vec![Key::KEY_1]);
+
+        // STX/ETX framing as many POS applications expect it
+        let config = ScannerConfig {
+            prefix: "<STX>".to_string(),
+            suffix: "<0x03>".to_string(),
+            terminator: Terminator::None,
+            ..Default::default()
+        };
+        let (actions, report) = plan_scan(&layout, UnsupportedChars::Strict, &config, None, "1").unwrap();
+        assert_eq!(
+            actions,
+            vec![
+                Press(Key::KEY_LEFTCTRL), Press(Key::KEY_B), Release(Key::KEY_B), Release(Key::KEY_LEFTCTRL), NextChar,
+                Press(Key::KEY_1), Release(Key::KEY_1), NextChar,
+                Press(Key::KEY_LEFTCTRL), Press(Key::KEY_C), Release(Key::KEY_C), Release(Key::KEY_LEFTCTRL), NextChar,
+            ]
+        );
+        assert!(report.is_exact());
+    }
+
+    #[test]
+    fn test_scan_with_symbology() {
+        let layout = KeyboardLayout::default();
+        let config = ScannerConfig { code_id: CodeId::Aim, terminator: Terminator::None, ..Default::default() };
+        let (actions, _) = plan_scan(&layout, UnsupportedChars::Skip, &config, Some(Symbology::Ean8), "96385074").unwrap();
+        let keys = pressed(&actions);
+        assert_eq!(&keys[..5], &[Key::KEY_RIGHTBRACE, Key::KEY_LEFTSHIFT, Key::KEY_E, Key::KEY_4, Key::KEY_9]);
+
+        // Without a symbology no code ID is sent
+        let (actions, _) = plan_scan(&layout, UnsupportedChars::Skip, &config, None, "9").unwrap();
+        assert_eq!(pressed(&actions), vec![Key::KEY_9]);
+
+        let error = plan_scan(&layout, UnsupportedChars::Skip, &config, Some(Symbology::Ean8), "96385075").unwrap_err();
+        assert!(error.to_string().contains("check digit"), "{}", error);
+    }
+
+    #[test]
+    fn test_control_chars() {
+        let layout = KeyboardLayout::default();
+        let text = "1\u{1d}2\u{1e}\u{04}";
+        let (actions, report) = plan_keys(&layout, UnsupportedChars::Strict, &ControlChars::default(), text).unwrap();
+        assert!(report.is_exact());
+        assert_eq!(
+            pressed(&actions),
+            vec![
+                Key::KEY_1,
+                Key::KEY_LEFTCTRL, Key::KEY_RIGHTBRACE,
+                Key::KEY_2,
+                Key::KEY_LEFTCTRL, Key::KEY_LEFTSHIFT, Key::KEY_6,
+                Key::KEY_LEFTCTRL, Key::KEY_D,
+            ]
+        );
+
+        let (actions, _) = plan_keys(&layout, UnsupportedChars::Skip, &ControlChars::AltKeypad, "\u{1d}").unwrap();
+        assert_eq!(pressed(&actions), vec![Key::KEY_LEFTALT, Key::KEY_KP0, Key::KEY_KP2, Key::KEY_KP9]);
+
+        let replace = ControlChars::Replace { gs: "~".to_string(), rs: String::new(), eot: String::new() };
+        let (actions, _) = plan_keys(&layout, UnsupportedChars::Skip, &replace, "\u{1d}\u{04}").unwrap();
+        assert_eq!(pressed(&actions), vec![Key::KEY_LEFTSHIFT, Key::KEY_GRAVE, Key::KEY_LEFTCTRL, Key::KEY_D]);
+    }
+
+    #[test]
+    fn test_gs1_scan() {
+        let layout = KeyboardLayout::default();
+        let config = ScannerConfig { terminator: Terminator::None, ..Default::default() };
+        let (actions, _) =
+            plan_scan(&layout, UnsupportedChars::Strict, &config, Some(Symbology::Gs1_128), "(10)1(21)2").unwrap();
+        assert_eq!(
+            pressed(&actions),
+            vec![Key::KEY_1, Key::KEY_0, Key::KEY_1, Key::KEY_LEFTCTRL, Key::KEY_RIGHTBRACE, Key::KEY_2, Key::KEY_1, Key::KEY_2]
+        );
+    }
+
+    #[test]
+    fn test_unicode_fallbacks() {
+        let layout = KeyboardLayout::default();
+        let (actions, report) = plan_keys(&layout, UnsupportedChars::UnicodeHex, &ControlChars::default(), "é").unwrap();
+        assert_eq!(report.substituted, vec![(0, 'é')]);
+        assert_eq!(
+            pressed(&actions),
+            vec![Key::KEY_LEFTCTRL, Key::KEY_LEFTSHIFT, Key::KEY_U, Key::KEY_E, Key::KEY_9, Key::KEY_SPACE]
+        );
+
+        // Windows-1252 codes: é is 233, € is 128; ✓ and Ā have none and are skipped
+        let (actions, report) =
+            plan_keys(&layout, UnsupportedChars::AltKeypad, &ControlChars::default(), "é✓€Ā").unwrap();
+        assert_eq!(
+            pressed(&actions),
+            vec![
+                Key::KEY_LEFTALT, Key::KEY_KP0, Key::KEY_KP2, Key::KEY_KP3, Key::KEY_KP3,
+                Key::KEY_LEFTALT, Key::KEY_KP0, Key::KEY_KP1, Key::KEY_KP2, Key::KEY_KP8,
+            ]
+        );
+        assert_eq!(report.substituted, vec![(0, 'é'), (2, '€')]);
+        assert_eq!(report.skipped, vec![(1, '✓'), (3, 'Ā')]);
+        assert_eq!(windows_1252('Ÿ'), Some(0x9F));
+        assert_eq!(windows_1252('\u{81}'), None);
+    }
 }
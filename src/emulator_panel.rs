@@ -0,0 +1,272 @@
+use gtk4::prelude::*;
+use gtk4::{Box, Button, DropDown, FlowBox, Frame, Label, Orientation, ScrolledWindow, SelectionMode, TextView};
+use glib::clone;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::Duration;
+use virtusdev::bill_emulator::{DeviceFault, DeviceState, UNIT_TYPE_NV200, UNIT_TYPE_SMART_HOPPER};
+use virtusdev::capture::{CaptureBroadcast, CaptureRecord, Direction};
+use virtusdev::control::Devices;
+use virtusdev::repl::format_money;
+use virtusdev::serial_bridge::SerialBridge;
+
+const FAULTS: &[&str] = &["jam", "cashbox_removed", "reject", "reset"];
+const REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+/// Oldest log lines are dropped past this many
+const MAX_LOG_LINES: i32 = 2000;
+
+/// Labels of one device panel that follow the device state
+struct DevicePanel {
+    address: u8,
+    state: Label,
+    levels: Label,
+}
+
+/// Tab that runs the bill & coin emulator and drives its devices
+pub fn build_emulator_panel() -> Box {
+    let panel = Box::new(Orientation::Vertical, 10);
+    panel.set_margin_top(20);
+    panel.set_margin_bottom(20);
+    panel.set_margin_start(20);
+    panel.set_margin_end(20);
+
+    let status_label = Label::new(Some("Status: ○ Stopped"));
+    status_label.set_halign(gtk4::Align::Start);
+    panel.append(&status_label);
+
+    let path_label = Label::new(Some("Serial port: -"));
+    path_label.set_halign(gtk4::Align::Start);
+    path_label.set_selectable(true);
+    panel.append(&path_label);
+
+    let start_button = Button::with_label("START EMULATOR");
+    start_button.add_css_class("suggested-action");
+    panel.append(&start_button);
+
+    let devices_box = Box::new(Orientation::Vertical, 10);
+    let devices_scroll = ScrolledWindow::builder()
+        .hscrollbar_policy(gtk4::PolicyType::Never)
+        .min_content_height(250)
+        .vexpand(true)
+        .child(&devices_box)
+        .build();
+    panel.append(&devices_scroll);
+
+    let log_title = Label::new(Some("Command Log"));
+    log_title.set_halign(gtk4::Align::Start);
+    panel.append(&log_title);
+
+    let log_view = TextView::builder().editable(false).monospace(true).build();
+    let log_scroll = ScrolledWindow::builder()
+        .min_content_height(150)
+        .child(&log_view)
+        .build();
+    panel.append(&log_scroll);
+
+    start_button.connect_clicked(clone!(
+        #[weak] status_label,
+        #[weak] path_label,
+        #[weak] devices_box,
+        #[weak] log_view,
+        move |button| {
+            let (path, devices, records) = match start_bridge() {
+                Ok(started) => started,
+                Err(e) => {
+                    status_label.set_text(&format!("Status: ⚠ {}", e));
+                    return;
+                }
+            };
+            button.set_sensitive(false);
+            status_label.set_text("Status: ● Running");
+            path_label.set_text(&format!("Serial port: {}", path));
+
+            let panels: Vec<DevicePanel> = {
+                let devices_guard = devices.lock().unwrap();
+                let mut sorted: Vec<&DeviceState> = devices_guard.values().collect();
+                sorted.sort_by_key(|device| device.address);
+                sorted
+                    .into_iter()
+                    .map(|device| {
+                        let (frame, panel) = build_device_panel(device, &devices, &log_view);
+                        devices_box.append(&frame);
+                        panel
+                    })
+                    .collect()
+            };
+
+            glib::timeout_add_local(REFRESH_INTERVAL, move || {
+                drain_log(&records, &log_view);
+                let devices = devices.lock().unwrap();
+                for panel in &panels {
+                    if let Some(device) = devices.get(&panel.address) {
+                        refresh_device_panel(panel, device);
+                    }
+                }
+                glib::ControlFlow::Continue
+            });
+        }
+    ));
+
+    panel
+}
+
+/// Create the PTY and run the serial bridge on a background thread
+fn start_bridge() -> anyhow::Result<(String, Devices, Receiver<CaptureRecord>)> {
+    let mut bridge = SerialBridge::new()?;
+    let events = CaptureBroadcast::new();
+    let records = events.subscribe();
+    bridge.add_capture(std::boxed::Box::new(events));
+
+    let path = bridge.slave_path().to_string();
+    let devices = bridge.get_devices();
+    thread::spawn(move || {
+        if let Err(e) = bridge.run() {
+            eprintln!("Serial bridge stopped: {}", e);
+        }
+    });
+    Ok((path, devices, records))
+}
+
+fn device_title(device: &DeviceState) -> String {
+    let kind = match device.unit_type {
+        UNIT_TYPE_NV200 => "NV200 Note Validator",
+        UNIT_TYPE_SMART_HOPPER => "Smart Hopper",
+        _ => "Device",
+    };
+    format!("{} (0x{:02X}, {})", kind, device.address, String::from_utf8_lossy(&device.currency_code))
+}
+
+fn build_device_panel(device: &DeviceState, devices: &Devices, log_view: &TextView) -> (Frame, DevicePanel) {
+    let address = device.address;
+    let is_note = device.unit_type == UNIT_TYPE_NV200;
+
+    let frame = Frame::new(Some(&device_title(device)));
+    let content = Box::new(Orientation::Vertical, 5);
+    content.set_margin_top(10);
+    content.set_margin_bottom(10);
+    content.set_margin_start(10);
+    content.set_margin_end(10);
+
+    let state = Label::new(None);
+    state.set_halign(gtk4::Align::Start);
+    content.append(&state);
+
+    // One button per channel denomination
+    let denominations = FlowBox::builder()
+        .selection_mode(SelectionMode::None)
+        .max_children_per_line(7)
+        .build();
+    for channel in &device.channels {
+        let value = channel.value;
+        let button = Button::with_label(&format_money(value));
+        button.connect_clicked(clone!(
+            #[strong] devices,
+            #[weak] log_view,
+            move |_| {
+                let mut devices = devices.lock().unwrap();
+                let Some(device) = devices.get_mut(&address) else {
+                    return;
+                };
+                let accepted = if is_note { device.insert_note(value) } else { device.insert_coins(value) };
+                if !accepted {
+                    append_log(&log_view, &format!("⚠ 0x{:02X} is disabled, {} not accepted", address, format_money(value)));
+                }
+            }
+        ));
+        denominations.insert(&button, -1);
+    }
+    content.append(&denominations);
+
+    let levels = Label::new(None);
+    levels.set_halign(gtk4::Align::Start);
+    levels.set_wrap(true);
+    content.append(&levels);
+
+    // Fault injection
+    let fault_box = Box::new(Orientation::Horizontal, 5);
+    let fault_choice = DropDown::from_strings(FAULTS);
+    let fault_button = Button::with_label("Inject Fault");
+    fault_button.connect_clicked(clone!(
+        #[strong] devices,
+        #[weak] fault_choice,
+        #[weak] log_view,
+        move |_| {
+            let Some(name) = FAULTS.get(fault_choice.selected() as usize) else {
+                return;
+            };
+            let Ok(fault) = name.parse::<DeviceFault>() else {
+                return;
+            };
+            if let Some(device) = devices.lock().unwrap().get_mut(&address) {
+                device.inject_fault(fault);
+                append_log(&log_view, &format!("⚡ 0x{:02X} {:?} queued", address, fault));
+            }
+        }
+    ));
+    fault_box.append(&fault_choice);
+    fault_box.append(&fault_button);
+    content.append(&fault_box);
+
+    frame.set_child(Some(&content));
+    let panel = DevicePanel { address, state, levels };
+    refresh_device_panel(&panel, device);
+    (frame, panel)
+}
+
+fn refresh_device_panel(panel: &DevicePanel, device: &DeviceState) {
+    let status = device.status();
+    panel.state.set_text(&format!(
+        "{} | payout {} | inhibits {:02X} {:02X} | {} queued event(s)",
+        if status.enabled { "● Enabled" } else { "○ Disabled" },
+        if status.payout_enabled { "on" } else { "off" },
+        status.inhibits[0],
+        status.inhibits[1],
+        status.queued_events.len()
+    ));
+
+    let levels: Vec<String> = status
+        .levels
+        .iter()
+        .map(|level| format!("{} × {}", format_money(level.value), level.count))
+        .collect();
+    panel.levels.set_text(&format!("Levels: {}", levels.join(", ")));
+}
+
+fn describe_record(record: &CaptureRecord) -> String {
+    let address = match record.undecodable {
+        true => "????".to_string(),
+        false => format!("0x{:02X}", record.address),
+    };
+    let route = match record.direction {
+        Direction::HostToDevice => format!("HOST → {}", address),
+        Direction::DeviceToHost => format!("{} → HOST", address),
+    };
+    let name = match &record.command {
+        Some(command) => format!("{} ({})", record.name, command),
+        None => record.name.clone(),
+    };
+    if record.events.is_empty() {
+        format!("{}  {}", route, name)
+    } else {
+        format!("{}  {}  {}", route, name, record.events.join(", "))
+    }
+}
+
+fn drain_log(records: &Receiver<CaptureRecord>, log_view: &TextView) {
+    for record in records.try_iter() {
+        append_log(log_view, &describe_record(&record));
+    }
+}
+
+fn append_log(log_view: &TextView, line: &str) {
+    let buffer = log_view.buffer();
+    buffer.insert(&mut buffer.end_iter(), &format!("{}\n", line));
+
+    let excess = buffer.line_count() - MAX_LOG_LINES;
+    if excess > 0 {
+        if let Some(mut cut) = buffer.iter_at_line(excess) {
+            buffer.delete(&mut buffer.start_iter(), &mut cut);
+        }
+    }
+    log_view.scroll_to_iter(&mut buffer.end_iter(), 0.0, false, 0.0, 1.0);
+}
@@ -9,6 +9,7 @@ pub const RESPONSE_KEY_NOT_SET: u8 = 0xFA;
 pub const RESPONSE_COMMAND_NOT_KNOWN: u8 = 0xF2;
 
 // Command codes
+pub const CMD_RESET: u8 = 0x01;
 pub const CMD_SYNC: u8 = 0x11;
 pub const CMD_SETUP_REQUEST: u8 = 0x05;
 pub const CMD_HOST_PROTOCOL: u8 = 0x06;
@@ -39,6 +40,94 @@ pub const EVENT_JAMMED: u8 = 0xD5;
 pub const EVENT_CASHBOX_REMOVED: u8 = 0xE3;
 pub const EVENT_COINS_VALUE_ADDED: u8 = 0xBF;
 
+const COMMAND_NAMES: &[(u8, &str)] = &[
+    (CMD_RESET, "RESET"),
+    (CMD_SYNC, "SYNC"),
+    (CMD_SETUP_REQUEST, "SETUP_REQUEST"),
+    (CMD_HOST_PROTOCOL, "HOST_PROTOCOL"),
+    (CMD_POLL, "POLL"),
+    (CMD_ENABLE, "ENABLE"),
+    (CMD_DISABLE, "DISABLE"),
+    (CMD_SET_INHIBITS, "SET_INHIBITS"),
+    (CMD_GET_ALL_LEVELS, "GET_ALL_LEVELS"),
+    (CMD_PAYOUT, "PAYOUT"),
+    (CMD_ENABLE_PAYOUT, "ENABLE_PAYOUT"),
+    (CMD_SETUP_ENCRYPTION, "SETUP_ENCRYPTION"),
+    (CMD_SET_ROUTE, "SET_ROUTE"),
+    (CMD_REJECT, "REJECT"),
+    (CMD_COIN_MECH_GLOBAL_INHIBIT, "COIN_MECH_GLOBAL_INHIBIT"),
+];
+
+/// Human readable name of a command code
+pub fn command_name(code: u8) -> &'static str {
+    COMMAND_NAMES
+        .iter()
+        .find(|(c, _)| *c == code)
+        .map(|(_, name)| *name)
+        .unwrap_or("UNKNOWN")
+}
+
+/// Human readable name of a response status byte
+pub fn status_name(code: u8) -> &'static str {
+    match code {
+        RESPONSE_OK => "OK",
+        RESPONSE_ERROR => "ERROR",
+        RESPONSE_KEY_NOT_SET => "KEY_NOT_SET",
+        RESPONSE_COMMAND_NOT_KNOWN => "COMMAND_NOT_KNOWN",
+        _ => "UNKNOWN_STATUS",
+    }
+}
+
+const EVENT_NAMES: &[(u8, &str)] = &[
+    (EVENT_RESET, "RESET"),
+    (EVENT_READ, "READ"),
+    (EVENT_CREDIT, "CREDIT"),
+    (EVENT_REJECTING, "REJECTING"),
+    (EVENT_REJECTED, "REJECTED"),
+    (EVENT_STACKING, "STACKING"),
+    (EVENT_STACKED, "STACKED"),
+    (EVENT_DISABLED, "DISABLED"),
+    (EVENT_DISPENSING, "DISPENSING"),
+    (EVENT_DISPENSED, "DISPENSED"),
+    (EVENT_JAMMED, "JAMMED"),
+    (EVENT_CASHBOX_REMOVED, "CASHBOX_REMOVED"),
+    (EVENT_COINS_VALUE_ADDED, "COINS_VALUE_ADDED"),
+];
+
+/// Human readable name of a poll event code
+pub fn event_name(code: u8) -> &'static str {
+    EVENT_NAMES
+        .iter()
+        .find(|(c, _)| *c == code)
+        .map(|(_, name)| *name)
+        .unwrap_or("UNKNOWN_EVENT")
+}
+
+/// Poll event code for a name such as "CREDIT" or "credit"
+pub fn event_code(name: &str) -> Option<u8> {
+    EVENT_NAMES
+        .iter()
+        .find(|(_, n)| n.eq_ignore_ascii_case(name))
+        .map(|(code, _)| *code)
+}
+
+/// Command code for a name such as "POLL" or "poll"
+pub fn command_code(name: &str) -> Option<u8> {
+    COMMAND_NAMES
+        .iter()
+        .find(|(_, n)| n.eq_ignore_ascii_case(name))
+        .map(|(code, _)| *code)
+}
+
+/// Parse a slave address written as "0x10" or "16"
+pub fn parse_address(s: &str) -> Result<u8> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| anyhow!("Invalid address: {}", s))
+}
+
 // CRC-16 using polynomial 0x8005, init 0xFFFF, no reflection (matches eSSP spec)
 const CRC_ESSP_ALGO: crc::Algorithm<u16> = crc::Algorithm {
     width: 16,
@@ -64,65 +153,48 @@ impl EsspPacket {
         Self { sequence, data }
     }
 
-    /// Serialize packet to bytes with STX, sequence, length, data, and CRC
+    /// Serialize packet to bytes: STX, then SEQ, LEN, DATA and CRC with every
+    /// 0x7F stuffed. LEN counts the unstuffed data, at most 255 bytes.
+    ///
+    /// # Panics
+    ///
+    /// If `data` is longer than 255 bytes, which LEN cannot express.
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut packet = Vec::new();
-        
-        // Calculate stuffed data first
-        let stuffed_data = stuff_bytes(&self.data);
-        
-        // STX
-        packet.push(STX);
-        
-        // Sequence
-        packet.push(self.sequence);
-
-        // Length (data only, not including sequence or length byte itself)
-        let length = stuffed_data.len();
-        packet.push(length as u8);
-        
-        // Data (already stuffed)
-        packet.extend_from_slice(&stuffed_data);
-        
-        // Calculate CRC on everything except STX
-        let crc_data = &packet[1..];
-        let crc = calculate_crc(crc_data);
-        
-        // Append CRC (little-endian)
-        packet.push((crc & 0xFF) as u8);
-        packet.push((crc >> 8) as u8);
-        
+        let len = u8::try_from(self.data.len())
+            .unwrap_or_else(|_| panic!("eSSP data is {} bytes, LEN allows at most 255", self.data.len()));
+        let mut body = Vec::with_capacity(self.data.len() + 4);
+        body.push(self.sequence);
+        body.push(len);
+        body.extend_from_slice(&self.data);
+
+        // CRC over SEQ + LEN + DATA before stuffing, little-endian
+        let crc = calculate_crc(&body);
+        body.extend_from_slice(&crc.to_le_bytes());
+
+        let mut packet = vec![STX];
+        packet.extend(stuff_bytes(&body));
         packet
     }
 
     /// Parse packet from bytes, returns packet and number of bytes consumed
     pub fn from_bytes(data: &[u8]) -> Result<(Self, usize)> {
-        if data.len() < 5 {
+        let Some(&first) = data.first() else {
             return Err(anyhow!("Packet too short"));
+        };
+        if first != STX {
+            return Err(anyhow!("Invalid STX byte: expected 0x7F, got 0x{:02X}", first));
         }
 
-        // Check STX
-        if data[0] != STX {
-            return Err(anyhow!("Invalid STX byte: expected 0x7F, got 0x{:02X}", data[0]));
-        }
-
-        // Byte 1 is Sequence
-        let sequence = data[1];
-
-        // Byte 2 is Length
-        let length = data[2] as usize;
-        
-        // Total = STX(1) + SEQ(1) + LEN(1) + DATA(len) + CRC(2)
-        let total_len = 1 + 1 + 1 + length + 2;
-
-        if data.len() < total_len {
-            return Err(anyhow!("Incomplete packet: need {}, have {}", total_len, data.len()));
-        }
+        let (body, consumed) = match deframe(data) {
+            Deframed::Complete(body, consumed) => (body, consumed),
+            Deframed::Incomplete => return Err(anyhow!("Incomplete packet: {} bytes", data.len())),
+            Deframed::Interrupted(offset) => return Err(anyhow!("Unstuffed STX at offset {}", offset)),
+        };
 
         // Verify CRC (calculated over SEQ + LEN + DATA)
-        let crc_data = &data[1..total_len - 2];
-        let expected_crc = calculate_crc(crc_data);
-        let received_crc = u16::from_le_bytes([data[total_len - 2], data[total_len - 1]]);
+        let (content, crc) = body.split_at(body.len() - 2);
+        let expected_crc = calculate_crc(content);
+        let received_crc = u16::from_le_bytes([crc[0], crc[1]]);
 
         if expected_crc != received_crc {
             return Err(anyhow!(
@@ -131,17 +203,123 @@ impl EsspPacket {
                 received_crc
             ));
         }
-        
-        // Extract and unstuff data
-        let stuffed_data = &data[3..3 + length];
-        let unstuffed_data = unstuff_bytes(stuffed_data)?;
 
-        Ok((Self::new(sequence, unstuffed_data), total_len))
+        Ok((Self::new(content[0], content[2..].to_vec()), consumed))
+    }
+
+    /// True if `data` starts like a frame but more bytes are needed to parse it
+    pub fn is_incomplete(data: &[u8]) -> bool {
+        match data.first() {
+            Some(&STX) => matches!(deframe(data), Deframed::Incomplete),
+            Some(_) => false,
+            None => true,
+        }
+    }
+}
+
+/// Outcome of reading the frame that starts at `data[0]`
+enum Deframed {
+    /// Unstuffed SEQ, LEN, DATA and CRC, and the number of raw bytes consumed
+    Complete(Vec<u8>, usize),
+    /// The bytes end before the frame does
+    Incomplete,
+    /// A lone STX at this offset starts a new frame before this one ended
+    Interrupted(usize),
+}
+
+/// Unstuff the bytes after STX until SEQ, LEN, LEN data bytes and the CRC are read
+fn deframe(data: &[u8]) -> Deframed {
+    let mut body = Vec::new();
+    // SEQ and LEN first, the rest of the size is known once LEN is read
+    let mut needed = 2;
+    let mut i = 1;
+
+    while body.len() < needed {
+        let Some(&byte) = data.get(i) else {
+            return Deframed::Incomplete;
+        };
+        if byte == STX {
+            match data.get(i + 1) {
+                Some(&STX) => i += 1,
+                Some(_) => return Deframed::Interrupted(i),
+                None => return Deframed::Incomplete,
+            }
+        }
+        i += 1;
+        body.push(byte);
+        if body.len() == 2 {
+            needed = 2 + body[1] as usize + 2;
+        }
+    }
+    Deframed::Complete(body, i)
+}
+
+/// Something found in a byte stream by `FrameScanner`
+#[derive(Debug, Clone)]
+pub enum ScanItem {
+    /// A valid frame and the raw bytes it was read from
+    Frame(EsspPacket, Vec<u8>),
+    /// Bytes that are not part of any valid frame
+    Garbage(Vec<u8>),
+}
+
+/// Splits a byte stream into frames, resynchronising on the next STX after
+/// garbage or a frame with a bad CRC.
+#[derive(Debug, Default)]
+pub struct FrameScanner {
+    buffer: Vec<u8>,
+}
+
+impl FrameScanner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bytes waiting for the rest of a frame
+    pub fn pending(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Add received bytes and return every complete item
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<ScanItem> {
+        self.buffer.extend_from_slice(bytes);
+        let mut items = Vec::new();
+        let mut garbage = Vec::new();
+
+        loop {
+            // Skip to the next start of frame
+            let start = self.buffer.iter().position(|&b| b == STX).unwrap_or(self.buffer.len());
+            garbage.extend(self.buffer.drain(..start));
+            if self.buffer.is_empty() {
+                break;
+            }
+
+            match EsspPacket::from_bytes(&self.buffer) {
+                Ok((packet, consumed)) => {
+                    if !garbage.is_empty() {
+                        items.push(ScanItem::Garbage(std::mem::take(&mut garbage)));
+                    }
+                    let raw = self.buffer.drain(..consumed).collect();
+                    items.push(ScanItem::Frame(packet, raw));
+                }
+                Err(_) => match deframe(&self.buffer) {
+                    Deframed::Incomplete => break,
+                    // The frame was cut short, resume at the new STX
+                    Deframed::Interrupted(offset) => garbage.extend(self.buffer.drain(..offset)),
+                    Deframed::Complete(..) => garbage.push(self.buffer.remove(0)),
+                },
+            }
+        }
+
+        if !garbage.is_empty() {
+            items.push(ScanItem::Garbage(garbage));
+        }
+        items
     }
 }
 
 /// Byte stuffing: 0x7F becomes 0x7F 0x7F
-fn stuff_bytes(data: &[u8]) -> Vec<u8> {
+pub fn stuff_bytes(data: &[u8]) -> Vec<u8> {
     let mut stuffed = Vec::with_capacity(data.len());
     for &byte in data {
         stuffed.push(byte);
@@ -152,25 +330,27 @@ fn stuff_bytes(data: &[u8]) -> Vec<u8> {
     stuffed
 }
 
-/// Byte unstuffing: 0x7F 0x7F becomes 0x7F
-fn unstuff_bytes(data: &[u8]) -> Result<Vec<u8>> {
-    let mut unstuffed = Vec::new();
+/// Byte unstuffing: 0x7F 0x7F becomes 0x7F. A lone 0x7F is an error, on the
+/// wire it starts a new frame.
+pub fn unstuff_bytes(data: &[u8]) -> Result<Vec<u8>> {
+    let mut unstuffed = Vec::with_capacity(data.len());
     let mut i = 0;
     while i < data.len() {
         let byte = data[i];
-        unstuffed.push(byte);
         if byte == STX {
-            if i + 1 < data.len() && data[i + 1] == STX {
-                i += 1; // Skip the second 0x7F
+            if data.get(i + 1) != Some(&STX) {
+                return Err(anyhow!("Unstuffed STX at offset {}", i));
             }
+            i += 1; // Skip the second 0x7F
         }
+        unstuffed.push(byte);
         i += 1;
     }
     Ok(unstuffed)
 }
 
 /// Calculate CRC-16 for eSSP protocol
-fn calculate_crc(data: &[u8]) -> u16 {
+pub fn calculate_crc(data: &[u8]) -> u16 {
     CRC_ESSP.checksum(data)
 }
 
@@ -209,10 +389,54 @@ mod tests {
         assert_eq!(parsed.data, original.data);
     }
 
+    #[test]
+    #[should_panic(expected = "LEN allows at most 255")]
+    fn test_oversized_data_panics() {
+        EsspPacket::new(0x80, vec![0; 256]).to_bytes();
+    }
+
+    #[test]
+    fn test_scanner_resyncs_after_garbage() {
+        let frame = EsspPacket::new(0x80, vec![CMD_POLL]).to_bytes();
+        let mut stream = vec![0x00, 0x12];
+        stream.extend_from_slice(&frame);
+
+        let mut scanner = FrameScanner::new();
+        // Split inside the frame to exercise partial frames
+        let mut items = scanner.push(&stream[..4]);
+        items.extend(scanner.push(&stream[4..]));
+        assert_eq!(items.len(), 2);
+        assert!(matches!(&items[0], ScanItem::Garbage(g) if g == &vec![0x00, 0x12]));
+        assert!(matches!(&items[1], ScanItem::Frame(p, raw) if p.data == vec![CMD_POLL] && raw == &frame));
+        assert!(scanner.pending().is_empty());
+    }
+
+    #[test]
+    fn test_stuffing_covers_crc() {
+        // CRC of ENABLE to 0x10 is 0x897F, its low byte is stuffed
+        let bytes = EsspPacket::new(0x10, vec![CMD_ENABLE]).to_bytes();
+        assert_eq!(bytes, vec![0x7F, 0x10, 0x01, 0x0A, 0x7F, 0x7F, 0x89]);
+        let (packet, consumed) = EsspPacket::from_bytes(&bytes).unwrap();
+        assert_eq!((packet.data, consumed), (vec![CMD_ENABLE], 7));
+        assert!(EsspPacket::is_incomplete(&bytes[..5]));
+    }
+
+    #[test]
+    fn test_lone_stx_interrupts_frame() {
+        let mut stream = vec![0x7F, 0x80, 0x05, 0x01];
+        let sync = EsspPacket::new(0x80, vec![CMD_SYNC]).to_bytes();
+        stream.extend_from_slice(&sync);
+        assert!(!EsspPacket::is_incomplete(&stream));
+        assert!(EsspPacket::from_bytes(&stream).is_err());
+        assert!(unstuff_bytes(&[0x01, 0x7F, 0x02]).is_err());
+    }
+
     #[test]
     fn test_packet_with_stx_in_data() {
         let original = EsspPacket::new(10, vec![0x05, 0x7F, 0x10, 0x7F]);
         let bytes = original.to_bytes();
+        // LEN counts the unstuffed data
+        assert_eq!(bytes[2], 4);
         let (parsed, _) = EsspPacket::from_bytes(&bytes).unwrap();
         assert_eq!(parsed.data, original.data);
     }
@@ -0,0 +1,178 @@
+use anyhow::{Context, Result};
+use mio::net::UnixStream;
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Token};
+use std::io::{ErrorKind, Write};
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Token of the shutdown pipe, sources use their index
+const SHUTDOWN: Token = Token(usize::MAX);
+
+/// A file descriptor served by `run`
+pub trait EventSource {
+    fn raw_fd(&self) -> RawFd;
+
+    /// Called when the fd becomes readable. Sources are registered edge
+    /// triggered: read until the fd would block.
+    fn on_readable(&mut self) -> Result<()>;
+}
+
+/// Stops a running event loop from another thread or from a signal
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    requested: Arc<AtomicBool>,
+    sender: Arc<Mutex<std::os::unix::net::UnixStream>>,
+    receiver: Arc<Mutex<Option<UnixStream>>>,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> Result<Self> {
+        let (sender, receiver) = std::os::unix::net::UnixStream::pair().context("Failed to create shutdown pipe")?;
+        sender.set_nonblocking(true)?;
+        receiver.set_nonblocking(true)?;
+        Ok(Self {
+            requested: Arc::new(AtomicBool::new(false)),
+            sender: Arc::new(Mutex::new(sender)),
+            receiver: Arc::new(Mutex::new(Some(UnixStream::from_std(receiver)))),
+        })
+    }
+
+    /// Ask the event loop to return
+    pub fn shutdown(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        // A full pipe already has a wakeup pending
+        let _ = self.sender.lock().unwrap().write(&[1]);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Shut down on SIGINT and SIGTERM instead of being killed, so that
+    /// destructors run
+    pub fn shutdown_on_signals(&self) -> Result<()> {
+        for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+            let sender = self.sender.lock().unwrap().try_clone()?;
+            signal_hook::low_level::pipe::register(signal, sender)
+                .with_context(|| format!("Failed to install handler for signal {}", signal))?;
+        }
+        Ok(())
+    }
+}
+
+/// Serve `sources` from the calling thread until `shutdown` is triggered.
+///
+/// Blocks in epoll between events, there is no polling interval.
+pub fn run(sources: &mut [&mut dyn EventSource], shutdown: &ShutdownHandle) -> Result<()> {
+    let mut poll = Poll::new().context("Failed to create epoll instance")?;
+
+    let mut receiver = shutdown
+        .receiver
+        .lock()
+        .unwrap()
+        .take()
+        .context("Shutdown handle is already used by another event loop")?;
+    poll.registry().register(&mut receiver, SHUTDOWN, Interest::READABLE)?;
+    for (index, source) in sources.iter().enumerate() {
+        poll.registry()
+            .register(&mut SourceFd(&source.raw_fd()), Token(index), Interest::READABLE)?;
+    }
+
+    let result = serve(&mut poll, sources, shutdown);
+
+    for source in sources.iter() {
+        let _ = poll.registry().deregister(&mut SourceFd(&source.raw_fd()));
+    }
+    let _ = poll.registry().deregister(&mut receiver);
+    *shutdown.receiver.lock().unwrap() = Some(receiver);
+    result
+}
+
+fn serve(poll: &mut Poll, sources: &mut [&mut dyn EventSource], shutdown: &ShutdownHandle) -> Result<()> {
+    let mut events = Events::with_capacity(16);
+    // A shutdown requested before the loop started
+    if shutdown.is_shutdown() {
+        return Ok(());
+    }
+
+    loop {
+        if let Err(e) = poll.poll(&mut events, None) {
+            if e.kind() == ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e).context("epoll_wait failed");
+        }
+
+        for event in events.iter() {
+            match event.token() {
+                // Explicit shutdown or a signal written to the pipe
+                SHUTDOWN => {
+                    shutdown.requested.store(true, Ordering::SeqCst);
+                    return Ok(());
+                }
+                Token(index) => {
+                    if let Some(source) = sources.get_mut(index) {
+                        source.on_readable()?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::thread;
+    use std::time::Duration;
+
+    struct Counter {
+        stream: std::os::unix::net::UnixStream,
+        bytes: usize,
+    }
+
+    impl EventSource for Counter {
+        fn raw_fd(&self) -> RawFd {
+            std::os::unix::io::AsRawFd::as_raw_fd(&self.stream)
+        }
+
+        fn on_readable(&mut self) -> Result<()> {
+            let mut buf = [0u8; 64];
+            loop {
+                match self.stream.read(&mut buf) {
+                    Ok(0) => return Ok(()),
+                    Ok(n) => self.bytes += n,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_serves_sources_until_shutdown() {
+        let (mut a, a_end) = std::os::unix::net::UnixStream::pair().unwrap();
+        let (mut b, b_end) = std::os::unix::net::UnixStream::pair().unwrap();
+        a_end.set_nonblocking(true).unwrap();
+        b_end.set_nonblocking(true).unwrap();
+        let mut first = Counter { stream: a_end, bytes: 0 };
+        let mut second = Counter { stream: b_end, bytes: 0 };
+
+        let shutdown = ShutdownHandle::new().unwrap();
+        let stopper = shutdown.clone();
+        let writer = thread::spawn(move || {
+            a.write_all(b"hello").unwrap();
+            b.write_all(b"hi").unwrap();
+            thread::sleep(Duration::from_millis(50));
+            stopper.shutdown();
+        });
+
+        run(&mut [&mut first, &mut second], &shutdown).unwrap();
+        writer.join().unwrap();
+        assert_eq!((first.bytes, second.bytes), (5, 2));
+        assert!(shutdown.is_shutdown());
+    }
+}
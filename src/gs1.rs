@@ -0,0 +1,324 @@
+use anyhow::{anyhow, Result};
+
+/// FNC1 in the transmitted data: ASCII group separator
+pub const GS: char = '\u{1d}';
+pub const RS: char = '\u{1e}';
+pub const EOT: char = '\u{04}';
+
+/// AIs whose first two digits give a predefined length: they need no
+/// separator after them even when another element follows
+const PREDEFINED_LENGTH: &[&str] = &[
+    "00", "01", "02", "03", "04", "11", "12", "13", "14", "15", "16", "17", "18", "19", "20", "31", "32",
+    "33", "34", "35", "36", "41",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Charset {
+    Numeric,
+    /// GS1 AI encodable character set 82
+    Alphanumeric,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AiFormat {
+    /// AI, or its first digits when the last ones are a parameter (e.g. "310"
+    /// for 3100…3109)
+    prefix: &'static str,
+    ai_len: usize,
+    title: &'static str,
+    charset: Charset,
+    min: usize,
+    max: usize,
+    check_digit: bool,
+    date: bool,
+}
+
+const fn ai(prefix: &'static str, ai_len: usize, title: &'static str, charset: Charset, min: usize, max: usize) -> AiFormat {
+    AiFormat { prefix, ai_len, title, charset, min, max, check_digit: false, date: false }
+}
+
+const fn with_check_digit(format: AiFormat) -> AiFormat {
+    AiFormat { check_digit: true, ..format }
+}
+
+const fn date(prefix: &'static str, title: &'static str) -> AiFormat {
+    AiFormat { date: true, ..ai(prefix, 2, title, Charset::Numeric, 6, 6) }
+}
+
+use Charset::{Alphanumeric as X, Numeric as N};
+
+/// Application Identifiers of the GS1 General Specifications used in retail,
+/// logistics and healthcare labels
+const AIS: &[AiFormat] = &[
+    with_check_digit(ai("00", 2, "SSCC", N, 18, 18)),
+    with_check_digit(ai("01", 2, "GTIN", N, 14, 14)),
+    with_check_digit(ai("02", 2, "CONTENT", N, 14, 14)),
+    ai("10", 2, "BATCH/LOT", X, 1, 20),
+    date("11", "PROD DATE"),
+    date("12", "DUE DATE"),
+    date("13", "PACK DATE"),
+    date("15", "BEST BEFORE"),
+    date("16", "SELL BY"),
+    date("17", "USE BY"),
+    ai("20", 2, "VARIANT", N, 2, 2),
+    ai("21", 2, "SERIAL", X, 1, 20),
+    ai("22", 2, "CPV", X, 1, 20),
+    ai("240", 3, "ADDITIONAL ID", X, 1, 30),
+    ai("241", 3, "CUST. PART No.", X, 1, 30),
+    ai("250", 3, "SECONDARY SERIAL", X, 1, 30),
+    ai("30", 2, "VAR. COUNT", N, 1, 8),
+    ai("31", 4, "MEASURE", N, 6, 6),
+    ai("32", 4, "MEASURE", N, 6, 6),
+    ai("33", 4, "MEASURE", N, 6, 6),
+    ai("34", 4, "MEASURE", N, 6, 6),
+    ai("35", 4, "MEASURE", N, 6, 6),
+    ai("36", 4, "MEASURE", N, 6, 6),
+    ai("37", 2, "COUNT", N, 1, 8),
+    ai("390", 4, "AMOUNT", N, 1, 15),
+    ai("392", 4, "PRICE", N, 1, 15),
+    ai("400", 3, "ORDER NUMBER", X, 1, 30),
+    ai("401", 3, "GINC", X, 1, 30),
+    with_check_digit(ai("402", 3, "GSIN", N, 17, 17)),
+    with_check_digit(ai("41", 3, "GLN", N, 13, 13)),
+    ai("420", 3, "SHIP TO POST", X, 1, 20),
+    ai("422", 3, "ORIGIN", N, 3, 3),
+    ai("71", 3, "NHRN", X, 1, 20),
+    ai("7003", 4, "EXPIRY TIME", N, 10, 10),
+    ai("8004", 4, "GIAI", X, 1, 30),
+    ai("8005", 4, "PRICE PER UNIT", N, 6, 6),
+    ai("8200", 4, "PRODUCT URL", X, 1, 70),
+    ai("90", 2, "INTERNAL", X, 1, 30),
+    ai("9", 2, "INTERNAL", X, 1, 90),
+];
+
+/// One AI and its value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Element {
+    pub ai: String,
+    pub value: String,
+}
+
+impl Element {
+    /// Data title of the AI, e.g. "GTIN" for 01
+    pub fn title(&self) -> &'static str {
+        lookup(&self.ai).map(|format| format.title).unwrap_or("UNKNOWN")
+    }
+}
+
+fn lookup(ai: &str) -> Option<&'static AiFormat> {
+    AIS.iter().find(|format| format.ai_len == ai.len() && ai.starts_with(format.prefix))
+}
+
+/// Format of the AI at the start of `data`
+fn lookup_prefix(data: &str) -> Option<&'static AiFormat> {
+    AIS.iter().find(|format| {
+        data.len() >= format.ai_len
+            && data.starts_with(format.prefix)
+            && data[..format.ai_len].bytes().all(|b| b.is_ascii_digit())
+    })
+}
+
+/// Parse the human readable form "(01)09501101530003(17)250101(10)ABC"
+pub fn parse_bracketed(text: &str) -> Result<Vec<Element>> {
+    let mut elements = Vec::new();
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let inner = rest
+            .strip_prefix('(')
+            .ok_or_else(|| anyhow!("Expected '(' before an AI at {:?}", rest))?;
+        let close = inner.find(')').ok_or_else(|| anyhow!("Missing ')' after AI"))?;
+        let ai = &inner[..close];
+        let after = &inner[close + 1..];
+        // The value runs to the next "(AI)"
+        let end = after
+            .match_indices('(')
+            .map(|(i, _)| i)
+            .find(|&i| bracketed_ai(&after[i..]).is_some())
+            .unwrap_or(after.len());
+        elements.push(Element { ai: ai.to_string(), value: after[..end].to_string() });
+        rest = &after[end..];
+    }
+    validate(&elements)?;
+    Ok(elements)
+}
+
+/// Known AI in "(AI)" at the start of `text`
+fn bracketed_ai(text: &str) -> Option<&str> {
+    let close = text.find(')')?;
+    let ai = &text[1..close];
+    (ai.bytes().all(|b| b.is_ascii_digit()) && lookup(ai).is_some()).then_some(ai)
+}
+
+/// Parse a transmitted element string, variable length values ended by GS
+pub fn parse_raw(data: &str) -> Result<Vec<Element>> {
+    // Lengths below count bytes, which only match characters in ASCII
+    if let Some(c) = data.chars().find(|c| !c.is_ascii()) {
+        return Err(anyhow!("{:?} is not allowed in GS1 data", c));
+    }
+    let mut elements = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let format = lookup_prefix(rest).ok_or_else(|| anyhow!("Unknown AI at {:?}", rest))?;
+        let (ai, after) = rest.split_at(format.ai_len);
+        let end = if format.min == format.max {
+            format.max.min(after.len())
+        } else {
+            after.find(GS).unwrap_or(after.len())
+        };
+        elements.push(Element { ai: ai.to_string(), value: after[..end].to_string() });
+        rest = after[end..].strip_prefix(GS).unwrap_or(&after[end..]);
+    }
+    validate(&elements)?;
+    Ok(elements)
+}
+
+/// Element string as transmitted: a GS after every element that does not
+/// have a predefined length, except the last one
+pub fn encode(elements: &[Element]) -> String {
+    let mut data = String::new();
+    for (i, element) in elements.iter().enumerate() {
+        data.push_str(&element.ai);
+        data.push_str(&element.value);
+        let predefined = PREDEFINED_LENGTH.contains(&&element.ai[..2]);
+        if !predefined && i + 1 < elements.len() {
+            data.push(GS);
+        }
+    }
+    data
+}
+
+/// Human readable form of the elements
+pub fn to_bracketed(elements: &[Element]) -> String {
+    elements.iter().map(|element| format!("({}){}", element.ai, element.value)).collect()
+}
+
+/// Check every value against the format of its AI
+pub fn validate(elements: &[Element]) -> Result<()> {
+    if elements.is_empty() {
+        return Err(anyhow!("No GS1 elements"));
+    }
+    for element in elements {
+        let format = lookup(&element.ai).ok_or_else(|| anyhow!("Unknown AI ({})", element.ai))?;
+        let value = &element.value;
+        let len = value.chars().count();
+        let context = || format!("AI ({}) {}", element.ai, format.title);
+        if len < format.min || len > format.max {
+            if format.min == format.max {
+                return Err(anyhow!("{}: expected {} characters, got {}", context(), format.max, len));
+            }
+            return Err(anyhow!("{}: expected {} to {} characters, got {}", context(), format.min, format.max, len));
+        }
+        match format.charset {
+            Charset::Numeric if !value.bytes().all(|b| b.is_ascii_digit()) => {
+                return Err(anyhow!("{}: {:?} is not numeric", context(), value));
+            }
+            Charset::Alphanumeric => {
+                if let Some(c) = value.chars().find(|c| !is_cset82(*c)) {
+                    return Err(anyhow!("{}: {:?} is not allowed", context(), c));
+                }
+            }
+            _ => {}
+        }
+        if format.check_digit {
+            let expected = crate::symbology::gs1_check_digit(&value[..len - 1]);
+            let actual = value.as_bytes()[len - 1] - b'0';
+            if actual != expected {
+                return Err(anyhow!("{}: check digit is {}, expected {}", context(), actual, expected));
+            }
+        }
+        if format.date {
+            let month: u32 = value[2..4].parse()?;
+            let day: u32 = value[4..6].parse()?;
+            // Day 00 means the last day of the month
+            if !(1..=12).contains(&month) || day > 31 {
+                return Err(anyhow!("{}: {} is not a YYMMDD date", context(), value));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_cset82(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!\"%&'()*+,-./:;<=>?_".contains(c)
+}
+
+/// Replace the <GS>, <RS>, <EOT>, <STX> and <ETX> placeholders, and <0xNN>
+/// for any other ASCII code, with the control characters, so they can be
+/// entered in a text field
+pub fn expand_placeholders(text: &str) -> String {
+    let text = text
+        .replace("<GS>", &GS.to_string())
+        .replace("<RS>", &RS.to_string())
+        .replace("<EOT>", &EOT.to_string())
+        .replace("<STX>", "\u{02}")
+        .replace("<ETX>", "\u{03}");
+
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text.as_str();
+    while let Some(start) = rest.find("<0x") {
+        expanded.push_str(&rest[..start]);
+        let code = rest
+            .get(start + 3..start + 6)
+            .filter(|tail| tail.ends_with('>'))
+            .and_then(|tail| u8::from_str_radix(&tail[..2], 16).ok())
+            .filter(u8::is_ascii);
+        match code {
+            Some(code) => {
+                expanded.push(code as char);
+                rest = &rest[start + 6..];
+            }
+            None => {
+                expanded.push('<');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bracketed_to_transmitted() {
+        let elements = parse_bracketed("(01)09501101530003(17)250100(10)AB-12(21)XYZ").unwrap();
+        assert_eq!(elements.len(), 4);
+        assert_eq!(elements[0].title(), "GTIN");
+        // No GS after the predefined lengths of 01 and 17, nor at the end
+        assert_eq!(encode(&elements), "01095011015300031725010010AB-12\u{1d}21XYZ");
+        assert_eq!(parse_raw(&encode(&elements)).unwrap(), elements);
+        assert_eq!(to_bracketed(&elements), "(01)09501101530003(17)250100(10)AB-12(21)XYZ");
+
+        // Values may contain brackets
+        let elements = parse_bracketed("(10)A(B)(3103)001250").unwrap();
+        assert_eq!(elements[0].value, "A(B)");
+        assert_eq!(elements[1].ai, "3103");
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let error = parse_bracketed("(01)09501101530004").unwrap_err();
+        assert!(error.to_string().contains("check digit is 4, expected 3"), "{}", error);
+        assert!(parse_bracketed("(17)251301").is_err(), "month 13");
+        assert!(parse_bracketed("(10)ABCDEFGHIJKLMNOPQRSTU").is_err(), "too long");
+        assert!(parse_bracketed("(10)AB CD").is_err(), "space is not in CSET 82");
+        assert!(parse_bracketed("(99)").is_err(), "empty value");
+        assert!(parse_bracketed("(23)1").is_err(), "unknown AI");
+        assert!(parse_bracketed("01095011015300031").is_err(), "no brackets");
+        assert!(parse_raw("0109501101530003\u{1d}99").is_err(), "empty value after GS");
+        assert!(parse_raw("01€€€€€€").is_err(), "non-ASCII in a fixed length value");
+        assert!(parse_raw("0€").is_err(), "non-ASCII in the AI");
+        let error = crate::symbology::Symbology::Gs1_128.validate("01€€€€€€").unwrap_err();
+        assert!(error.to_string().contains("'€' is not allowed"), "{}", error);
+    }
+
+    #[test]
+    fn test_placeholders() {
+        assert_eq!(expand_placeholders("[)><RS>06<GS>1P123<RS><EOT>"), "[)>\u{1e}06\u{1d}1P123\u{1e}\u{04}");
+        assert_eq!(expand_placeholders("<STX>A<ETX><0x1c><0x0D>"), "\u{02}A\u{03}\u{1c}\r");
+        // Not a placeholder: left as typed
+        assert_eq!(expand_placeholders("<0x80><0x1><0xZZ>€<0x"), "<0x80><0x1><0xZZ>€<0x");
+    }
+}
@@ -0,0 +1,224 @@
+use anyhow::{anyhow, Context, Result};
+use nix::sys::termios::{self, SetArg, SpecialCharacterIndices};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::bill_emulator::{DeviceFault, DeviceState, DeviceStatus, PollEvent};
+use crate::capture::{CaptureBroadcast, CaptureRecord, Direction};
+use crate::control::Devices;
+use crate::essp_protocol::*;
+use crate::event_loop::ShutdownHandle;
+use crate::scenario::{Scenario, ScenarioResult};
+use crate::serial_bridge::SerialBridge;
+use crate::sniffer::open_serial_port;
+
+/// Emulator running in-process for integration tests.
+///
+/// Starts a `SerialBridge` with the default devices on a background thread.
+/// Point the code under test at `slave_path()`, or talk to it directly with
+/// `connect()`. The bridge is shut down and the PTY closed on drop.
+pub struct EmulatorHarness {
+    slave_path: String,
+    devices: Devices,
+    shutdown: ShutdownHandle,
+    frames: Receiver<CaptureRecord>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl EmulatorHarness {
+    pub fn start() -> Result<Self> {
+        let mut bridge = SerialBridge::new()?;
+        let events = CaptureBroadcast::new();
+        let frames = events.subscribe();
+        bridge.add_capture(Box::new(events));
+
+        let slave_path = bridge.slave_path().to_string();
+        let devices = bridge.get_devices();
+        let shutdown = bridge.shutdown_handle();
+        let thread = thread::spawn(move || bridge.run());
+
+        Ok(Self {
+            slave_path,
+            devices,
+            shutdown,
+            frames,
+            thread: Some(thread),
+        })
+    }
+
+    /// Serial port to hand to the host under test
+    pub fn slave_path(&self) -> &str {
+        &self.slave_path
+    }
+
+    /// Devices shared with the bridge, for anything the helpers below do not cover
+    pub fn devices(&self) -> Devices {
+        self.devices.clone()
+    }
+
+    /// Open the slave side as an eSSP host
+    pub fn connect(&self) -> Result<TestHost> {
+        TestHost::open(&self.slave_path)
+    }
+
+    fn with_device<T>(&self, address: u8, f: impl FnOnce(&mut DeviceState) -> T) -> Result<T> {
+        let mut devices = self.devices.lock().unwrap();
+        let device = devices
+            .get_mut(&address)
+            .ok_or_else(|| anyhow!("No device at address 0x{:02X}", address))?;
+        Ok(f(device))
+    }
+
+    /// Insert a note (value in cents), failing if the device did not accept it
+    pub fn insert_note(&self, address: u8, value: u32) -> Result<()> {
+        if !self.with_device(address, |device| device.insert_note(value))? {
+            return Err(anyhow!("Device 0x{:02X} did not accept a {} cent note", address, value));
+        }
+        Ok(())
+    }
+
+    /// Insert coins (value in cents), failing if the device is disabled
+    pub fn insert_coins(&self, address: u8, value: u32) -> Result<()> {
+        if !self.with_device(address, |device| device.insert_coins(value))? {
+            return Err(anyhow!("Device 0x{:02X} is disabled", address));
+        }
+        Ok(())
+    }
+
+    pub fn inject_fault(&self, address: u8, fault: DeviceFault) -> Result<()> {
+        self.with_device(address, |device| device.inject_fault(fault))
+    }
+
+    pub fn status(&self, address: u8) -> Result<DeviceStatus> {
+        self.with_device(address, |device| device.status())
+    }
+
+    /// Wait until the host sends `command` to any device. Frames seen since
+    /// the harness started count, so calling this after the command was sent
+    /// does not race.
+    pub fn wait_for_command(&self, command: u8, timeout: Duration) -> Result<CaptureRecord> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.frames.recv_timeout(remaining) {
+                Ok(record)
+                    if record.direction == Direction::HostToDevice && record.data.first() == Some(&command) =>
+                {
+                    return Ok(record)
+                }
+                Ok(_) => continue,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(anyhow!("Host did not send {} within {:?}", command_name(command), timeout))
+                }
+                Err(RecvTimeoutError::Disconnected) => return Err(anyhow!("Serial bridge stopped")),
+            }
+        }
+    }
+
+    /// Run a scenario against the frames seen by this harness
+    pub fn run_scenario(&self, scenario: &Scenario) -> ScenarioResult {
+        scenario.run(&self.devices, &self.frames)
+    }
+}
+
+impl Drop for EmulatorHarness {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+        if let Some(thread) = self.thread.take() {
+            match thread.join() {
+                Ok(Err(e)) => eprintln!("Serial bridge failed: {}", e),
+                Err(_) => eprintln!("Serial bridge thread panicked"),
+                Ok(Ok(())) => {}
+            }
+        }
+    }
+}
+
+/// Minimal eSSP host for tests: frames commands, tracks the sequence flag per
+/// address and reads back the response.
+pub struct TestHost {
+    port: File,
+    scanner: FrameScanner,
+    /// Frames received but not read yet
+    received: VecDeque<EsspPacket>,
+    /// Sequence flag of the next command per address
+    next_flag: HashMap<u8, bool>,
+    pub timeout: Duration,
+}
+
+impl TestHost {
+    pub fn open(path: &str) -> Result<Self> {
+        let port = open_serial_port(path, 9600)?;
+        // Reads return after 100 ms without data so that timeouts can be enforced
+        let mut tio = termios::tcgetattr(&port)?;
+        tio.control_chars[SpecialCharacterIndices::VMIN as usize] = 0;
+        tio.control_chars[SpecialCharacterIndices::VTIME as usize] = 1;
+        termios::tcsetattr(&port, SetArg::TCSANOW, &tio)?;
+
+        Ok(Self {
+            port,
+            scanner: FrameScanner::new(),
+            received: VecDeque::new(),
+            next_flag: HashMap::new(),
+            timeout: Duration::from_secs(2),
+        })
+    }
+
+    /// SYNC resets the sequence: it is sent with the flag set and the next
+    /// command goes out with the flag cleared
+    pub fn sync(&mut self, address: u8) -> Result<EsspPacket> {
+        self.next_flag.insert(address, true);
+        self.command(address, &[CMD_SYNC])
+    }
+
+    /// Send a command with the next sequence flag and return the response
+    pub fn command(&mut self, address: u8, data: &[u8]) -> Result<EsspPacket> {
+        let flag = self.next_flag.get(&address).copied().unwrap_or(true);
+        self.next_flag.insert(address, !flag);
+        self.transact(address | if flag { 0x80 } else { 0x00 }, data)
+    }
+
+    /// Send a command with an explicit SEQ/ADDR byte, e.g. to retransmit
+    pub fn transact(&mut self, sequence: u8, data: &[u8]) -> Result<EsspPacket> {
+        self.send_raw(&EsspPacket::new(sequence, data.to_vec()).to_bytes())?;
+        self.read_frame()
+    }
+
+    pub fn send_raw(&mut self, bytes: &[u8]) -> Result<()> {
+        self.port.write_all(bytes).context("Failed to write to the emulator")
+    }
+
+    /// Next frame from the emulator, garbage in between is skipped
+    pub fn read_frame(&mut self) -> Result<EsspPacket> {
+        let deadline = Instant::now() + self.timeout;
+        let mut buf = [0u8; 256];
+        loop {
+            if let Some(packet) = self.received.pop_front() {
+                return Ok(packet);
+            }
+            if Instant::now() >= deadline {
+                return Err(anyhow!("No response within {:?}", self.timeout));
+            }
+            let n = self.port.read(&mut buf).context("Failed to read from the emulator")?;
+            for item in self.scanner.push(&buf[..n]) {
+                if let ScanItem::Frame(packet, _) = item {
+                    self.received.push_back(packet);
+                }
+            }
+        }
+    }
+
+    /// POLL `address` and return its events, failing on a non-OK status
+    pub fn poll(&mut self, address: u8) -> Result<Vec<PollEvent>> {
+        let response = self.command(address, &[CMD_POLL])?;
+        match response.data.split_first() {
+            Some((&RESPONSE_OK, events)) => PollEvent::parse_poll_data(events)
+                .ok_or_else(|| anyhow!("Malformed POLL response {:02X?}", response.data)),
+            _ => Err(anyhow!("POLL failed: {:02X?}", response.data)),
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::device::DeviceIdentity;
+use crate::symbology::Symbology;
+
+const UHID_PATH: &str = "/dev/uhid";
+
+// struct uhid_event from <linux/uhid.h>: a u32 type followed by the largest
+// request, uhid_create2_req (name, phys, uniq, rd_size, bus, vendor, product,
+// version, country, rd_data)
+const UHID_DESTROY: u32 = 1;
+const UHID_CREATE2: u32 = 11;
+const UHID_INPUT2: u32 = 12;
+const UHID_DATA_MAX: usize = 4096;
+const UHID_EVENT_SIZE: usize = 4 + 128 + 64 + 64 + 2 + 2 + 4 * 4 + UHID_DATA_MAX;
+
+/// Report ID of the Scanned Data Report
+pub const SCANNED_DATA_REPORT_ID: u8 = 0x02;
+/// Decoded Data bytes in one report, longer scans continue in the next ones
+pub const DECODED_DATA_LEN: usize = 56;
+/// Report ID, byte count, 3 symbology identifier bytes, data, continued flag
+pub const SCANNED_DATA_REPORT_LEN: usize = 1 + 1 + 3 + DECODED_DATA_LEN + 1;
+
+/// Barcode Scanner (usage page 0x8C) application collection with a Scanned
+/// Data Report:
+///
+/// | Byte  | Usage                                      |
+/// |-------|--------------------------------------------|
+/// | 0     | Report ID 2                                |
+/// | 1     | Byte Count (Generic Desktop 0x3B)          |
+/// | 2-4   | Symbology Identifier 1-3 (0xFB-0xFD)       |
+/// | 5-60  | Decoded Data (0xFE), buffered bytes        |
+/// | 61    | bit 0: Decode Data Continued (0xFF)        |
+pub const REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x8C, // Usage Page (Barcode Scanner)
+    0x09, 0x02, // Usage (Barcode Scanner)
+    0xA1, 0x01, // Collection (Application)
+    0x09, 0x12, //   Usage (Scanned Data Report)
+    0xA1, 0x02, //   Collection (Logical)
+    0x85, SCANNED_DATA_REPORT_ID, // Report ID (2)
+    0x15, 0x00, //     Logical Minimum (0)
+    0x26, 0xFF, 0x00, // Logical Maximum (255)
+    0x75, 0x08, //     Report Size (8)
+    0x05, 0x01, //     Usage Page (Generic Desktop)
+    0x09, 0x3B, //     Usage (Byte Count)
+    0x95, 0x01, //     Report Count (1)
+    0x81, 0x02, //     Input (Data, Var, Abs)
+    0x05, 0x8C, //     Usage Page (Barcode Scanner)
+    0x09, 0xFB, //     Usage (Symbology Identifier 1)
+    0x09, 0xFC, //     Usage (Symbology Identifier 2)
+    0x09, 0xFD, //     Usage (Symbology Identifier 3)
+    0x95, 0x03, //     Report Count (3)
+    0x81, 0x02, //     Input (Data, Var, Abs)
+    0x09, 0xFE, //     Usage (Decoded Data)
+    0x95, DECODED_DATA_LEN as u8, // Report Count (56)
+    0x82, 0x02, 0x01, // Input (Data, Var, Abs, Buffered Bytes)
+    0x09, 0xFF, //     Usage (Decode Data Continued)
+    0x25, 0x01, //     Logical Maximum (1)
+    0x75, 0x01, //     Report Size (1)
+    0x95, 0x01, //     Report Count (1)
+    0x81, 0x02, //     Input (Data, Var, Abs)
+    0x75, 0x07, //     Report Size (7)
+    0x81, 0x03, //     Input (Const, Var, Abs)
+    0xC0, //         End Collection
+    0xC0, //       End Collection
+];
+
+/// Barcode scanner presented as a HID POS device through /dev/uhid: scans
+/// reach the host as raw data with their symbology, without a keyboard layout
+pub struct HidPosScanner {
+    file: File,
+    identity: DeviceIdentity,
+    hidraw_path: String,
+}
+
+impl HidPosScanner {
+    pub fn new(identity: &DeviceIdentity) -> Result<Self> {
+        identity.validate()?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(UHID_PATH)
+            .with_context(|| format!("Failed to open {}", UHID_PATH))?;
+        file.write_all(&create_event(identity, REPORT_DESCRIPTOR))
+            .context("Failed to create HID POS device")?;
+
+        // Give the system time to register the device
+        thread::sleep(Duration::from_millis(100));
+        let hidraw_path = find_hidraw(identity).unwrap_or_else(|| "Unknown".to_string());
+
+        Ok(Self { file, identity: identity.clone(), hidraw_path })
+    }
+
+    pub fn identity(&self) -> &DeviceIdentity {
+        &self.identity
+    }
+
+    /// /dev/hidrawN of the device
+    pub fn hidraw_path(&self) -> &str {
+        &self.hidraw_path
+    }
+
+    /// Send `data` as Scanned Data Reports. GS1 data in the "(01)…" form is
+    /// converted to an element string first. Returns the number of reports
+    /// and the time it took to send them.
+    pub fn send_scan(&mut self, data: &str, symbology: Option<Symbology>) -> Result<(usize, Duration)> {
+        let start = Instant::now();
+        let (data, aim_id) = match symbology {
+            Some(symbology) => (symbology.encode(data)?, symbology.aim_id()),
+            None => (data.to_string(), ""),
+        };
+        let reports = scanned_data_reports(aim_id, data.as_bytes());
+        for report in &reports {
+            self.file
+                .write_all(&input_event(report))
+                .context("Failed to send scanned data report")?;
+        }
+        Ok((reports.len(), start.elapsed()))
+    }
+}
+
+impl Drop for HidPosScanner {
+    fn drop(&mut self) {
+        let mut event = vec![0u8; UHID_EVENT_SIZE];
+        event[..4].copy_from_slice(&UHID_DESTROY.to_ne_bytes());
+        let _ = self.file.write_all(&event);
+    }
+}
+
+/// UHID_CREATE2 event for a device with `descriptor`
+fn create_event(identity: &DeviceIdentity, descriptor: &[u8]) -> Vec<u8> {
+    let mut event = vec![0u8; UHID_EVENT_SIZE];
+    event[..4].copy_from_slice(&UHID_CREATE2.to_ne_bytes());
+    let request = &mut event[4..];
+    let name = identity.name.as_bytes();
+    request[..name.len()].copy_from_slice(name);
+    let rest = &mut request[256..];
+    rest[0..2].copy_from_slice(&(descriptor.len() as u16).to_ne_bytes());
+    rest[2..4].copy_from_slice(&identity.bus.bus_type().0.to_ne_bytes());
+    rest[4..8].copy_from_slice(&(identity.vendor_id as u32).to_ne_bytes());
+    rest[8..12].copy_from_slice(&(identity.product_id as u32).to_ne_bytes());
+    rest[12..16].copy_from_slice(&(identity.version as u32).to_ne_bytes());
+    // Country code 0: not localized
+    rest[20..20 + descriptor.len()].copy_from_slice(descriptor);
+    event
+}
+
+/// UHID_INPUT2 event carrying one input report
+fn input_event(report: &[u8]) -> Vec<u8> {
+    let mut event = vec![0u8; UHID_EVENT_SIZE];
+    event[..4].copy_from_slice(&UHID_INPUT2.to_ne_bytes());
+    event[4..6].copy_from_slice(&(report.len() as u16).to_ne_bytes());
+    event[6..6 + report.len()].copy_from_slice(report);
+    event
+}
+
+/// Scanned Data Reports for `data`, split in chunks of 56 bytes with Decode
+/// Data Continued set on all but the last one
+pub fn scanned_data_reports(aim_id: &str, data: &[u8]) -> Vec<[u8; SCANNED_DATA_REPORT_LEN]> {
+    let chunks: Vec<&[u8]> = if data.is_empty() {
+        vec![&[]]
+    } else {
+        data.chunks(DECODED_DATA_LEN).collect()
+    };
+    let last = chunks.len() - 1;
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut report = [0u8; SCANNED_DATA_REPORT_LEN];
+            report[0] = SCANNED_DATA_REPORT_ID;
+            report[1] = chunk.len() as u8;
+            for (byte, id) in report[2..5].iter_mut().zip(aim_id.bytes()) {
+                *byte = id;
+            }
+            report[5..5 + chunk.len()].copy_from_slice(chunk);
+            report[SCANNED_DATA_REPORT_LEN - 1] = (i != last) as u8;
+            report
+        })
+        .collect()
+}
+
+/// The hidraw node the kernel created for `identity`
+fn find_hidraw(identity: &DeviceIdentity) -> Option<String> {
+    let hid_id = format!(
+        "HID_ID={:04X}:{:08X}:{:08X}",
+        identity.bus.bus_type().0,
+        identity.vendor_id,
+        identity.product_id
+    );
+    let hid_name = format!("HID_NAME={}", identity.name);
+    std::fs::read_dir("/sys/class/hidraw")
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let uevent = std::fs::read_to_string(entry.path().join("device/uevent")).unwrap_or_default();
+            uevent.lines().any(|line| line == hid_id) && uevent.lines().any(|line| line == hid_name)
+        })
+        .map(|entry| PathBuf::from("/dev").join(entry.file_name()).display().to_string())
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scanned_data_reports() {
+        let reports = scanned_data_reports("]E0", b"4006381333931");
+        assert_eq!(reports.len(), 1);
+        let report = &reports[0];
+        assert_eq!(&report[..5], &[0x02, 13, b']', b'E', b'0']);
+        assert_eq!(&report[5..18], b"4006381333931");
+        assert!(report[18..].iter().all(|&b| b == 0));
+
+        // 120 bytes: 56 + 56 + 8, continued on all but the last
+        let data: Vec<u8> = (0..120).collect();
+        let reports = scanned_data_reports("]Q1", &data);
+        assert_eq!(reports.iter().map(|r| r[1]).collect::<Vec<_>>(), vec![56, 56, 8]);
+        assert_eq!(reports.iter().map(|r| r[61]).collect::<Vec<_>>(), vec![1, 1, 0]);
+        let joined: Vec<u8> = reports.iter().flat_map(|r| r[5..5 + r[1] as usize].to_vec()).collect();
+        assert_eq!(joined, data);
+
+        assert_eq!(scanned_data_reports("", b"").len(), 1);
+    }
+
+    #[test]
+    fn test_uhid_events() {
+        let identity = DeviceIdentity { name: "POS".to_string(), vendor_id: 0x0C2E, ..Default::default() };
+        let event = create_event(&identity, REPORT_DESCRIPTOR);
+        assert_eq!(event.len(), 4376);
+        assert_eq!(u32::from_ne_bytes(event[..4].try_into().unwrap()), UHID_CREATE2);
+        assert_eq!(&event[4..8], b"POS\0");
+        assert_eq!(u16::from_ne_bytes([event[260], event[261]]) as usize, REPORT_DESCRIPTOR.len());
+        assert_eq!(u16::from_ne_bytes([event[262], event[263]]), 0x03, "BUS_USB");
+        assert_eq!(u32::from_ne_bytes(event[264..268].try_into().unwrap()), 0x0C2E);
+        assert_eq!(&event[280..280 + REPORT_DESCRIPTOR.len()], REPORT_DESCRIPTOR);
+
+        let event = input_event(&[0x02, 0xAA]);
+        assert_eq!(u32::from_ne_bytes(event[..4].try_into().unwrap()), UHID_INPUT2);
+        assert_eq!(&event[4..8], &[2, 0, 0x02, 0xAA]);
+    }
+
+    #[test]
+    fn test_descriptor_report_length() {
+        // Sum Report Size × Report Count over the Input items
+        let (mut size, mut count, mut bits) = (0u32, 0u32, 0u32);
+        let mut i = 0;
+        while i < REPORT_DESCRIPTOR.len() {
+            let prefix = REPORT_DESCRIPTOR[i];
+            let len = match prefix & 0x03 {
+                3 => 4,
+                n => n as usize,
+            };
+            let value = REPORT_DESCRIPTOR[i + 1..i + 1 + len]
+                .iter()
+                .rev()
+                .fold(0u32, |acc, &b| acc << 8 | b as u32);
+            match prefix & 0xFC {
+                0x74 => size = value,
+                0x94 => count = value,
+                0x80 => bits += size * count,
+                _ => {}
+            }
+            i += 1 + len;
+        }
+        assert_eq!(1 + bits as usize / 8, SCANNED_DATA_REPORT_LEN);
+    }
+}
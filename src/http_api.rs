@@ -0,0 +1,351 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use tungstenite::{protocol::Role, Message, WebSocket};
+
+use crate::capture::{CaptureBroadcast, CaptureRecord};
+use crate::control::{execute, Devices, RpcError, INVALID_PARAMS, METHOD_NOT_FOUND};
+
+/// Upper bound on a request head; the API never needs more
+const MAX_HEAD_LEN: usize = 16 * 1024;
+const MAX_HEADERS: usize = 32;
+/// Largest accepted request body; API bodies are a few bytes of JSON
+const MAX_BODY_LEN: usize = 64 * 1024;
+/// A client that stops sending mid-request is dropped after this
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a WebSocket client waits for control frames between events
+const WS_POLL: Duration = Duration::from_millis(50);
+/// Embedded HTTP server exposing the emulated devices.
+///
+/// REST endpoints (values in cents, addresses as `0x10` or `16`):
+///   GET  /api/devices
+///   GET  /api/devices/{address}
+///   POST /api/devices/{address}/notes    {"value": 2000}
+///   POST /api/devices/{address}/coins    {"value": 25}
+///   POST /api/devices/{address}/levels   {"value": 500, "count": 10}
+///   POST /api/devices/{address}/faults   {"fault": "jam"}
+///   PUT  /api/devices/{address}/enabled  {"enabled": true}
+///
+/// `GET /ws` upgrades to a WebSocket streaming every captured frame as JSON.
+pub struct HttpServer {
+    addr: SocketAddr,
+}
+
+impl HttpServer {
+    /// Listen on `addr` (e.g. "127.0.0.1:8080") and serve on a background thread
+    pub fn start(addr: &str, devices: Devices, events: CaptureBroadcast) -> Result<Self> {
+        let listener = TcpListener::bind(addr).with_context(|| format!("Failed to start HTTP server on {}", addr))?;
+        let addr = listener.local_addr()?;
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let devices = devices.clone();
+                        let events = events.clone();
+                        thread::spawn(move || {
+                            if let Err(e) = serve_connection(stream, &devices, events) {
+                                eprintln!("HTTP client error: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => eprintln!("HTTP accept failed: {}", e),
+                }
+            }
+        });
+
+        Ok(Self { addr })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+/// A parsed request head plus whatever was read past it
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    rest: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn read_head(stream: &mut TcpStream) -> Result<Request> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            anyhow::bail!("connection closed before the request head");
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut parsed = httparse::Request::new(&mut headers);
+        if let httparse::Status::Complete(len) = parsed.parse(&buffer)? {
+            return Ok(Request {
+                method: parsed.method.unwrap_or_default().to_string(),
+                path: parsed.path.unwrap_or_default().split('?').next().unwrap_or("").to_string(),
+                headers: parsed
+                    .headers
+                    .iter()
+                    .map(|h| (h.name.to_string(), String::from_utf8_lossy(h.value).into_owned()))
+                    .collect(),
+                rest: buffer[len..].to_vec(),
+            });
+        }
+        if buffer.len() > MAX_HEAD_LEN {
+            anyhow::bail!("request head too large");
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        101 => "Switching Protocols",
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        _ => "",
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: u16, extra_headers: &str, body: &Value) -> std::io::Result<()> {
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n{}",
+        status,
+        reason(status),
+        body.len(),
+        extra_headers,
+        body
+    )?;
+    stream.flush()
+}
+
+fn error_status(error: &RpcError) -> u16 {
+    match error.code {
+        INVALID_PARAMS => 400,
+        METHOD_NOT_FOUND => 404,
+        _ => 409,
+    }
+}
+
+/// Map a REST route onto a control method and its params
+fn route(method: &str, path: &str, body: Value) -> Result<(&'static str, Value), RpcError> {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let with_address = |address: &str, mut params: Value| {
+        if !params.is_object() {
+            params = json!({});
+        }
+        params["address"] = json!(address);
+        params
+    };
+
+    match (method, segments.as_slice()) {
+        ("GET", ["api", "devices"]) => Ok(("list_devices", Value::Null)),
+        ("GET", ["api", "devices", address]) => Ok(("get_state", with_address(address, Value::Null))),
+        ("POST", ["api", "devices", address, "notes"]) => Ok(("insert_note", with_address(address, body))),
+        ("POST", ["api", "devices", address, "coins"]) => Ok(("insert_coin", with_address(address, body))),
+        ("POST", ["api", "devices", address, "levels"]) => Ok(("set_level", with_address(address, body))),
+        ("POST", ["api", "devices", address, "faults"]) => Ok(("inject_fault", with_address(address, body))),
+        ("PUT", ["api", "devices", address, "enabled"]) => Ok(("set_enabled", with_address(address, body))),
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("no route for {} {}", method, path))),
+    }
+}
+
+fn serve_connection(mut stream: TcpStream, devices: &Devices, events: CaptureBroadcast) -> Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let request = read_head(&mut stream)?;
+    if request.path == "/ws" {
+        return stream_events(stream, request, events);
+    }
+
+    let length: usize = match request.header("Content-Length").map(|v| v.trim().parse()) {
+        None => 0,
+        Some(Ok(length)) => length,
+        Some(Err(_)) => {
+            respond(&mut stream, 400, "", &json!({ "error": "invalid Content-Length" }))?;
+            return Ok(());
+        }
+    };
+    if length > MAX_BODY_LEN {
+        let error = format!("body of {} bytes is over the {} byte limit", length, MAX_BODY_LEN);
+        respond(&mut stream, 413, "", &json!({ "error": error }))?;
+        return Ok(());
+    }
+    let mut body = request.rest.clone();
+    body.truncate(length);
+    if body.len() < length {
+        let mut remaining = vec![0u8; length - body.len()];
+        stream.read_exact(&mut remaining)?;
+        body.extend_from_slice(&remaining);
+    }
+
+    let body = if body.iter().all(u8::is_ascii_whitespace) {
+        Value::Null
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(body) => body,
+            Err(e) => {
+                respond(&mut stream, 400, "", &json!({ "error": format!("invalid JSON body: {}", e) }))?;
+                return Ok(());
+            }
+        }
+    };
+
+    let result = route(&request.method, &request.path, body)
+        .and_then(|(method, params)| execute(devices, method, &params));
+    match result {
+        Ok(value) => respond(&mut stream, 200, "", &value)?,
+        Err(error) => respond(&mut stream, error_status(&error), "", &json!({ "error": error.message }))?,
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+    Ok(())
+}
+
+fn stream_events(mut stream: TcpStream, request: Request, events: CaptureBroadcast) -> Result<()> {
+    if request.method != "GET" {
+        respond(&mut stream, 405, "Allow: GET\r\n", &json!({ "error": "WebSocket upgrades must use GET" }))?;
+        return Ok(());
+    }
+    let Some(key) = request.header("Sec-WebSocket-Key") else {
+        respond(&mut stream, 400, "", &json!({ "error": "expected a WebSocket upgrade" }))?;
+        return Ok(());
+    };
+
+    let accept = tungstenite::handshake::derive_accept_key(key.as_bytes());
+    write!(
+        stream,
+        "HTTP/1.1 101 {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        reason(101),
+        accept
+    )?;
+    stream.flush()?;
+
+    // Reads time out so one thread can both forward events and answer
+    // the client's pings and close frames
+    stream.set_read_timeout(Some(Duration::from_millis(1)))?;
+    let mut socket = WebSocket::from_partially_read(stream, request.rest, Role::Server, None);
+    let receiver = events.subscribe();
+    let result = forward_events(&mut socket, &receiver);
+    // Dropping the receiver unsubscribes on the next broadcast
+    drop(receiver);
+    result
+}
+
+fn forward_events(socket: &mut WebSocket<TcpStream>, receiver: &Receiver<CaptureRecord>) -> Result<()> {
+    loop {
+        match receiver.recv_timeout(WS_POLL) {
+            Ok(record) => socket.send(Message::Text(json!(record).to_string()))?,
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+
+        // Pongs and the close reply are queued by read() and written by flush()
+        match socket.read() {
+            Ok(Message::Close(_)) => {
+                socket.flush()?;
+                return Ok(());
+            }
+            Ok(_) => socket.flush()?,
+            Err(tungstenite::Error::Io(e)) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                socket.flush()?
+            }
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{CaptureSink, Direction};
+    use crate::serial_bridge::default_devices;
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+
+    #[test]
+    fn test_routes() {
+        let (method, params) = route("POST", "/api/devices/0x10/coins", json!({ "value": 25 })).unwrap();
+        assert_eq!(method, "insert_coin");
+        assert_eq!(params, json!({ "address": "0x10", "value": 25 }));
+
+        let (method, _) = route("GET", "/api/devices/", Value::Null).unwrap();
+        assert_eq!(method, "list_devices");
+
+        assert!(route("DELETE", "/api/devices/0", Value::Null).is_err());
+    }
+
+    #[test]
+    fn test_oversized_body_is_refused() {
+        let server = HttpServer::start("127.0.0.1:0", Arc::new(Mutex::new(default_devices())), CaptureBroadcast::new()).unwrap();
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream
+            .write_all(b"POST /api/devices/0/notes HTTP/1.1\r\nHost: localhost\r\nContent-Length: 99999999999\r\n\r\n")
+            .unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).unwrap();
+        assert!(reply.starts_with("HTTP/1.1 413"), "{}", reply);
+    }
+
+    #[test]
+    fn test_websocket_control_frames() {
+        let events = CaptureBroadcast::new();
+        let server = HttpServer::start("127.0.0.1:0", Arc::new(Mutex::new(default_devices())), events.clone()).unwrap();
+
+        // Only GET may upgrade
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream.write_all(b"POST /ws HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).unwrap();
+        assert!(reply.starts_with("HTTP/1.1 405"), "{}", reply);
+
+        let url = format!("ws://{}/ws", server.addr());
+        let (mut socket, _) = tungstenite::client(url, TcpStream::connect(server.addr()).unwrap()).unwrap();
+
+        socket.send(Message::Ping(b"hello".to_vec())).unwrap();
+        assert_eq!(socket.read().unwrap(), Message::Pong(b"hello".to_vec()));
+
+        let mut broadcast = events.clone();
+        let record = CaptureRecord::undecodable(Direction::DeviceToHost, &[0x42], 0);
+        broadcast.record(&record).unwrap();
+        assert!(matches!(socket.read().unwrap(), Message::Text(_)));
+
+        // The server must answer the close handshake rather than drop the TCP connection
+        socket.close(None).unwrap();
+        loop {
+            match socket.read() {
+                Ok(_) => continue,
+                Err(tungstenite::Error::ConnectionClosed) => break,
+                Err(e) => panic!("close handshake not answered: {}", e),
+            }
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while events.subscriber_count() > 0 {
+            assert!(Instant::now() < deadline, "subscriber outlived its WebSocket");
+            broadcast.record(&record).unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use anyhow::{anyhow, Context, Result};
+use evdev::Key;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Layouts shipped with virtusdev: (id, table)
+const BUILTIN_LAYOUTS: &[(&str, &str)] = &[
+    ("us", include_str!("../layouts/us.layout")),
+    ("gb", include_str!("../layouts/gb.layout")),
+    ("br-abnt2", include_str!("../layouts/br-abnt2.layout")),
+    ("de", include_str!("../layouts/de.layout")),
+    ("fr-azerty", include_str!("../layouts/fr-azerty.layout")),
+];
+
+/// Characters typed by dead keys when followed by space
+const DEAD_KEYS: &[(&str, char)] = &[
+    ("dead_grave", '`'),
+    ("dead_acute", '´'),
+    ("dead_circumflex", '^'),
+    ("dead_tilde", '~'),
+    ("dead_diaeresis", '¨'),
+    ("dead_cedilla", '¸'),
+    ("dead_ogonek", '˛'),
+];
+
+/// One key press with the modifiers held around it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyStroke {
+    pub key: Key,
+    pub shift: bool,
+    pub altgr: bool,
+}
+
+impl KeyStroke {
+    pub fn new(key: Key) -> Self {
+        Self { key, shift: false, altgr: false }
+    }
+}
+
+/// Maps characters to the key strokes that type them on a host with a
+/// given keyboard layout.
+///
+/// Tables are XKB-style text, one row per key: the evdev key name followed by
+/// the symbols of the levels base, Shift, AltGr and Shift+AltGr (see `layouts/`).
+#[derive(Debug, Clone)]
+pub struct KeyboardLayout {
+    id: String,
+    name: String,
+    strokes: HashMap<char, Vec<KeyStroke>>,
+}
+
+impl KeyboardLayout {
+    /// Ids of the built-in layouts
+    pub fn builtin_ids() -> impl Iterator<Item = &'static str> {
+        BUILTIN_LAYOUTS.iter().map(|(id, _)| *id)
+    }
+
+    /// Built-in layout such as "us", "br-abnt2" or "de"
+    pub fn builtin(id: &str) -> Option<Self> {
+        let (id, table) = BUILTIN_LAYOUTS.iter().find(|(builtin, _)| *builtin == id)?;
+        Some(Self::parse(id, table).expect("built-in layout tables are valid"))
+    }
+
+    /// Load a layout table, the id is the file name without extension
+    pub fn load(path: &Path) -> Result<Self> {
+        let table = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read layout {}", path.display()))?;
+        let id = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("custom");
+        Self::parse(id, &table).with_context(|| format!("Invalid layout {}", path.display()))
+    }
+
+    /// Built-in layouts followed by the *.layout files in `dir`, if it exists
+    pub fn available(dir: &Path) -> Vec<Self> {
+        let mut layouts: Vec<Self> = Self::builtin_ids().filter_map(Self::builtin).collect();
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "layout"))
+            .collect();
+        paths.sort();
+        for path in paths {
+            match Self::load(&path) {
+                Ok(layout) => layouts.push(layout),
+                Err(e) => eprintln!("Skipping layout: {:#}", e),
+            }
+        }
+        layouts
+    }
+
+    pub fn parse(id: &str, table: &str) -> Result<Self> {
+        let mut layout = Self {
+            id: id.to_string(),
+            name: id.to_string(),
+            strokes: HashMap::new(),
+        };
+        // Characters reachable through a dead key and space, used only when
+        // no key types them directly
+        let mut dead = HashMap::new();
+
+        for (number, line) in table.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix("name") {
+                if let Some(name) = name.trim_start().strip_prefix('=') {
+                    layout.name = name.trim().to_string();
+                    continue;
+                }
+            }
+
+            let mut tokens = line.split_whitespace();
+            let key_name = tokens.next().unwrap_or_default();
+            let key = Key::from_str(key_name)
+                .map_err(|_| anyhow!("Line {}: unknown key '{}'", number + 1, key_name))?;
+
+            for (level, token) in tokens.enumerate() {
+                let stroke = match level {
+                    0 => KeyStroke::new(key),
+                    1 => KeyStroke { shift: true, ..KeyStroke::new(key) },
+                    2 => KeyStroke { altgr: true, ..KeyStroke::new(key) },
+                    3 => KeyStroke { key, shift: true, altgr: true },
+                    _ => return Err(anyhow!("Line {}: more than 4 levels", number + 1)),
+                };
+                match parse_symbol(token).map_err(|e| anyhow!("Line {}: {}", number + 1, e))? {
+                    Symbol::Char(c) => layout.insert(c, vec![stroke]),
+                    Symbol::Dead(c) => {
+                        dead.entry(c).or_insert(stroke);
+                    }
+                    Symbol::None => {}
+                }
+            }
+        }
+
+        let space = layout
+            .strokes
+            .get(&' ')
+            .cloned()
+            .ok_or_else(|| anyhow!("Layout has no space key"))?;
+        for (c, stroke) in dead {
+            layout.strokes.entry(c).or_insert_with(|| [&[stroke], space.as_slice()].concat());
+        }
+        // Control characters are the same on every layout
+        layout.insert('\n', vec![KeyStroke::new(Key::KEY_ENTER)]);
+        layout.insert('\t', vec![KeyStroke::new(Key::KEY_TAB)]);
+        Ok(layout)
+    }
+
+    /// Keep the cheapest way to type `c`: fewest modifiers
+    fn insert(&mut self, c: char, strokes: Vec<KeyStroke>) {
+        let cost = |strokes: &[KeyStroke]| -> usize {
+            strokes.iter().map(|s| 1 + s.shift as usize + 2 * s.altgr as usize).sum()
+        };
+        match self.strokes.get(&c) {
+            Some(existing) if cost(existing) <= cost(&strokes) => {}
+            _ => {
+                self.strokes.insert(c, strokes);
+            }
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Key strokes that type `c`, or None if the layout cannot type it
+    pub fn keystrokes(&self, c: char) -> Option<&[KeyStroke]> {
+        self.strokes.get(&c).map(Vec::as_slice)
+    }
+}
+
+impl Default for KeyboardLayout {
+    fn default() -> Self {
+        Self::builtin("us").unwrap()
+    }
+}
+
+enum Symbol {
+    Char(char),
+    Dead(char),
+    None,
+}
+
+fn parse_symbol(token: &str) -> Result<Symbol> {
+    if token == "NoSymbol" {
+        return Ok(Symbol::None);
+    }
+    if token == "space" {
+        return Ok(Symbol::Char(' '));
+    }
+    if token.starts_with("dead_") {
+        return DEAD_KEYS
+            .iter()
+            .find(|(name, _)| *name == token)
+            .map(|(_, c)| Symbol::Dead(*c))
+            .ok_or_else(|| anyhow!("unknown dead key '{}'", token));
+    }
+    let mut chars = token.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(Symbol::Char(c)),
+        _ => Err(anyhow!("invalid symbol '{}'", token)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strokes(layout: &KeyboardLayout, c: char) -> Vec<(Key, bool, bool)> {
+        layout
+            .keystrokes(c)
+            .unwrap_or_else(|| panic!("{} cannot type {:?}", layout.id(), c))
+            .iter()
+            .map(|s| (s.key, s.shift, s.altgr))
+            .collect()
+    }
+
+    #[test]
+    fn test_builtin_layouts_type_printable_ascii() {
+        for id in KeyboardLayout::builtin_ids() {
+            let layout = KeyboardLayout::builtin(id).unwrap();
+            for c in (' '..='~').chain(['\n', '\t']) {
+                assert!(layout.keystrokes(c).is_some(), "{} cannot type {:?}", id, c);
+            }
+        }
+    }
+
+    #[test]
+    fn test_layout_specific_keys() {
+        let us = KeyboardLayout::default();
+        assert_eq!(strokes(&us, '!'), vec![(Key::KEY_1, true, false)]);
+        assert_eq!(strokes(&us, 'a'), vec![(Key::KEY_A, false, false)]);
+
+        let de = KeyboardLayout::builtin("de").unwrap();
+        assert_eq!(strokes(&de, 'z'), vec![(Key::KEY_Y, false, false)]);
+        assert_eq!(strokes(&de, '@'), vec![(Key::KEY_Q, false, true)]);
+        // Dead key followed by space
+        assert_eq!(strokes(&de, '^'), vec![(Key::KEY_GRAVE, false, false), (Key::KEY_SPACE, false, false)]);
+
+        let fr = KeyboardLayout::builtin("fr-azerty").unwrap();
+        assert_eq!(strokes(&fr, '1'), vec![(Key::KEY_1, true, false)]);
+        assert_eq!(strokes(&fr, 'a'), vec![(Key::KEY_Q, false, false)]);
+
+        let br = KeyboardLayout::builtin("br-abnt2").unwrap();
+        assert_eq!(strokes(&br, '/'), vec![(Key::KEY_RO, false, false)]);
+        assert_eq!(strokes(&br, 'ç'), vec![(Key::KEY_SEMICOLON, false, false)]);
+        assert_eq!(strokes(&br, '~'), vec![(Key::KEY_APOSTROPHE, false, false), (Key::KEY_SPACE, false, false)]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(KeyboardLayout::parse("x", "KEY_SPACE space\nKEY_FOO a").is_err());
+        assert!(KeyboardLayout::parse("x", "KEY_SPACE space\nKEY_A ab").is_err());
+        assert!(KeyboardLayout::parse("x", "KEY_SPACE space\nKEY_A dead_foo").is_err());
+        assert!(KeyboardLayout::parse("x", "KEY_A a A").is_err(), "no space key");
+
+        let layout = KeyboardLayout::parse("x", "name = Test\nKEY_SPACE space\nKEY_A a NoSymbol b").unwrap();
+        assert_eq!(layout.name(), "Test");
+        assert!(layout.keystrokes('A').is_none());
+        assert_eq!(strokes(&layout, 'b'), vec![(Key::KEY_A, false, true)]);
+    }
+}
@@ -1,4 +1,23 @@
 pub mod essp_protocol;
 pub mod bill_emulator;
 pub mod serial_bridge;
+pub mod bridge_manager;
+pub mod event_loop;
+pub mod line_noise;
+mod rng;
+pub mod capture;
+pub mod replay;
+pub mod sniffer;
+pub mod control;
+pub mod http_api;
+pub mod scenario;
+pub mod repl;
+pub mod harness;
 pub mod device;
+pub mod keyboard_layout;
+pub mod scanner_config;
+pub mod symbology;
+pub mod gs1;
+pub mod timing;
+pub mod hid_pos;
+pub mod serial_scanner;
@@ -0,0 +1,304 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::essp_protocol::*;
+use crate::rng::Rng;
+
+/// Kind of line or protocol error injected into a response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    CorruptCrc,
+    DropResponse,
+    DuplicateResponse,
+    TruncateFrame,
+    StrayBytes,
+    WrongSequence,
+    WrongAddress,
+}
+
+impl FaultKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FaultKind::CorruptCrc => "corrupt-crc",
+            FaultKind::DropResponse => "drop",
+            FaultKind::DuplicateResponse => "duplicate",
+            FaultKind::TruncateFrame => "truncate",
+            FaultKind::StrayBytes => "stray",
+            FaultKind::WrongSequence => "wrong-seq",
+            FaultKind::WrongAddress => "wrong-addr",
+        }
+    }
+}
+
+impl fmt::Display for FaultKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for FaultKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "corrupt-crc" | "crc" => Ok(FaultKind::CorruptCrc),
+            "drop" => Ok(FaultKind::DropResponse),
+            "duplicate" | "dup" => Ok(FaultKind::DuplicateResponse),
+            "truncate" => Ok(FaultKind::TruncateFrame),
+            "stray" | "stray-bytes" => Ok(FaultKind::StrayBytes),
+            "wrong-seq" => Ok(FaultKind::WrongSequence),
+            "wrong-addr" => Ok(FaultKind::WrongAddress),
+            _ => Err(anyhow!("Unknown fault kind: {}", s)),
+        }
+    }
+}
+
+/// When a fault fires
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trigger {
+    /// Fire on each reply with the given probability (0.0 - 1.0)
+    Probability(f64),
+    /// Fire on the nth reply (1-based), optionally counting only replies to one command
+    Nth { command: Option<u8>, n: u32 },
+}
+
+/// A single fault injection rule
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultRule {
+    pub kind: FaultKind,
+    pub trigger: Trigger,
+}
+
+impl FromStr for FaultRule {
+    type Err = anyhow::Error;
+
+    /// Parse a rule from the command line syntax:
+    ///   `corrupt-crc:0.05`  - corrupt 5% of replies
+    ///   `drop@poll#5`       - drop the 5th POLL reply
+    ///   `duplicate@3`       - duplicate the 3rd reply of any command
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some((kind, probability)) = s.split_once(':') {
+            let probability: f64 = probability
+                .parse()
+                .map_err(|_| anyhow!("Invalid probability in fault rule: {}", s))?;
+            if !(0.0..=1.0).contains(&probability) {
+                return Err(anyhow!("Probability must be between 0 and 1: {}", s));
+            }
+            return Ok(Self {
+                kind: kind.parse()?,
+                trigger: Trigger::Probability(probability),
+            });
+        }
+
+        if let Some((kind, schedule)) = s.split_once('@') {
+            let (command, n) = match schedule.split_once('#') {
+                Some((name, n)) => {
+                    let code = command_code(name)
+                        .ok_or_else(|| anyhow!("Unknown command in fault rule: {}", name))?;
+                    (Some(code), n)
+                }
+                None => (None, schedule),
+            };
+            let n: u32 = n
+                .parse()
+                .map_err(|_| anyhow!("Invalid reply number in fault rule: {}", s))?;
+            if n == 0 {
+                return Err(anyhow!("Reply numbers start at 1: {}", s));
+            }
+            return Ok(Self {
+                kind: kind.parse()?,
+                trigger: Trigger::Nth { command, n },
+            });
+        }
+
+        Err(anyhow!(
+            "Invalid fault rule '{}': expected KIND:PROBABILITY or KIND@[COMMAND#]N",
+            s
+        ))
+    }
+}
+
+/// Applies fault rules to outgoing responses
+#[derive(Debug, Clone)]
+pub struct LineNoise {
+    rules: Vec<FaultRule>,
+    seed: u64,
+    rng: Rng,
+    replies: u32,
+    replies_by_command: HashMap<u8, u32>,
+}
+
+impl LineNoise {
+    pub fn new(rules: Vec<FaultRule>, seed: u64) -> Self {
+        Self {
+            rules,
+            seed,
+            rng: Rng::new(seed),
+            replies: 0,
+            replies_by_command: HashMap::new(),
+        }
+    }
+
+    /// Create with a seed taken from the clock
+    pub fn with_random_seed(rules: Vec<FaultRule>) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Self::new(rules, seed)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn rules(&self) -> &[FaultRule] {
+        &self.rules
+    }
+
+    /// Decide which faults fire for the reply to `cmd_code`
+    pub fn select(&mut self, cmd_code: u8) -> Vec<FaultKind> {
+        self.replies += 1;
+        let command_count = {
+            let count = self.replies_by_command.entry(cmd_code).or_insert(0);
+            *count += 1;
+            *count
+        };
+
+        let mut fired = Vec::new();
+        for rule in &self.rules {
+            let fire = match rule.trigger {
+                Trigger::Probability(p) => self.rng.next_f64() < p,
+                Trigger::Nth { command: None, n } => self.replies == n,
+                Trigger::Nth { command: Some(code), n } => code == cmd_code && command_count == n,
+            };
+            if fire && !fired.contains(&rule.kind) {
+                fired.push(rule.kind);
+            }
+        }
+        fired
+    }
+
+    /// Build the bytes to put on the wire for `response`, applying `faults`.
+    /// Returns an empty vector when the response is dropped.
+    pub fn apply(&mut self, response: &EsspPacket, faults: &[FaultKind]) -> Vec<u8> {
+        let mut packet = response.clone();
+
+        // Header faults are applied before framing so the CRC stays valid
+        if faults.contains(&FaultKind::WrongSequence) {
+            packet.sequence ^= 0x80;
+        }
+        if faults.contains(&FaultKind::WrongAddress) {
+            let address = (packet.sequence & 0x7F).wrapping_add(1) & 0x7F;
+            packet.sequence = (packet.sequence & 0x80) | address;
+        }
+
+        let mut frame = packet.to_bytes();
+
+        if faults.contains(&FaultKind::CorruptCrc) {
+            // Corrupt the CRC before stuffing so that the frame stays well formed
+            let mask = self.rng.range(1, 256) as u8;
+            if let Ok(mut body) = unstuff_bytes(&frame[1..]) {
+                let last = body.len() - 1;
+                body[last] ^= mask;
+                frame.truncate(1);
+                frame.extend(stuff_bytes(&body));
+            }
+        }
+        if faults.contains(&FaultKind::TruncateFrame) {
+            let keep = self.rng.range(1, frame.len());
+            frame.truncate(keep);
+        }
+        if faults.contains(&FaultKind::StrayBytes) {
+            let count = self.rng.range(1, 5);
+            let mut stray = Vec::with_capacity(count + frame.len());
+            for _ in 0..count {
+                let mut byte = self.rng.next_u64() as u8;
+                if byte == STX {
+                    byte = 0x00;
+                }
+                stray.push(byte);
+            }
+            stray.extend_from_slice(&frame);
+            frame = stray;
+        }
+        if faults.contains(&FaultKind::DuplicateResponse) {
+            frame.extend_from_within(..);
+        }
+        if faults.contains(&FaultKind::DropResponse) {
+            frame.clear();
+        }
+
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rules() {
+        let rule: FaultRule = "corrupt-crc:0.25".parse().unwrap();
+        assert_eq!(rule.kind, FaultKind::CorruptCrc);
+        assert_eq!(rule.trigger, Trigger::Probability(0.25));
+
+        let rule: FaultRule = "drop@poll#5".parse().unwrap();
+        assert_eq!(rule.trigger, Trigger::Nth { command: Some(CMD_POLL), n: 5 });
+
+        let rule: FaultRule = "duplicate@3".parse().unwrap();
+        assert_eq!(rule.trigger, Trigger::Nth { command: None, n: 3 });
+
+        assert!("drop@poll#0".parse::<FaultRule>().is_err());
+        assert!("drop:1.5".parse::<FaultRule>().is_err());
+        assert!("melt@1".parse::<FaultRule>().is_err());
+    }
+
+    #[test]
+    fn test_nth_command_schedule() {
+        let mut noise = LineNoise::new(vec!["drop@poll#2".parse().unwrap()], 1);
+        assert!(noise.select(CMD_POLL).is_empty());
+        assert!(noise.select(CMD_SYNC).is_empty());
+        assert_eq!(noise.select(CMD_POLL), vec![FaultKind::DropResponse]);
+        assert!(noise.select(CMD_POLL).is_empty());
+    }
+
+    #[test]
+    fn test_seed_is_reproducible() {
+        let rules = vec!["corrupt-crc:0.5".parse().unwrap(), "stray:0.5".parse().unwrap()];
+        let response = build_response(0x80, RESPONSE_OK, &[0x01, 0x02]);
+
+        let run = |seed| {
+            let mut noise = LineNoise::new(rules.clone(), seed);
+            (0..32)
+                .map(|_| {
+                    let faults = noise.select(CMD_POLL);
+                    noise.apply(&response, &faults)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(42), run(42));
+        assert_ne!(run(42), run(43));
+    }
+
+    #[test]
+    fn test_header_faults_keep_crc_valid() {
+        let mut noise = LineNoise::new(vec![], 0);
+        let response = build_response(0x80, RESPONSE_OK, &[]);
+
+        let bytes = noise.apply(&response, &[FaultKind::WrongSequence]);
+        let (packet, _) = EsspPacket::from_bytes(&bytes).unwrap();
+        assert_eq!(packet.sequence, 0x00);
+
+        let bytes = noise.apply(&response, &[FaultKind::WrongAddress]);
+        let (packet, _) = EsspPacket::from_bytes(&bytes).unwrap();
+        assert_eq!(packet.sequence, 0x81);
+
+        let bytes = noise.apply(&response, &[FaultKind::CorruptCrc]);
+        assert!(EsspPacket::from_bytes(&bytes).is_err());
+    }
+}
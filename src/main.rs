@@ -1,13 +1,21 @@
-mod device;
+mod emulator_panel;
 
-use device::{VirtualKeyboard, BAUDRATE, DEVICE_NAME, PRODUCT_ID, VENDOR_ID};
 use gtk4::prelude::*;
-use gtk4::{Application, ApplicationWindow, Box, Button, Entry, Label, Orientation, ScrolledWindow};
+use gtk4::{Application, ApplicationWindow, Box, Button, DropDown, Entry, Label, Notebook, Orientation, ScrolledWindow, SpinButton, StringList};
 use glib::clone;
 use std::cell::RefCell;
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use emulator_panel::build_emulator_panel;
+use virtusdev::device::{parse_hex_u16, BusKind, DeviceIdentity, UnsupportedChars, VirtualKeyboard, BAUDRATE};
+use virtusdev::hid_pos::HidPosScanner;
+use virtusdev::keyboard_layout::KeyboardLayout;
+use virtusdev::gs1;
+use virtusdev::scanner_config::{ControlChars, ScannerConfig, Terminator};
+use virtusdev::serial_scanner::{SerialFraming, SerialScanner, SerialScannerConfig};
+use virtusdev::symbology::{CodeId, Symbology};
+use virtusdev::timing::TimingProfile;
 
 fn main() {
     let app = Application::builder()
@@ -18,37 +26,139 @@ fn main() {
     app.run();
 }
 
-struct ScanRecord {
-    barcode: String,
-    duration: Duration,
+fn config_dir() -> PathBuf {
+    let home = std::env::var_os("HOME").unwrap_or_default();
+    PathBuf::from(home).join(".config/virtusdev")
+}
+
+/// Custom layout tables, in addition to the built-in ones
+fn layouts_dir() -> PathBuf {
+    config_dir().join("layouts")
+}
+
+/// Prefix, suffix and terminator, kept between runs
+fn scanner_config_path() -> PathBuf {
+    config_dir().join("scanner.json")
+}
+
+/// A virtual scanner of either backend
+enum Scanner {
+    /// Keyboard wedge on /dev/uinput
+    Keyboard(std::boxed::Box<VirtualKeyboard>),
+    /// HID POS barcode scanner on /dev/uhid
+    HidPos(HidPosScanner),
+    /// Serial scanner on a PTY
+    Serial(SerialScanner),
+}
+
+impl Scanner {
+    const BACKENDS: [&'static str; 3] = ["Keyboard wedge", "HID POS", "Serial (PTY)"];
+
+    fn identity(&self) -> &DeviceIdentity {
+        match self {
+            Self::Keyboard(device) => device.identity(),
+            Self::HidPos(device) => device.identity(),
+            Self::Serial(device) => device.identity(),
+        }
+    }
+
+    /// Device node the host reads the scans from
+    fn node(&self) -> String {
+        match self {
+            Self::Keyboard(device) => format!("Event: {}", device.event_path()),
+            Self::HidPos(device) => format!("hidraw: {}", device.hidraw_path()),
+            Self::Serial(device) => format!("Serial port: {}", device.slave_path()),
+        }
+    }
+
+    fn baud_rate(&self) -> u32 {
+        match self {
+            Self::Serial(device) => device.config().baud_rate,
+            _ => BAUDRATE,
+        }
+    }
+
+    /// Dropdown entry of the scanner
+    fn title(&self) -> String {
+        let path = match self {
+            Self::Keyboard(device) => device.event_path(),
+            Self::HidPos(device) => device.hidraw_path(),
+            Self::Serial(device) => device.slave_path(),
+        };
+        format!("{} — {}", self.identity().describe(), path)
+    }
+
+    /// Send a scan; returns the history summary and the warnings
+    fn scan(&mut self, barcode: &str, symbology: Option<Symbology>) -> anyhow::Result<(String, Option<String>)> {
+        match self {
+            Self::Keyboard(device) => {
+                let report = device.send_scan(barcode, symbology)?;
+                Ok((report.timing_summary(), report.warnings()))
+            }
+            Self::HidPos(device) => {
+                let (reports, duration) = device.send_scan(barcode, symbology)?;
+                let summary = format!("HID POS, {} report(s) in {:.1}ms", reports, duration.as_secs_f64() * 1000.0);
+                Ok((summary, None))
+            }
+            Self::Serial(device) => {
+                let (bytes, duration) = device.send_scan(barcode, symbology)?;
+                let summary = format!("serial, {} bytes in {:.1}ms", bytes, duration.as_secs_f64() * 1000.0);
+                Ok((summary, None))
+            }
+        }
+    }
 }
 
 struct AppState {
-    device: Option<Arc<Mutex<VirtualKeyboard>>>,
-    event_path: String,
-    scan_history: Rc<RefCell<Vec<ScanRecord>>>,
+    /// Virtual scanners; the keyboard wedges share the same settings and
+    /// scans go to the selected one
+    scanners: Vec<Arc<Mutex<Scanner>>>,
+    selected: usize,
+    /// Framing as last saved, for a keyboard wedge added when there is none
+    framing: ScannerConfig,
 }
 
-fn build_ui(app: &Application) {
-    // Try to create the virtual device
-    let (device, event_path, error_msg) = match VirtualKeyboard::new() {
-        Ok(device) => {
-            let event_path = device.event_path().to_string();
-            (Some(Arc::new(Mutex::new(device))), event_path, None)
+impl AppState {
+    fn selected(&self) -> Option<&Arc<Mutex<Scanner>>> {
+        self.scanners.get(self.selected)
+    }
+
+    /// Layout, unsupported characters mode and framing of the keyboard wedges
+    fn keyboard_settings(&self) -> Option<(KeyboardLayout, UnsupportedChars, ScannerConfig)> {
+        self.scanners.iter().find_map(|scanner| match &*scanner.lock().unwrap() {
+            Scanner::Keyboard(device) => Some((device.layout().clone(), device.unsupported_chars(), device.config().clone())),
+            _ => None,
+        })
+    }
+
+    /// Apply a setting to every keyboard wedge
+    fn configure(&self, mut apply: impl FnMut(&mut VirtualKeyboard)) {
+        for scanner in &self.scanners {
+            if let Scanner::Keyboard(device) = &mut *scanner.lock().unwrap() {
+                apply(device);
+            }
         }
+    }
+}
+
+fn build_ui(app: &Application) {
+    // Try to create the keyboard wedge; without /dev/uinput the HID POS and
+    // serial backends still work
+    let (device, error_msg) = match VirtualKeyboard::new(&DeviceIdentity::default()) {
+        Ok(device) => (Some(Arc::new(Mutex::new(Scanner::Keyboard(std::boxed::Box::new(device))))), None),
         Err(e) => {
             let error_msg = if e.to_string().contains("Permission denied") {
-                "Permission denied: /dev/uinput\n\nPlease run with sudo:\n  sudo ./virtusdev\n\nOr add udev rule:\n  sudo usermod -a -G input $USER".to_string()
+                "Permission denied: /dev/uinput. Run with sudo or join the input group (sudo usermod -a -G input $USER)".to_string()
             } else {
                 format!("Failed to create device: {}", e)
             };
-            (None, String::new(), Some(error_msg))
+            (None, Some(error_msg))
         }
     };
 
     let window = ApplicationWindow::builder()
         .application(app)
-        .title("VirtusDev - Virtual Barcode Scanner")
+        .title("VirtusDev - Virtual Devices")
         .default_width(500)
         .default_height(600)
         .build();
@@ -59,144 +169,488 @@ fn build_ui(app: &Application) {
     main_box.set_margin_start(20);
     main_box.set_margin_end(20);
 
-    if let Some(error) = error_msg {
-        // Error view
-        let error_label = Label::new(Some("⚠ Error"));
-        error_label.add_css_class("error");
-        let error_details = Label::new(Some(&error));
-        error_details.set_wrap(true);
-        
-        main_box.append(&error_label);
-        main_box.append(&error_details);
-    } else {
-        // Running view
-        let state = Rc::new(RefCell::new(AppState {
-            device,
-            event_path: event_path.clone(),
-            scan_history: Rc::new(RefCell::new(Vec::new())),
-        }));
-
-        // Status label
-        let status_label = Label::new(Some("Status: ○ Running"));
-        status_label.set_halign(gtk4::Align::Start);
-        main_box.append(&status_label);
-
-        // Device information
-        let info_box = Box::new(Orientation::Vertical, 5);
-        info_box.set_margin_top(10);
-        
-        let info_title = Label::new(Some("Device Information"));
-        info_title.set_halign(gtk4::Align::Start);
-        info_box.append(&info_title);
-        
-        info_box.append(&Label::new(Some(&format!("Name: {}", DEVICE_NAME))));
-        info_box.append(&Label::new(Some(&format!("Event: {}", event_path))));
-        info_box.append(&Label::new(Some(&format!("Baudrate: {} bps", BAUDRATE))));
-        info_box.append(&Label::new(Some(&format!("Vendor ID: 0x{:04X}", VENDOR_ID))));
-        info_box.append(&Label::new(Some(&format!("Product ID: 0x{:04X}", PRODUCT_ID))));
-        
-        main_box.append(&info_box);
-
-        // Input section
-        let input_box = Box::new(Orientation::Vertical, 10);
-        input_box.set_margin_top(20);
-        
-        let entry = Entry::new();
-        entry.set_placeholder_text(Some("Enter barcode..."));
-        input_box.append(&entry);
-
-        let scan_button = Button::with_label("SCAN");
-        scan_button.add_css_class("suggested-action");
-        input_box.append(&scan_button);
-
-        main_box.append(&input_box);
-
-        // History section
-        let history_box = Box::new(Orientation::Vertical, 5);
-        history_box.set_margin_top(20);
-        
-        let history_title = Label::new(Some("Recent Scans"));
-        history_title.set_halign(gtk4::Align::Start);
-        history_box.append(&history_title);
-        
-        let history_scroll = ScrolledWindow::builder()
-            .hscrollbar_policy(gtk4::PolicyType::Never)
-            .min_content_height(150)
-            .build();
-        
-        let history_list = Box::new(Orientation::Vertical, 5);
-        let no_scans_label = Label::new(Some("No scans yet"));
-        no_scans_label.set_halign(gtk4::Align::Start);
-        history_list.append(&no_scans_label);
-        
-        history_scroll.set_child(Some(&history_list));
-        history_box.append(&history_scroll);
-        main_box.append(&history_box);
-
-        // Footer
-        let footer = Label::new(Some("Made by jvwaldrich0"));
-        footer.set_margin_top(20);
-        footer.add_css_class("dim-label");
-        main_box.append(&footer);
-
-        // Connect scan button
-        let state_clone = Rc::clone(&state);
-        let entry_clone = entry.clone();
-        let status_clone = status_label.clone();
-        let history_clone = history_list.clone();
-        
-        scan_button.connect_clicked(clone!(@weak entry_clone as entry, @weak status_clone as status, @weak history_clone as history => move |_| {
-            let barcode = entry.text().to_string();
-            if barcode.is_empty() {
-                return;
+    // Saved data framing, applied to the keyboard wedges further down
+    let config = ScannerConfig::load(&scanner_config_path()).unwrap_or_else(|e| {
+        eprintln!("Using default scanner config: {:#}", e);
+        ScannerConfig::default()
+    });
+    let state = Rc::new(RefCell::new(AppState {
+        scanners: device.into_iter().collect(),
+        selected: 0,
+        framing: config.clone(),
+    }));
+
+    // Status label
+    let status_label = Label::new(Some("Status: ○ Running"));
+    status_label.set_halign(gtk4::Align::Start);
+    status_label.set_wrap(true);
+    if let Some(error) = &error_msg {
+        status_label.set_text(&format!("Status: ⚠ No keyboard wedge: {}", error));
+    }
+    main_box.append(&status_label);
+
+    // Sends the barcode when the host triggers a serial scanner, shown for those only
+    let present_button = Button::with_label("Present");
+    present_button.set_tooltip_text(Some("Queue the barcode until the host triggers a read"));
+    present_button.set_visible(false);
+
+    // Device information of the selected scanner
+    let info_box = Box::new(Orientation::Vertical, 5);
+    info_box.set_margin_top(10);
+    
+    let info_title = Label::new(Some("Device Information"));
+    info_title.set_halign(gtk4::Align::Start);
+    info_box.append(&info_title);
+
+    let scanner_list = StringList::new(&[]);
+    for device in &state.borrow().scanners {
+        scanner_list.append(&device.lock().unwrap().title());
+    }
+    let scanner_box = Box::new(Orientation::Horizontal, 10);
+    let scanner_dropdown = DropDown::new(Some(scanner_list.clone()), None::<gtk4::Expression>);
+    scanner_dropdown.set_hexpand(true);
+    let remove_button = Button::with_label("Remove");
+    scanner_box.append(&scanner_dropdown);
+    scanner_box.append(&remove_button);
+    info_box.append(&scanner_box);
+
+    let info_labels: [Label; 6] = std::array::from_fn(|_| {
+        let label = Label::new(None);
+        label.set_halign(gtk4::Align::Start);
+        info_box.append(&label);
+        label
+    });
+    let show_info = Rc::new(clone!(#[strong] state, #[strong] info_labels, #[weak] present_button, move || {
+        let state = state.borrow();
+        let Some(device) = state.selected() else { return };
+        let device = device.lock().unwrap();
+        present_button.set_visible(matches!(*device, Scanner::Serial(_)));
+        let identity = device.identity();
+        let lines = [
+            format!("Name: {}", identity.name),
+            device.node(),
+            format!("Baudrate: {} bps", device.baud_rate()),
+            format!("Vendor ID: 0x{:04X}", identity.vendor_id),
+            format!("Product ID: 0x{:04X}", identity.product_id),
+            format!("Bus: {} (version 0x{:04X})", identity.bus.label(), identity.version),
+        ];
+        for (label, line) in info_labels.iter().zip(lines) {
+            label.set_text(&line);
+        }
+    }));
+    show_info();
+
+    let selection_info = Rc::clone(&show_info);
+    scanner_dropdown.connect_selected_notify(clone!(#[strong] state, move |dropdown| {
+        state.borrow_mut().selected = dropdown.selected() as usize;
+        selection_info();
+    }));
+
+    // Another scanner, e.g. a second lane or another brand's VID:PID
+    let add_box = Box::new(Orientation::Horizontal, 10);
+    let defaults = DeviceIdentity::default();
+    let name_entry = Entry::new();
+    name_entry.set_placeholder_text(Some("Name"));
+    name_entry.set_text(&defaults.name);
+    name_entry.set_hexpand(true);
+    let [vid_entry, pid_entry, version_entry] = [("VID", defaults.vendor_id), ("PID", defaults.product_id), ("Version", defaults.version)]
+        .map(|(placeholder, value)| {
+            let id_entry = Entry::new();
+            id_entry.set_placeholder_text(Some(placeholder));
+            id_entry.set_text(&format!("0x{:04X}", value));
+            id_entry.set_width_chars(7);
+            id_entry
+        });
+    let bus_labels: Vec<&str> = BusKind::ALL.iter().map(|bus| bus.label()).collect();
+    let bus_dropdown = DropDown::from_strings(&bus_labels);
+    let backend_dropdown = DropDown::from_strings(&Scanner::BACKENDS);
+    let framing_labels: Vec<&str> = SerialFraming::ALL.iter().map(|framing| framing.label()).collect();
+    let serial_framing_dropdown = DropDown::from_strings(&framing_labels);
+    serial_framing_dropdown.set_selected(SerialFraming::ALL.iter().position(|f| *f == SerialFraming::default()).unwrap_or(0) as u32);
+    serial_framing_dropdown.set_tooltip_text(Some("Framing of the serial scanner"));
+    serial_framing_dropdown.set_sensitive(false);
+    backend_dropdown.connect_selected_notify(clone!(#[weak] serial_framing_dropdown, move |dropdown| {
+        serial_framing_dropdown.set_sensitive(dropdown.selected() == 2);
+    }));
+    if error_msg.is_some() {
+        backend_dropdown.set_selected(2);
+    }
+    let add_button = Button::with_label("Add scanner");
+    add_box.append(&name_entry);
+    add_box.append(&vid_entry);
+    add_box.append(&pid_entry);
+    add_box.append(&bus_dropdown);
+    add_box.append(&version_entry);
+    add_box.append(&backend_dropdown);
+    add_box.append(&serial_framing_dropdown);
+    add_box.append(&add_button);
+    info_box.append(&add_box);
+    
+    main_box.append(&info_box);
+
+    add_button.connect_clicked(clone!(#[strong] state, #[weak] scanner_list, #[weak] scanner_dropdown, #[weak] status_label, #[weak] name_entry, #[weak] vid_entry, #[weak] pid_entry, #[weak] version_entry, #[weak] bus_dropdown, #[weak] backend_dropdown, #[weak] serial_framing_dropdown, move |_| {
+        let identity = (|| -> anyhow::Result<DeviceIdentity> {
+            Ok(DeviceIdentity {
+                name: name_entry.text().trim().to_string(),
+                vendor_id: parse_hex_u16(&vid_entry.text())?,
+                product_id: parse_hex_u16(&pid_entry.text())?,
+                bus: BusKind::ALL[bus_dropdown.selected() as usize % BusKind::ALL.len()],
+                version: parse_hex_u16(&version_entry.text())?,
+            })
+        })();
+        let created = identity.and_then(|identity| {
+            let current = state.borrow().keyboard_settings();
+            match backend_dropdown.selected() {
+                1 => return Ok(Scanner::HidPos(HidPosScanner::new(&identity)?)),
+                2 => {
+                    // Code ID and baud rate follow the keyboard wedge settings
+                    let mut config = SerialScannerConfig {
+                        framing: SerialFraming::ALL[serial_framing_dropdown.selected() as usize % SerialFraming::ALL.len()],
+                        ..Default::default()
+                    };
+                    if let Some((_, _, keyboard_config)) = &current {
+                        config.code_id = keyboard_config.code_id;
+                        if let TimingProfile::Baud(rate) = keyboard_config.timing {
+                            config.baud_rate = rate;
+                        }
+                    }
+                    return Ok(Scanner::Serial(SerialScanner::new(&identity, config)?));
+                }
+                _ => {}
             }
+            // A new keyboard wedge gets the settings of the others, or the
+            // saved framing if it is the first one
+            let mut device = VirtualKeyboard::new(&identity)?;
+            match current {
+                Some((layout, unsupported, config)) => {
+                    device.set_layout(layout);
+                    device.set_unsupported_chars(unsupported);
+                    device.set_config(config)?;
+                }
+                None => device.set_config(state.borrow().framing.clone())?,
+            }
+            Ok(Scanner::Keyboard(std::boxed::Box::new(device)))
+        });
+        match created {
+            Ok(device) => {
+                println!("✓ Virtual scanner created: {}", device.title());
+                scanner_list.append(&device.title());
+                state.borrow_mut().scanners.push(Arc::new(Mutex::new(device)));
+                scanner_dropdown.set_selected(scanner_list.n_items() - 1);
+            }
+            Err(e) => status_label.set_text(&format!("Status: ✗ {:#}", e)),
+        }
+    }));
+
+    remove_button.connect_clicked(clone!(#[strong] state, #[weak] scanner_list, #[weak] scanner_dropdown, #[weak] status_label, move |_| {
+        let selected = scanner_dropdown.selected();
+        if state.borrow().scanners.len() <= 1 {
+            status_label.set_text("Status: ⚠ The last scanner cannot be removed");
+            return;
+        }
+        // Dropping the device destroys the uinput or uhid node, or closes the PTY
+        state.borrow_mut().scanners.remove(selected as usize);
+        scanner_list.remove(selected);
+        scanner_dropdown.set_selected(selected.saturating_sub(1));
+        state.borrow_mut().selected = scanner_dropdown.selected() as usize;
+        show_info();
+    }));
 
-            let state_ref = state_clone.borrow();
-            if let Some(device) = &state_ref.device {
-                status.set_text("Status: ● Scanning");
-                
-                let device_clone = Arc::clone(device);
-                let barcode_for_device = barcode.clone();
-                let barcode_for_display = barcode.clone();
-                
-                glib::spawn_future_local(clone!(@weak entry, @weak status, @weak history => async move {
-                    let result: Result<Duration, anyhow::Error> = glib::spawn_future(async move {
-                        let mut device = device_clone.lock().unwrap();
-                        device.send_barcode(&barcode_for_device)
-                    }).await.unwrap();
-
-                    status.set_text("Status: ○ Running");
-                    
-                    match result {
-                        Ok(duration) => {
-                            let duration_ms = duration.as_secs_f64() * 1000.0;
-                            let scan_label = Label::new(Some(&format!("• {} ({:.1}ms)", barcode_for_display, duration_ms)));
-                            scan_label.set_halign(gtk4::Align::Start);
-                            
-                            // Clear "No scans yet" if present
-                            if let Some(first_child) = history.first_child() {
-                                if first_child.is::<Label>() {
-                                    history.remove(&first_child);
-                                }
+    // Input section
+    let input_box = Box::new(Orientation::Vertical, 10);
+    input_box.set_margin_top(20);
+
+    // Keyboard layout of the host receiving the scans
+    let layouts = KeyboardLayout::available(&layouts_dir());
+    let layout_names: Vec<&str> = layouts.iter().map(KeyboardLayout::name).collect();
+    let layout_box = Box::new(Orientation::Horizontal, 10);
+    layout_box.append(&Label::new(Some("Keyboard layout:")));
+    let layout_dropdown = DropDown::from_strings(&layout_names);
+    layout_dropdown.set_hexpand(true);
+    layout_box.append(&layout_dropdown);
+    input_box.append(&layout_box);
+
+    let layout_state = Rc::clone(&state);
+    layout_dropdown.connect_selected_notify(move |dropdown| {
+        if let Some(layout) = layouts.get(dropdown.selected() as usize) {
+            layout_state.borrow().configure(|device| device.set_layout(layout.clone()));
+        }
+    });
+
+    // Characters the layout cannot type
+    let unsupported_labels: Vec<&str> = UnsupportedChars::ALL.iter().map(|mode| mode.label()).collect();
+    let unsupported_box = Box::new(Orientation::Horizontal, 10);
+    unsupported_box.append(&Label::new(Some("Unsupported characters:")));
+    let unsupported_dropdown = DropDown::from_strings(&unsupported_labels);
+    unsupported_dropdown.set_hexpand(true);
+    unsupported_box.append(&unsupported_dropdown);
+    input_box.append(&unsupported_box);
+
+    let unsupported_state = Rc::clone(&state);
+    unsupported_dropdown.connect_selected_notify(move |dropdown| {
+        if let Some(&mode) = UnsupportedChars::ALL.get(dropdown.selected() as usize) {
+            unsupported_state.borrow().configure(|device| device.set_unsupported_chars(mode));
+        }
+    });
+    
+    // Data framing, saved on every change
+    state.borrow().configure(|device| {
+        if let Err(e) = device.set_config(config.clone()) {
+            status_label.set_text(&format!("Status: ⚠ {:#}", e));
+        }
+    });
+
+    let framing_box = Box::new(Orientation::Horizontal, 10);
+    let prefix_entry = Entry::new();
+    prefix_entry.set_placeholder_text(Some("Prefix"));
+    prefix_entry.set_text(&config.prefix);
+    let suffix_entry = Entry::new();
+    suffix_entry.set_placeholder_text(Some("Suffix"));
+    suffix_entry.set_text(&config.suffix);
+    let focus_entry = Entry::new();
+    focus_entry.set_placeholder_text(Some("Focus key, e.g. KEY_F12"));
+    focus_entry.set_text(config.focus_key.as_deref().unwrap_or_default());
+    let terminator_labels: Vec<&str> = Terminator::ALL.iter().map(|terminator| terminator.label()).collect();
+    let terminator_dropdown = DropDown::from_strings(&terminator_labels);
+    terminator_dropdown.set_selected(Terminator::ALL.iter().position(|t| *t == config.terminator).unwrap_or(0) as u32);
+    let code_id_labels: Vec<&str> = CodeId::ALL.iter().map(|code_id| code_id.label()).collect();
+    let code_id_dropdown = DropDown::from_strings(&code_id_labels);
+    code_id_dropdown.set_selected(CodeId::ALL.iter().position(|c| *c == config.code_id).unwrap_or(0) as u32);
+    framing_box.append(&prefix_entry);
+    framing_box.append(&code_id_dropdown);
+    framing_box.append(&suffix_entry);
+    framing_box.append(&focus_entry);
+    framing_box.append(&terminator_dropdown);
+    input_box.append(&framing_box);
+
+    // GS, RS and EOT in GS1 / ISO 15434 data
+    let control_box = Box::new(Orientation::Horizontal, 10);
+    control_box.append(&Label::new(Some("GS/RS/EOT:")));
+    let control_modes = [
+        ControlChars::CtrlKey,
+        ControlChars::AltKeypad,
+        ControlChars::Replace { gs: String::new(), rs: String::new(), eot: String::new() },
+    ];
+    let control_labels: Vec<&str> = control_modes.iter().map(ControlChars::label).collect();
+    let control_dropdown = DropDown::from_strings(&control_labels);
+    control_dropdown.set_selected(match config.control_chars {
+        ControlChars::CtrlKey => 0,
+        ControlChars::AltKeypad => 1,
+        ControlChars::Replace { .. } => 2,
+    });
+    control_box.append(&control_dropdown);
+    let replacement_entries = [("GS", gs1::GS), ("RS", gs1::RS), ("EOT", gs1::EOT)].map(|(name, c)| {
+        let replacement_entry = Entry::new();
+        replacement_entry.set_placeholder_text(Some(&format!("{} as", name)));
+        replacement_entry.set_text(config.control_chars.replacement(c).unwrap_or_default());
+        control_box.append(&replacement_entry);
+        replacement_entry
+    });
+    input_box.append(&control_box);
+
+    // Key event timing, a profile from the config file is kept in the list
+    let timing_box = Box::new(Orientation::Horizontal, 10);
+    timing_box.append(&Label::new(Some("Timing:")));
+    let mut timing_profiles = TimingProfile::PRESETS.to_vec();
+    if !timing_profiles.contains(&config.timing) {
+        timing_profiles.push(config.timing);
+    }
+    let timing_labels: Vec<String> = timing_profiles.iter().map(|profile| profile.label()).collect();
+    let timing_dropdown = DropDown::from_strings(&timing_labels.iter().map(String::as_str).collect::<Vec<_>>());
+    timing_dropdown.set_selected(timing_profiles.iter().position(|p| *p == config.timing).unwrap_or(0) as u32);
+    timing_dropdown.set_hexpand(true);
+    timing_box.append(&timing_dropdown);
+    timing_box.append(&Label::new(Some("Jitter %:")));
+    let jitter_spin = SpinButton::with_range(0.0, 50.0, 1.0);
+    jitter_spin.set_value(config.jitter_percent as f64);
+    timing_box.append(&jitter_spin);
+    input_box.append(&timing_box);
+
+    // Barcode and its symbology, "Any" sends the data as typed
+    let barcode_box = Box::new(Orientation::Horizontal, 10);
+    let entry = Entry::new();
+    entry.set_placeholder_text(Some("Enter barcode... (GS1: (01)…(17)…(10)…, <GS> <RS> <EOT>)"));
+    entry.set_hexpand(true);
+    let symbology_labels: Vec<&str> = std::iter::once("Any")
+        .chain(Symbology::ALL.iter().map(|symbology| symbology.name()))
+        .collect();
+    let symbology_dropdown = DropDown::from_strings(&symbology_labels);
+    barcode_box.append(&entry);
+    barcode_box.append(&symbology_dropdown);
+    input_box.append(&barcode_box);
+
+    let scan_button = Button::with_label("SCAN");
+    scan_button.add_css_class("suggested-action");
+    scan_button.set_hexpand(true);
+    let scan_box = Box::new(Orientation::Horizontal, 10);
+    scan_box.append(&scan_button);
+    scan_box.append(&present_button);
+    input_box.append(&scan_box);
+
+    main_box.append(&input_box);
+
+    // History section
+    let history_box = Box::new(Orientation::Vertical, 5);
+    history_box.set_margin_top(20);
+    
+    let history_title = Label::new(Some("Recent Scans"));
+    history_title.set_halign(gtk4::Align::Start);
+    history_box.append(&history_title);
+    
+    let history_scroll = ScrolledWindow::builder()
+        .hscrollbar_policy(gtk4::PolicyType::Never)
+        .min_content_height(150)
+        .build();
+    
+    let history_list = Box::new(Orientation::Vertical, 5);
+    let no_scans_label = Label::new(Some("No scans yet"));
+    no_scans_label.set_halign(gtk4::Align::Start);
+    history_list.append(&no_scans_label);
+    
+    history_scroll.set_child(Some(&history_list));
+    history_box.append(&history_scroll);
+    main_box.append(&history_box);
+
+    // Footer
+    let footer = Label::new(Some("Made by jvwaldrich0"));
+    footer.set_margin_top(20);
+    footer.add_css_class("dim-label");
+    main_box.append(&footer);
+
+    // Connect scan button
+    let state_clone = Rc::clone(&state);
+    let entry_clone = entry.clone();
+    let status_clone = status_label.clone();
+    let history_clone = history_list.clone();
+    
+    scan_button.connect_clicked(clone!(#[weak(rename_to = entry)] entry_clone, #[weak(rename_to = status)] status_clone, #[weak(rename_to = history)] history_clone, #[weak] symbology_dropdown, move |_| {
+        let barcode = gs1::expand_placeholders(&entry.text());
+        if barcode.is_empty() {
+            return;
+        }
+        let symbology = (symbology_dropdown.selected() as usize)
+            .checked_sub(1)
+            .and_then(|index| Symbology::ALL.get(index).copied());
+
+        let state_ref = state_clone.borrow();
+        if let Some(device) = state_ref.selected() {
+            status.set_text("Status: ● Scanning");
+            
+            let device_clone = Arc::clone(device);
+            let barcode_for_device = barcode.clone();
+            let barcode_for_display = entry.text().to_string();
+            
+            glib::spawn_future_local(clone!(#[weak] entry, #[weak] status, #[weak] history, async move {
+                let result: Result<(String, Option<String>), anyhow::Error> = glib::spawn_future(async move {
+                    let mut device = device_clone.lock().unwrap();
+                    device.scan(&barcode_for_device, symbology)
+                }).await.unwrap();
+
+                match result {
+                    Ok((summary, warnings)) => {
+                        let mut text = format!("• {} ({})", barcode_for_display, summary);
+                        match warnings {
+                            Some(warnings) => {
+                                status.set_text(&format!("Status: ⚠ Scan altered: {}", warnings));
+                                text.push_str(&format!(" ⚠ {}", warnings));
                             }
-                            
-                            history.prepend(&scan_label);
-                            entry.set_text("");
+                            None => status.set_text("Status: ○ Running"),
                         }
-                        Err(e) => {
-                            eprintln!("Scan failed: {}", e);
+                        let scan_label = Label::new(Some(&text));
+                        scan_label.set_halign(gtk4::Align::Start);
+                        scan_label.set_wrap(true);
+                        
+                        // Clear "No scans yet" if present
+                        if let Some(first_child) = history.first_child() {
+                            if first_child.is::<Label>() {
+                                history.remove(&first_child);
+                            }
                         }
+                        
+                        history.prepend(&scan_label);
+                        entry.set_text("");
                     }
-                }));
+                    Err(e) => {
+                        eprintln!("Scan failed: {}", e);
+                        status.set_text(&format!("Status: ✗ Scan failed: {}", e));
+                    }
+                }
+            }));
+        }
+    }));
+
+    present_button.connect_clicked(clone!(#[strong] state, #[weak] entry, #[weak] status_label, #[weak] symbology_dropdown, move |_| {
+        let barcode = gs1::expand_placeholders(&entry.text());
+        if barcode.is_empty() {
+            return;
+        }
+        let symbology = (symbology_dropdown.selected() as usize)
+            .checked_sub(1)
+            .and_then(|index| Symbology::ALL.get(index).copied());
+        let state = state.borrow();
+        let Some(device) = state.selected() else { return };
+        let Scanner::Serial(scanner) = &*device.lock().unwrap() else { return };
+        match scanner.present(&barcode, symbology) {
+            Ok(waiting) => {
+                status_label.set_text(&format!("Status: ○ Presented, {} waiting for the host trigger", waiting));
+                entry.set_text("");
             }
-        }));
+            Err(e) => status_label.set_text(&format!("Status: ✗ {:#}", e)),
+        }
+    }));
 
-        // Connect enter key
-        entry.connect_activate(clone!(@weak scan_button => move |_| {
-            scan_button.emit_clicked();
-        }));
+    // Apply and save the framing whenever it is edited
+    let apply_config = Rc::new(clone!(#[weak] prefix_entry, #[weak] code_id_dropdown, #[weak] suffix_entry, #[weak] focus_entry, #[weak] terminator_dropdown, #[weak] control_dropdown, #[strong] replacement_entries, #[weak] timing_dropdown, #[weak] jitter_spin, #[weak] status_label, #[strong] state, move || {
+        let focus_key = focus_entry.text().trim().to_string();
+        let [gs, rs, eot] = replacement_entries.each_ref().map(|entry| entry.text().to_string());
+        let control_chars = match control_dropdown.selected() {
+            0 => ControlChars::CtrlKey,
+            1 => ControlChars::AltKeypad,
+            _ => ControlChars::Replace { gs, rs, eot },
+        };
+        let config = ScannerConfig {
+            prefix: prefix_entry.text().to_string(),
+            code_id: CodeId::ALL[code_id_dropdown.selected() as usize % CodeId::ALL.len()],
+            suffix: suffix_entry.text().to_string(),
+            terminator: Terminator::ALL[terminator_dropdown.selected() as usize % Terminator::ALL.len()],
+            focus_key: (!focus_key.is_empty()).then_some(focus_key),
+            control_chars,
+            timing: timing_profiles.get(timing_dropdown.selected() as usize).copied().unwrap_or_default(),
+            jitter_percent: jitter_spin.value() as u8,
+        };
+        let applied = state.borrow().scanners.iter().try_for_each(|scanner| match &mut *scanner.lock().unwrap() {
+            Scanner::Keyboard(device) => device.set_config(config.clone()),
+            _ => Ok(()),
+        });
+        let result = applied.and_then(|()| config.save(&scanner_config_path()));
+        match result {
+            Ok(()) => {
+                state.borrow_mut().framing = config;
+                status_label.set_text("Status: ○ Running");
+            }
+            Err(e) => status_label.set_text(&format!("Status: ⚠ {:#}", e)),
+        }
+    }));
+    for framing_entry in [&prefix_entry, &suffix_entry, &focus_entry].into_iter().chain(&replacement_entries) {
+        let apply_config = Rc::clone(&apply_config);
+        framing_entry.connect_changed(move |_| apply_config());
+    }
+    let jitter_apply = Rc::clone(&apply_config);
+    jitter_spin.connect_value_changed(move |_| jitter_apply());
+    for framing_dropdown in [&code_id_dropdown, &terminator_dropdown, &control_dropdown, &timing_dropdown] {
+        let apply_config = Rc::clone(&apply_config);
+        framing_dropdown.connect_selected_notify(move |_| apply_config());
     }
 
-    window.set_child(Some(&main_box));
+    // Connect enter key
+    entry.connect_activate(clone!(#[weak] scan_button, move |_| {
+        scan_button.emit_clicked();
+    }));
+
+    let notebook = Notebook::new();
+    notebook.append_page(&main_box, Some(&Label::new(Some("Barcode Scanner"))));
+    notebook.append_page(&build_emulator_panel(), Some(&Label::new(Some("Bill & Coin Emulator"))));
+
+    window.set_child(Some(&notebook));
     window.present();
 }
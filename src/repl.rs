@@ -0,0 +1,367 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::bill_emulator::{DeviceFault, DeviceState, UNIT_TYPE_NV200, UNIT_TYPE_SMART_HOPPER};
+use crate::control::Devices;
+use crate::essp_protocol::parse_address;
+
+pub const HELP: &str = "\
+Commands (amounts in currency units, e.g. 50.00 or 0.25):
+  insert note ADDR AMOUNT [CURRENCY]   e.g. insert note 0x00 50.00 BRL
+  insert coin ADDR AMOUNT [CURRENCY]   e.g. insert coin 0x10 0.25
+  levels [ADDR]                        show stored cash levels
+  levels ADDR AMOUNT COUNT             set the stored count of a denomination
+  events [ADDR]                        show events queued for the next POLL
+  fault ADDR KIND                      jam, cashbox_removed, reject or reset
+  enable ADDR | disable ADDR
+  status [ADDR]
+  help
+  quit";
+
+const COMMANDS: &[&str] = &["insert", "levels", "events", "fault", "enable", "disable", "status", "help", "quit"];
+const FAULTS: &[&str] = &["jam", "cashbox_removed", "reject", "reset"];
+
+/// Which kind of cash an `insert` command puts in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cash {
+    Note,
+    Coin,
+}
+
+/// A parsed REPL command
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplCommand {
+    Insert {
+        cash: Cash,
+        address: u8,
+        value: u32,
+        currency: Option<[u8; 3]>,
+    },
+    Levels { address: Option<u8> },
+    SetLevel { address: u8, value: u32, count: u16 },
+    Events { address: Option<u8> },
+    Fault { address: u8, fault: DeviceFault },
+    Enable { address: u8, enabled: bool },
+    Status { address: Option<u8> },
+    Help,
+    Quit,
+}
+
+/// Parse an amount in currency units ("50", "0.25") into cents
+pub fn parse_money(s: &str) -> Result<u32> {
+    let invalid = || anyhow!("Invalid amount: {}", s);
+    let (units, cents) = s.split_once('.').unwrap_or((s, ""));
+    if cents.len() > 2 || !cents.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let units: u32 = units.parse().map_err(|_| invalid())?;
+    let cents: u32 = format!("{:0<2}", cents).parse().map_err(|_| invalid())?;
+    units.checked_mul(100).and_then(|u| u.checked_add(cents)).ok_or_else(invalid)
+}
+
+/// Format cents as currency units, e.g. 2500 -> "25.00"
+pub fn format_money(cents: u32) -> String {
+    format!("{}.{:02}", cents / 100, cents % 100)
+}
+
+fn parse_currency(s: &str) -> Result<[u8; 3]> {
+    let upper = s.to_ascii_uppercase();
+    upper
+        .as_bytes()
+        .try_into()
+        .ok()
+        .filter(|code: &[u8; 3]| code.iter().all(u8::is_ascii_alphabetic))
+        .ok_or_else(|| anyhow!("Invalid currency: {}", s))
+}
+
+fn optional_address(arg: Option<&&str>) -> Result<Option<u8>> {
+    arg.map(|a| parse_address(a)).transpose()
+}
+
+impl ReplCommand {
+    pub fn parse(line: &str) -> Result<Self> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let usage = || anyhow!("Invalid command '{}', type 'help' for the list of commands", line.trim());
+
+        let command = match words.as_slice() {
+            ["insert", kind, address, amount, currency @ ..] if currency.len() <= 1 => {
+                let cash = match *kind {
+                    "note" | "bill" => Cash::Note,
+                    "coin" => Cash::Coin,
+                    _ => return Err(usage()),
+                };
+                ReplCommand::Insert {
+                    cash,
+                    address: parse_address(address)?,
+                    value: parse_money(amount)?,
+                    currency: currency.first().map(|c| parse_currency(c)).transpose()?,
+                }
+            }
+            ["levels", address, amount, count] => ReplCommand::SetLevel {
+                address: parse_address(address)?,
+                value: parse_money(amount)?,
+                count: count.parse().with_context(|| format!("Invalid count: {}", count))?,
+            },
+            ["levels", rest @ ..] if rest.len() <= 1 => ReplCommand::Levels {
+                address: optional_address(rest.first())?,
+            },
+            ["events", rest @ ..] if rest.len() <= 1 => ReplCommand::Events {
+                address: optional_address(rest.first())?,
+            },
+            ["status", rest @ ..] if rest.len() <= 1 => ReplCommand::Status {
+                address: optional_address(rest.first())?,
+            },
+            ["fault", address, fault] => ReplCommand::Fault {
+                address: parse_address(address)?,
+                fault: fault.parse()?,
+            },
+            ["enable", address] | ["disable", address] => ReplCommand::Enable {
+                address: parse_address(address)?,
+                enabled: words[0] == "enable",
+            },
+            ["help"] | ["?"] => ReplCommand::Help,
+            ["quit"] | ["exit"] => ReplCommand::Quit,
+            _ => return Err(usage()),
+        };
+        Ok(command)
+    }
+}
+
+fn device_mut(devices: &mut HashMap<u8, DeviceState>, address: u8) -> Result<&mut DeviceState> {
+    devices
+        .get_mut(&address)
+        .ok_or_else(|| anyhow!("No device at address 0x{:02X}", address))
+}
+
+/// Devices selected by an optional address, sorted by address
+fn selected(devices: &HashMap<u8, DeviceState>, address: Option<u8>) -> Result<Vec<&DeviceState>> {
+    let mut selected: Vec<&DeviceState> = match address {
+        Some(address) => vec![devices
+            .get(&address)
+            .ok_or_else(|| anyhow!("No device at address 0x{:02X}", address))?],
+        None => devices.values().collect(),
+    };
+    selected.sort_by_key(|device| device.address);
+    Ok(selected)
+}
+
+fn currency(device: &DeviceState) -> String {
+    String::from_utf8_lossy(&device.currency_code).into_owned()
+}
+
+/// Insert cash after checking the denomination against the device's channel table
+fn insert(device: &mut DeviceState, cash: Cash, value: u32, currency_code: Option<[u8; 3]>) -> Result<String> {
+    let takes = match device.unit_type {
+        UNIT_TYPE_NV200 => Some(("NV200 note validator", Cash::Note)),
+        UNIT_TYPE_SMART_HOPPER => Some(("Smart Hopper", Cash::Coin)),
+        _ => None,
+    };
+    if let Some((kind, accepted)) = takes.filter(|&(_, accepted)| accepted != cash) {
+        let plural = |cash| if cash == Cash::Note { "notes" } else { "coins" };
+        return Err(anyhow!(
+            "Device 0x{:02X} ({}) takes {}, not {}",
+            device.address,
+            kind,
+            plural(accepted),
+            plural(cash)
+        ));
+    }
+    let denominations = || {
+        device
+            .channels
+            .iter()
+            .map(|channel| format_money(channel.value))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let Some(channel) = device.channels.iter().find(|channel| channel.value == value) else {
+        return Err(anyhow!(
+            "Device 0x{:02X} has no channel for {} {} (channels: {})",
+            device.address,
+            format_money(value),
+            currency(device),
+            denominations()
+        ));
+    };
+    if let Some(code) = currency_code {
+        if code != channel.currency {
+            return Err(anyhow!(
+                "Device 0x{:02X} accepts {}, not {}",
+                device.address,
+                String::from_utf8_lossy(&channel.currency),
+                String::from_utf8_lossy(&code)
+            ));
+        }
+    }
+
+    let (accepted, kind) = match cash {
+        Cash::Note => (device.insert_note(value), "note"),
+        Cash::Coin => (device.insert_coins(value), "coin"),
+    };
+    if !accepted {
+        return Err(anyhow!("Device 0x{:02X} is disabled", device.address));
+    }
+    Ok(format!("✓ Inserted {} {} {}", format_money(value), currency(device), kind))
+}
+
+/// Run a command against the devices and return the text to show
+pub fn execute(devices: &Devices, command: &ReplCommand) -> Result<String> {
+    let mut devices = devices.lock().unwrap();
+    let mut out = String::new();
+
+    match command {
+        ReplCommand::Insert { cash, address, value, currency } => {
+            return insert(device_mut(&mut devices, *address)?, *cash, *value, *currency);
+        }
+        ReplCommand::Levels { address } => {
+            for device in selected(&devices, *address)? {
+                let status = device.status();
+                writeln!(out, "[0x{:02X}] {}", device.address, status.currency)?;
+                for level in status.levels {
+                    writeln!(out, "  {:>8} x {}", format_money(level.value), level.count)?;
+                }
+            }
+        }
+        ReplCommand::SetLevel { address, value, count } => {
+            let device = device_mut(&mut devices, *address)?;
+            device.set_level(*value, *count);
+            writeln!(out, "✓ [0x{:02X}] {} x {}", address, format_money(*value), count)?;
+        }
+        ReplCommand::Events { address } => {
+            for device in selected(&devices, *address)? {
+                let events = device.status().queued_events;
+                let events = if events.is_empty() { "(none)".to_string() } else { events.join(", ") };
+                writeln!(out, "[0x{:02X}] {}", device.address, events)?;
+            }
+        }
+        ReplCommand::Fault { address, fault } => {
+            device_mut(&mut devices, *address)?.inject_fault(*fault);
+            writeln!(out, "✓ [0x{:02X}] {:?} queued", address, fault)?;
+        }
+        ReplCommand::Enable { address, enabled } => {
+            device_mut(&mut devices, *address)?.set_enabled(*enabled);
+            let state = if *enabled { "enabled" } else { "disabled" };
+            writeln!(out, "✓ [0x{:02X}] {}", address, state)?;
+        }
+        ReplCommand::Status { address } => {
+            for device in selected(&devices, *address)? {
+                let status = device.status();
+                let channels: Vec<String> = status.channels.iter().map(|&v| format_money(v)).collect();
+                writeln!(
+                    out,
+                    "[0x{:02X}] unit 0x{:02X} {} | {} | payout {} | inhibits {:02X} {:02X}\n  channels: {}\n  queued: {}",
+                    status.address,
+                    status.unit_type,
+                    status.currency,
+                    if status.enabled { "enabled" } else { "disabled" },
+                    if status.payout_enabled { "on" } else { "off" },
+                    status.inhibits[0],
+                    status.inhibits[1],
+                    channels.join(", "),
+                    status.queued_events.len()
+                )?;
+            }
+        }
+        ReplCommand::Help => out.push_str(HELP),
+        ReplCommand::Quit => {}
+    }
+
+    Ok(out.trim_end().to_string())
+}
+
+/// Tab completion candidates for the word under the cursor.
+///
+/// `line` is the input up to the cursor. Returns the start of the word being
+/// completed and the candidates for it.
+pub fn complete(devices: &HashMap<u8, DeviceState>, line: &str) -> (usize, Vec<String>) {
+    let start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+    let prefix = &line[start..];
+    let words: Vec<&str> = line[..start].split_whitespace().collect();
+
+    let mut addresses: Vec<u8> = devices.keys().copied().collect();
+    addresses.sort();
+    let addresses = || addresses.iter().map(|a| format!("0x{:02X}", a)).collect::<Vec<_>>();
+    let device = |word: &str| parse_address(word).ok().and_then(|a| devices.get(&a));
+
+    let candidates: Vec<String> = match words.as_slice() {
+        [] => COMMANDS.iter().map(|c| c.to_string()).collect(),
+        ["insert"] => vec!["note".to_string(), "coin".to_string()],
+        ["insert", _] | ["levels"] | ["events"] | ["status"] | ["fault"] | ["enable"] | ["disable"] => addresses(),
+        ["insert", _, address] | ["levels", address] => device(address)
+            .map(|d| d.channels.iter().map(|c| format_money(c.value)).collect())
+            .unwrap_or_default(),
+        ["insert", _, address, _] => device(address).map(|d| vec![currency(d)]).unwrap_or_default(),
+        ["fault", _] => FAULTS.iter().map(|f| f.to_string()).collect(),
+        _ => Vec::new(),
+    };
+
+    let candidates = candidates
+        .into_iter()
+        .filter(|c| c.to_ascii_lowercase().starts_with(&prefix.to_ascii_lowercase()))
+        .collect();
+    (start, candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial_bridge::default_devices;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(
+            ReplCommand::parse("insert note 0x00 50.00 BRL").unwrap(),
+            ReplCommand::Insert { cash: Cash::Note, address: 0x00, value: 5000, currency: Some(*b"BRL") }
+        );
+        assert_eq!(
+            ReplCommand::parse("insert coin 16 0.25").unwrap(),
+            ReplCommand::Insert { cash: Cash::Coin, address: 0x10, value: 25, currency: None }
+        );
+        assert_eq!(ReplCommand::parse("levels").unwrap(), ReplCommand::Levels { address: None });
+        assert_eq!(
+            ReplCommand::parse("levels 0x00 2 10").unwrap(),
+            ReplCommand::SetLevel { address: 0x00, value: 200, count: 10 }
+        );
+        assert!(ReplCommand::parse("1").is_err());
+        assert!(ReplCommand::parse("insert coin 0x10 0.255").is_err());
+    }
+
+    #[test]
+    fn test_insert_validates_channels() {
+        let devices: Devices = Arc::new(Mutex::new(default_devices()));
+        for address in [0x00, 0x10] {
+            execute(&devices, &ReplCommand::Enable { address, enabled: true }).unwrap();
+        }
+
+        // R$2 is an NV200 channel, $1 is not
+        execute(&devices, &ReplCommand::parse("insert note 0x00 2 BRL").unwrap()).unwrap();
+        assert!(execute(&devices, &ReplCommand::parse("insert note 0x00 1").unwrap()).is_err());
+        assert!(execute(&devices, &ReplCommand::parse("insert note 0x00 50 USD").unwrap()).is_err());
+
+        // 50¢ goes to the hopper because the address says so, not because of its value
+        execute(&devices, &ReplCommand::parse("insert coin 0x10 0.50").unwrap()).unwrap();
+        assert!(execute(&devices, &ReplCommand::parse("insert coin 0x10 0.30").unwrap()).is_err());
+
+        // Each device takes only its own kind of cash
+        let error = execute(&devices, &ReplCommand::parse("insert coin 0x00 2").unwrap()).unwrap_err();
+        assert_eq!(error.to_string(), "Device 0x00 (NV200 note validator) takes notes, not coins");
+        let error = execute(&devices, &ReplCommand::parse("insert note 0x10 0.50").unwrap()).unwrap_err();
+        assert_eq!(error.to_string(), "Device 0x10 (Smart Hopper) takes coins, not notes");
+
+        let devices = devices.lock().unwrap();
+        assert_eq!(devices[&0x00].status().queued_events, vec!["RESET", "READ(ch 1)", "CREDIT(ch 1)"]);
+        assert_eq!(devices[&0x10].status().queued_events.len(), 2);
+    }
+
+    #[test]
+    fn test_complete() {
+        let devices = default_devices();
+        assert_eq!(complete(&devices, "in").1, vec!["insert"]);
+        assert_eq!(complete(&devices, "insert n"), (7, vec!["note".to_string()]));
+        assert_eq!(complete(&devices, "insert coin ").1, vec!["0x00", "0x10"]);
+        assert_eq!(complete(&devices, "insert note 0x00 5").1, vec!["5.00", "50.00"]);
+        assert_eq!(complete(&devices, "fault 0x00 c").1, vec!["cashbox_removed"]);
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::bill_emulator::DeviceState;
+use crate::capture::{CaptureRecord, Direction};
+use crate::essp_protocol::*;
+
+/// A host command from a capture together with the device's recorded answer
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    pub command: CaptureRecord,
+    pub response: Option<CaptureRecord>,
+}
+
+/// Pair each host frame with the next device frame from the same address
+pub fn pair_transactions(records: &[CaptureRecord]) -> Vec<Transaction> {
+    let mut transactions = Vec::new();
+    let mut pending: HashMap<u8, usize> = HashMap::new();
+
+    // Nothing to replay from bytes that were not a frame
+    for record in records.iter().filter(|record| !record.undecodable) {
+        match record.direction {
+            Direction::HostToDevice => {
+                pending.insert(record.address, transactions.len());
+                transactions.push(Transaction {
+                    command: record.clone(),
+                    response: None,
+                });
+            }
+            Direction::DeviceToHost => {
+                if let Some(index) = pending.remove(&record.address) {
+                    transactions[index].response = Some(record.clone());
+                }
+            }
+        }
+    }
+
+    transactions
+}
+
+/// Answers the host with responses recorded in a capture.
+///
+/// Responses are matched on the exact command bytes first and on the command
+/// code alone as a fallback. Recorded answers to the same command are played
+/// back in order and the last one is repeated once they run out.
+#[derive(Debug, Default)]
+pub struct ReplayDevice {
+    exact: HashMap<(u8, Vec<u8>), VecDeque<Vec<u8>>>,
+    by_code: HashMap<(u8, u8), VecDeque<Vec<u8>>>,
+}
+
+impl ReplayDevice {
+    pub fn from_records(records: &[CaptureRecord]) -> Self {
+        let mut replay = Self::default();
+        for transaction in pair_transactions(records) {
+            let Some(response) = transaction.response else {
+                continue;
+            };
+            let address = transaction.command.address;
+            let command = transaction.command.data;
+            if let Some(&code) = command.first() {
+                replay
+                    .by_code
+                    .entry((address, code))
+                    .or_default()
+                    .push_back(response.data.clone());
+            }
+            replay
+                .exact
+                .entry((address, command))
+                .or_default()
+                .push_back(response.data);
+        }
+        replay
+    }
+
+    /// Number of distinct recorded commands
+    pub fn len(&self) -> usize {
+        self.exact.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exact.is_empty()
+    }
+
+    /// Recorded response data (status byte first) for a command, if any
+    pub fn respond(&mut self, address: u8, cmd: &[u8]) -> Option<Vec<u8>> {
+        if let Some(queue) = self.exact.get_mut(&(address, cmd.to_vec())) {
+            return Some(Self::next(queue));
+        }
+        let code = *cmd.first()?;
+        self.by_code.get_mut(&(address, code)).map(Self::next)
+    }
+
+    fn next(queue: &mut VecDeque<Vec<u8>>) -> Vec<u8> {
+        if queue.len() > 1 {
+            queue.pop_front().unwrap_or_default()
+        } else {
+            queue.front().cloned().unwrap_or_default()
+        }
+    }
+}
+
+/// A response from `DeviceState` that differs from the recorded one
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    /// Index of the transaction in the capture
+    pub index: usize,
+    pub timestamp_us: u64,
+    pub address: u8,
+    pub command: Vec<u8>,
+    pub expected: Vec<u8>,
+    pub actual: Vec<u8>,
+}
+
+/// Outcome of replaying a capture against the emulator
+#[derive(Debug, Clone, Default)]
+pub struct ReplayReport {
+    pub transactions: usize,
+    /// Commands whose recorded response was missing from the capture
+    pub unanswered: usize,
+    /// Commands for addresses with no emulated device
+    pub unknown_address: usize,
+    pub divergences: Vec<Divergence>,
+}
+
+impl ReplayReport {
+    pub fn passed(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+/// Act as the host: send every recorded command to `devices` and compare the
+/// emulator's responses with the recorded ones.
+pub fn replay_host(records: &[CaptureRecord], devices: &mut HashMap<u8, DeviceState>) -> ReplayReport {
+    let mut report = ReplayReport::default();
+
+    for (index, transaction) in pair_transactions(records).into_iter().enumerate() {
+        report.transactions += 1;
+        let command = &transaction.command;
+
+        let Some(device) = devices.get_mut(&command.address) else {
+            report.unknown_address += 1;
+            continue;
+        };
+        let (status, data) = device.handle_sequenced_command(command.sequence_flag, &command.data);
+
+        let Some(response) = transaction.response else {
+            report.unanswered += 1;
+            continue;
+        };
+
+        let mut actual = vec![status];
+        actual.extend_from_slice(&data);
+        if actual != response.data {
+            report.divergences.push(Divergence {
+                index,
+                timestamp_us: command.timestamp_us,
+                address: command.address,
+                command: command.data.clone(),
+                expected: response.data,
+                actual,
+            });
+        }
+    }
+
+    report
+}
+
+/// Human readable summary of a divergence
+pub fn describe_divergence(divergence: &Divergence) -> String {
+    let code = divergence.command.first().copied().unwrap_or(0);
+    format!(
+        "#{} [0x{:02X}] {}: expected {:02X?}, emulator sent {:02X?}",
+        divergence.index,
+        divergence.address,
+        command_name(code),
+        divergence.expected,
+        divergence.actual
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::FrameDecoder;
+
+    fn record_session(commands: &[(u8, Vec<u8>)]) -> Vec<CaptureRecord> {
+        let mut devices = HashMap::new();
+        devices.insert(0x00, DeviceState::new_note_device(0x00));
+        let mut decoder = FrameDecoder::new();
+        let mut records = Vec::new();
+
+        for (i, (sequence, cmd)) in commands.iter().enumerate() {
+            let packet = EsspPacket::new(*sequence, cmd.clone());
+            let (status, data) = devices
+                .get_mut(&0x00)
+                .unwrap()
+                .handle_sequenced_command(sequence & 0x80 != 0, cmd);
+            let response = build_response(*sequence, status, &data);
+            records.push(decoder.decode_packet(Direction::HostToDevice, &packet, &packet.to_bytes(), i as u64));
+            records.push(decoder.decode_packet(Direction::DeviceToHost, &response, &response.to_bytes(), i as u64));
+        }
+        records
+    }
+
+    #[test]
+    fn test_replay_device_plays_responses_in_order() {
+        let records = record_session(&[
+            (0x80, vec![CMD_SYNC]),
+            (0x00, vec![CMD_POLL]),
+            (0x80, vec![CMD_POLL]),
+        ]);
+        let mut replay = ReplayDevice::from_records(&records);
+
+        assert_eq!(replay.respond(0x00, &[CMD_SYNC]), Some(vec![RESPONSE_OK]));
+        assert_eq!(replay.respond(0x00, &[CMD_POLL]), Some(vec![RESPONSE_OK, 1, EVENT_RESET]));
+        assert_eq!(replay.respond(0x00, &[CMD_POLL]), Some(vec![RESPONSE_OK, 1, EVENT_DISABLED]));
+        // Last answer repeats once the recording runs out
+        assert_eq!(replay.respond(0x00, &[CMD_POLL]), Some(vec![RESPONSE_OK, 1, EVENT_DISABLED]));
+        assert_eq!(replay.respond(0x10, &[CMD_POLL]), None);
+    }
+
+    #[test]
+    fn test_replay_host_reports_divergence() {
+        let mut records = record_session(&[(0x80, vec![CMD_SYNC]), (0x00, vec![CMD_POLL])]);
+
+        let mut devices = HashMap::new();
+        devices.insert(0x00, DeviceState::new_note_device(0x00));
+        assert!(replay_host(&records, &mut devices).passed());
+
+        // Pretend the field device reported a credit on that poll
+        records[3].data = vec![RESPONSE_OK, 1, EVENT_CREDIT, 1];
+        let mut devices = HashMap::new();
+        devices.insert(0x00, DeviceState::new_note_device(0x00));
+        let report = replay_host(&records, &mut devices);
+        assert_eq!(report.transactions, 2);
+        assert_eq!(report.divergences.len(), 1);
+        assert_eq!(report.divergences[0].index, 1);
+    }
+}
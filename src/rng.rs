@@ -0,0 +1,43 @@
+/// Small xorshift64* generator so that runs can be reproduced from a seed
+#[derive(Debug, Clone)]
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        // xorshift must never start from zero, which a seed equal to K gives
+        const K: u64 = 0x9E37_79B9_7F4A_7C15;
+        let s = seed ^ K;
+        Self(if s == 0 { K } else { s })
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform value in `low..high` (high exclusive)
+    pub(crate) fn range(&mut self, low: usize, high: usize) -> usize {
+        low + (self.next_u64() % (high - low) as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seed_equal_to_mixing_constant() {
+        // This seed cancels the mixing constant; a zero state would only give zeros
+        let mut rng = Rng::new(0x9E37_79B9_7F4A_7C15);
+        let values: Vec<f64> = (0..4).map(|_| rng.next_f64()).collect();
+        assert!(values.iter().any(|&v| v != 0.0));
+    }
+}
@@ -0,0 +1,195 @@
+use anyhow::{anyhow, Context, Result};
+use evdev::Key;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::gs1::{expand_placeholders, EOT, GS, RS};
+use crate::symbology::CodeId;
+use crate::timing::TimingProfile;
+
+/// Key sequence sent after the data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Terminator {
+    None,
+    #[default]
+    Enter,
+    KeypadEnter,
+    Tab,
+    /// Ctrl+M then Ctrl+J, i.e. CR LF as a terminal application reads them
+    CrLf,
+}
+
+impl Terminator {
+    pub const ALL: [Self; 5] = [Self::Enter, Self::KeypadEnter, Self::Tab, Self::CrLf, Self::None];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::None => "None",
+            Self::Enter => "Enter",
+            Self::KeypadEnter => "Keypad Enter",
+            Self::Tab => "Tab",
+            Self::CrLf => "CR LF (Ctrl+M Ctrl+J)",
+        }
+    }
+}
+
+/// How GS, RS, EOT and other ASCII control characters in the data are typed
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlChars {
+    /// Ctrl with the character 0x40 above, e.g. Ctrl+] for GS, Ctrl+D for EOT
+    #[default]
+    CtrlKey,
+    /// Alt held while typing the code on the keypad, e.g. Alt+029 for GS
+    AltKeypad,
+    /// Typed as text, e.g. `{ "replace": { "gs": "~" } }`; other control
+    /// characters fall back to the Ctrl key
+    Replace {
+        #[serde(default)]
+        gs: String,
+        #[serde(default)]
+        rs: String,
+        #[serde(default)]
+        eot: String,
+    },
+}
+
+impl ControlChars {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::CtrlKey => "Ctrl key (Ctrl+])",
+            Self::AltKeypad => "Alt + keypad code",
+            Self::Replace { .. } => "Replacement text",
+        }
+    }
+
+    /// Replacement text of `c`, if any
+    pub fn replacement(&self, c: char) -> Option<&str> {
+        let Self::Replace { gs, rs, eot } = self else {
+            return None;
+        };
+        let text = match c {
+            GS => gs,
+            RS => rs,
+            EOT => eot,
+            _ => return None,
+        };
+        (!text.is_empty()).then_some(text.as_str())
+    }
+}
+
+/// Data editing of the emulated scanner, stored as JSON, e.g.
+///
+/// ```json
+/// { "prefix": "", "suffix": "", "terminator": "tab", "focus_key": "KEY_F12",
+///   "code_id": "aim", "control_chars": "ctrl_key", "timing": { "baud": 9600 },
+///   "jitter_percent": 10 }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScannerConfig {
+    /// Typed before every barcode; may hold placeholders such as `<STX>`,
+    /// see `gs1::expand_placeholders`
+    pub prefix: String,
+    /// Symbology identifier typed between the prefix and the data, for scans
+    /// with a known symbology
+    pub code_id: CodeId,
+    /// Typed after every barcode, before the terminator; may hold placeholders
+    pub suffix: String,
+    pub terminator: Terminator,
+    /// Key tapped before the data, e.g. KEY_F12 to focus an input field
+    pub focus_key: Option<String>,
+    pub control_chars: ControlChars,
+    /// Key event timing, 115200 baud by default
+    pub timing: TimingProfile,
+    /// Random variation of every delay, in percent
+    pub jitter_percent: u8,
+}
+
+impl ScannerConfig {
+    /// Saved configuration, or the default one if `path` does not exist
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read scanner config {}", path.display()))?;
+        let config: Self = serde_json::from_str(&text)
+            .with_context(|| format!("Invalid scanner config {}", path.display()))?;
+        config.focus_key()?;
+        Ok(config)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write scanner config {}", path.display()))
+    }
+
+    /// The prefix with its placeholders expanded
+    pub fn prefix_text(&self) -> String {
+        expand_placeholders(&self.prefix)
+    }
+
+    /// The suffix with its placeholders expanded
+    pub fn suffix_text(&self) -> String {
+        expand_placeholders(&self.suffix)
+    }
+
+    /// The focus key as an evdev key, None when not configured
+    pub fn focus_key(&self) -> Result<Option<Key>> {
+        match self.focus_key.as_deref().map(str::trim) {
+            None | Some("") => Ok(None),
+            Some(name) => Key::from_str(name)
+                .map(Some)
+                .map_err(|_| anyhow!("Unknown focus key '{}'", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_round_trip() {
+        let path = std::env::temp_dir().join(format!("virtusdev-scanner-{}/scanner.json", std::process::id()));
+        assert_eq!(ScannerConfig::load(&path).unwrap(), ScannerConfig::default());
+
+        let config = ScannerConfig {
+            prefix: "[".to_string(),
+            code_id: CodeId::Aim,
+            suffix: "]".to_string(),
+            terminator: Terminator::CrLf,
+            focus_key: Some("KEY_F12".to_string()),
+            control_chars: ControlChars::Replace { gs: "~".to_string(), rs: String::new(), eot: String::new() },
+            timing: TimingProfile::HumanTypist,
+            jitter_percent: 15,
+        };
+        config.save(&path).unwrap();
+        let loaded = ScannerConfig::load(&path).unwrap();
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        assert_eq!(loaded, config);
+        assert_eq!(loaded.focus_key().unwrap(), Some(Key::KEY_F12));
+    }
+
+    #[test]
+    fn test_partial_and_invalid_configs() {
+        let config: ScannerConfig = serde_json::from_str(r#"{ "terminator": "keypad_enter" }"#).unwrap();
+        assert_eq!(config.terminator, Terminator::KeypadEnter);
+        assert_eq!(config.focus_key().unwrap(), None);
+
+        let config = ScannerConfig { focus_key: Some("KEY_NOPE".to_string()), ..Default::default() };
+        assert!(config.focus_key().is_err());
+
+        let config: ScannerConfig = serde_json::from_str(r#"{ "control_chars": { "replace": { "gs": "<GS>" } } }"#).unwrap();
+        assert_eq!(config.control_chars.replacement(GS), Some("<GS>"));
+        assert_eq!(config.control_chars.replacement(RS), None);
+    }
+}
@@ -0,0 +1,346 @@
+use anyhow::{anyhow, Context, Result};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::bill_emulator::{DeviceFault, DeviceState, PollEvent};
+use crate::capture::{CaptureRecord, Direction};
+use crate::control::Devices;
+use crate::essp_protocol::*;
+use crate::repl::parse_money;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const NOTE_ADDRESS: u8 = 0x00;
+const COIN_ADDRESS: u8 = 0x10;
+
+/// What a `wait for` / `expect` step is looking for
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expectation {
+    /// Host sends a command, optionally with an amount (PAYOUT)
+    Command { code: u8, amount: Option<u32> },
+    /// Device reports a poll event to the host
+    Event { code: u8 },
+}
+
+/// One scenario statement
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    InsertNote { address: u8, value: u32 },
+    InsertCoin { address: u8, value: u32 },
+    InjectFault { address: u8, fault: DeviceFault },
+    SetLevel { address: u8, value: u32, count: u16 },
+    SetEnabled { address: u8, enabled: bool },
+    Sleep(Duration),
+    Expect {
+        expectation: Expectation,
+        address: Option<u8>,
+        timeout: Duration,
+    },
+}
+
+/// A step with its position in the source, for error messages
+#[derive(Debug, Clone)]
+pub struct ScenarioStep {
+    pub line: usize,
+    pub text: String,
+    pub step: Step,
+}
+
+/// A parsed scenario file
+#[derive(Debug, Clone, Default)]
+pub struct Scenario {
+    pub steps: Vec<ScenarioStep>,
+}
+
+/// Amount in cents: "2000" is cents, "20.00" is currency units
+fn parse_amount(s: &str) -> Result<u32> {
+    if s.contains('.') {
+        parse_money(s)
+    } else {
+        s.parse().with_context(|| format!("invalid amount '{}'", s))
+    }
+}
+
+/// Durations like "3s", "500ms" or "1.5s"
+fn parse_duration(s: &str) -> Result<Duration> {
+    let invalid = || anyhow!("invalid duration '{}' (use e.g. 3s or 500ms)", s);
+    if let Some(ms) = s.strip_suffix("ms") {
+        return Ok(Duration::from_millis(ms.parse().map_err(|_| invalid())?));
+    }
+    let secs = s.strip_suffix('s').unwrap_or(s);
+    let secs: f64 = secs.parse().map_err(|_| invalid())?;
+    Ok(Duration::from_secs_f64(secs))
+}
+
+/// Strip a trailing `on ADDR` and `within DURATION` from the words of a statement
+fn parse_modifiers(words: &mut Vec<&str>) -> Result<(Option<u8>, Option<Duration>)> {
+    let mut address = None;
+    let mut timeout = None;
+    while words.len() >= 2 {
+        let n = words.len();
+        match words[n - 2] {
+            "on" => address = Some(parse_address(words[n - 1])?),
+            "within" => timeout = Some(parse_duration(words[n - 1])?),
+            _ => break,
+        }
+        words.truncate(n - 2);
+    }
+    Ok((address, timeout))
+}
+
+fn parse_expectation(words: &[&str]) -> Result<Expectation> {
+    match words {
+        ["event", name] => Ok(Expectation::Event {
+            code: event_code(name).ok_or_else(|| anyhow!("unknown poll event '{}'", name))?,
+        }),
+        ["host", rest @ ..] => parse_expectation(rest),
+        [name, rest @ ..] if rest.len() <= 1 => {
+            let code = command_code(name).ok_or_else(|| anyhow!("unknown command '{}'", name))?;
+            let amount = rest.first().map(|a| parse_amount(a)).transpose()?;
+            Ok(Expectation::Command { code, amount })
+        }
+        _ => Err(anyhow!("expected COMMAND [AMOUNT] or event EVENT")),
+    }
+}
+
+fn parse_step(statement: &str) -> Result<Step> {
+    let mut words: Vec<&str> = statement.split_whitespace().collect();
+    let (address, timeout) = parse_modifiers(&mut words)?;
+
+    let step = match words.as_slice() {
+        ["insert", "coin", value] => Step::InsertCoin {
+            address: address.unwrap_or(COIN_ADDRESS),
+            value: parse_amount(value)?,
+        },
+        ["insert", "note", value] | ["insert", value] => Step::InsertNote {
+            address: address.unwrap_or(NOTE_ADDRESS),
+            value: parse_amount(value)?,
+        },
+        ["inject", fault] => Step::InjectFault {
+            address: address.unwrap_or(NOTE_ADDRESS),
+            fault: fault.parse()?,
+        },
+        ["set", "level", value, count] => Step::SetLevel {
+            address: address.unwrap_or(NOTE_ADDRESS),
+            value: parse_amount(value)?,
+            count: count.parse().with_context(|| format!("invalid count '{}'", count))?,
+        },
+        ["enable"] | ["disable"] => Step::SetEnabled {
+            address: address.unwrap_or(NOTE_ADDRESS),
+            enabled: words[0] == "enable",
+        },
+        ["sleep", duration] => Step::Sleep(parse_duration(duration)?),
+        ["wait", "for", rest @ ..] | ["expect", rest @ ..] => Step::Expect {
+            expectation: parse_expectation(rest)?,
+            address,
+            timeout: timeout.unwrap_or(DEFAULT_TIMEOUT),
+        },
+        _ => return Err(anyhow!("unknown statement")),
+    };
+    Ok(step)
+}
+
+impl Scenario {
+    /// Parse a scenario. Statements are separated by newlines or `;`, `#` starts a comment.
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut steps = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let line_no = index + 1;
+            let code = line.split('#').next().unwrap_or("");
+            for statement in code.split(';') {
+                let statement = statement.trim();
+                if statement.is_empty() {
+                    continue;
+                }
+                let step = parse_step(&statement.to_ascii_lowercase())
+                    .with_context(|| format!("line {}: '{}'", line_no, statement))?;
+                steps.push(ScenarioStep {
+                    line: line_no,
+                    text: statement.to_string(),
+                    step,
+                });
+            }
+        }
+        Ok(Self { steps })
+    }
+
+    pub fn load(path: &std::path::Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read scenario {}", path.display()))?;
+        Self::parse(&source)
+    }
+
+    /// Run every step in order against `devices`, watching the frames on `events`.
+    /// Stops at the first failing step.
+    pub fn run(&self, devices: &Devices, events: &Receiver<CaptureRecord>) -> ScenarioResult {
+        for (index, step) in self.steps.iter().enumerate() {
+            println!("▶ line {}: {}", step.line, step.text);
+            if let Err(e) = run_step(&step.step, devices, events) {
+                return ScenarioResult {
+                    passed: index,
+                    failure: Some(format!("line {}: '{}': {}", step.line, step.text, e)),
+                };
+            }
+        }
+        ScenarioResult {
+            passed: self.steps.len(),
+            failure: None,
+        }
+    }
+}
+
+/// Outcome of a scenario run
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScenarioResult {
+    /// Number of steps that passed
+    pub passed: usize,
+    pub failure: Option<String>,
+}
+
+impl ScenarioResult {
+    pub fn success(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+fn with_device(devices: &Devices, address: u8, f: impl FnOnce(&mut DeviceState) -> bool) -> Result<()> {
+    let mut devices = devices.lock().unwrap();
+    let device = devices
+        .get_mut(&address)
+        .ok_or_else(|| anyhow!("no device at address 0x{:02X}", address))?;
+    if f(device) {
+        Ok(())
+    } else {
+        Err(anyhow!("device 0x{:02X} did not accept it (disabled?)", address))
+    }
+}
+
+fn matches(expectation: &Expectation, address: Option<u8>, record: &CaptureRecord) -> bool {
+    if address.is_some_and(|a| a != record.address) {
+        return false;
+    }
+    match (expectation, record.direction) {
+        (Expectation::Command { code, amount }, Direction::HostToDevice) => {
+            record.data.first() == Some(code)
+                && amount.is_none_or(|amount| {
+                    record.data.get(1..5).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])) == Some(amount)
+                })
+        }
+        (Expectation::Event { code }, Direction::DeviceToHost) => {
+            record.command.as_deref() == Some(command_name(CMD_POLL))
+                && record.data.first() == Some(&RESPONSE_OK)
+                && PollEvent::parse_poll_data(&record.data[1..])
+                    .is_some_and(|events| events.iter().any(|e| e.event_code == *code))
+        }
+        _ => false,
+    }
+}
+
+fn run_step(step: &Step, devices: &Devices, events: &Receiver<CaptureRecord>) -> Result<()> {
+    match step {
+        Step::InsertNote { address, value } => with_device(devices, *address, |d| d.insert_note(*value)),
+        Step::InsertCoin { address, value } => with_device(devices, *address, |d| d.insert_coins(*value)),
+        Step::InjectFault { address, fault } => with_device(devices, *address, |d| {
+            d.inject_fault(*fault);
+            true
+        }),
+        Step::SetLevel { address, value, count } => with_device(devices, *address, |d| {
+            d.set_level(*value, *count);
+            true
+        }),
+        Step::SetEnabled { address, enabled } => with_device(devices, *address, |d| {
+            d.set_enabled(*enabled);
+            true
+        }),
+        Step::Sleep(duration) => {
+            thread::sleep(*duration);
+            Ok(())
+        }
+        Step::Expect { expectation, address, timeout } => {
+            let deadline = Instant::now() + *timeout;
+            loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                match events.recv_timeout(remaining) {
+                    Ok(record) if matches(expectation, *address, &record) => return Ok(()),
+                    Ok(_) => continue,
+                    Err(RecvTimeoutError::Timeout) => {
+                        return Err(anyhow!("not seen within {:.1}s", timeout.as_secs_f64()))
+                    }
+                    Err(RecvTimeoutError::Disconnected) => return Err(anyhow!("serial bridge stopped")),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::FrameDecoder;
+    use crate::serial_bridge::default_devices;
+    use std::sync::{mpsc, Arc, Mutex};
+
+    #[test]
+    fn test_parse_statements() {
+        let scenario = Scenario::parse(
+            "wait for ENABLE; insert 2000 on 0x00\n\
+             expect host PAYOUT 500 within 3s # change\n\
+             inject jam; expect RESET\n\
+             insert coin 0.25; expect event CREDIT on 0x00 within 500ms",
+        )
+        .unwrap();
+        let steps: Vec<Step> = scenario.steps.into_iter().map(|s| s.step).collect();
+
+        assert_eq!(steps[0], Step::Expect {
+            expectation: Expectation::Command { code: CMD_ENABLE, amount: None },
+            address: None,
+            timeout: DEFAULT_TIMEOUT,
+        });
+        assert_eq!(steps[1], Step::InsertNote { address: 0x00, value: 2000 });
+        assert_eq!(steps[2], Step::Expect {
+            expectation: Expectation::Command { code: CMD_PAYOUT, amount: Some(500) },
+            address: None,
+            timeout: Duration::from_secs(3),
+        });
+        assert_eq!(steps[3], Step::InjectFault { address: 0x00, fault: DeviceFault::Jam });
+        assert_eq!(steps[5], Step::InsertCoin { address: 0x10, value: 25 });
+        assert_eq!(steps[6], Step::Expect {
+            expectation: Expectation::Event { code: EVENT_CREDIT },
+            address: Some(0x00),
+            timeout: Duration::from_millis(500),
+        });
+    }
+
+    #[test]
+    fn test_parse_error_reports_line() {
+        let error = Scenario::parse("sleep 1s\ninsert lots").unwrap_err();
+        assert!(format!("{:#}", error).contains("line 2"));
+    }
+
+    #[test]
+    fn test_amounts() {
+        assert_eq!(parse_amount("2000").unwrap(), 2000);
+        assert_eq!(parse_amount("20.00").unwrap(), 2000);
+        assert_eq!(parse_amount("0.25").unwrap(), 25);
+        for invalid in ["-5.00", "-5", "1e30", "99999999.99", "4294967296", "1.005", "1.2.3"] {
+            assert!(parse_amount(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_run_against_recorded_stream() {
+        let devices: Devices = Arc::new(Mutex::new(default_devices()));
+        let (tx, rx) = mpsc::channel();
+        let mut decoder = FrameDecoder::new();
+        let payout = EsspPacket::new(0x80, vec![CMD_PAYOUT, 0xF4, 0x01, 0x00, 0x00, b'B', b'R', b'L']);
+        tx.send(decoder.decode_packet(Direction::HostToDevice, &payout, &[], 0)).unwrap();
+
+        let scenario = Scenario::parse("enable; insert 20.00; expect PAYOUT 500 within 100ms").unwrap();
+        assert!(scenario.run(&devices, &rx).success());
+
+        let scenario = Scenario::parse("expect PAYOUT within 50ms").unwrap();
+        let result = scenario.run(&devices, &rx);
+        assert_eq!(result.passed, 0);
+        assert!(result.failure.unwrap().contains("not seen"));
+    }
+}
@@ -1,70 +1,137 @@
 use anyhow::{Context, Result};
 use nix::pty::{openpty, OpenptyResult};
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Duration;
 
 use crate::bill_emulator::DeviceState;
+use crate::capture::{timestamp_now, CaptureRecord, CaptureSink, Direction, FrameDecoder};
 use crate::essp_protocol::*;
+use crate::event_loop::{self, EventSource, ShutdownHandle};
+use crate::line_noise::LineNoise;
+use crate::replay::ReplayDevice;
+
+/// The device set of a standard kiosk bus: NV200 at 0x00 and Smart Hopper at 0x10
+pub fn default_devices() -> HashMap<u8, DeviceState> {
+    let mut devices = HashMap::new();
+    devices.insert(0x00, DeviceState::new_note_device(0x00));
+    devices.insert(0x10, DeviceState::new_coin_device(0x10));
+    devices
+}
+
+/// Create a pty pair and return the non-blocking master and the slave path.
+/// The slave is closed, the host opens it by path.
+pub(crate) fn open_pty() -> Result<(File, String)> {
+    // Create pty pair
+    let OpenptyResult { master, slave } = openpty(None, None)
+        .context("Failed to create pty pair")?;
+
+    // Get slave path using raw fd
+    let master_fd = master.as_raw_fd();
+
+    // Unlock and grant access to slave PTY
+    unsafe {
+        libc::grantpt(master_fd);
+        libc::unlockpt(master_fd);
+    }
+
+    let slave_path = unsafe {
+        let path_ptr = libc::ptsname(master_fd);
+        if path_ptr.is_null() {
+            return Err(anyhow::anyhow!("Failed to get slave pty path"));
+        }
+        std::ffi::CStr::from_ptr(path_ptr)
+            .to_string_lossy()
+            .into_owned()
+    };
+
+    // Close slave fd (we only need the path)
+    drop(slave);
+
+    // The event loop reads until EAGAIN
+    unsafe {
+        let flags = libc::fcntl(master_fd, libc::F_GETFL);
+        libc::fcntl(master_fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+    }
+
+    Ok((File::from(master), slave_path))
+}
 
 pub struct SerialBridge {
-    master_fd: RawFd,
+    /// Bus name for logs and captures when several bridges run side by side
+    name: Option<String>,
+    master: File,
     slave_path: String,
+    /// Splits the bytes received from the host into frames
+    scanner: FrameScanner,
+    shutdown: ShutdownHandle,
     devices: Arc<Mutex<HashMap<u8, DeviceState>>>,
+    line_noise: Option<LineNoise>,
+    decoder: FrameDecoder,
+    captures: Vec<Box<dyn CaptureSink>>,
+    replay: Option<ReplayDevice>,
 }
 
 impl SerialBridge {
+    /// Bridge with the standard kiosk devices (see `default_devices`)
     pub fn new() -> Result<Self> {
-        // Create pty pair
-        let OpenptyResult { master, slave } = openpty(None, None)
-            .context("Failed to create pty pair")?;
-
-        // Get slave path using raw fd
-        let master_fd = master.as_raw_fd();
-        let slave_fd = slave.as_raw_fd();
-        
-        // Unlock and grant access to slave PTY
-        unsafe {
-            libc::grantpt(master_fd);
-            libc::unlockpt(master_fd);
-        }
-        
-        let slave_path = unsafe {
-            let path_ptr = libc::ptsname(master_fd);
-            if path_ptr.is_null() {
-                return Err(anyhow::anyhow!("Failed to get slave pty path"));
-            }
-            std::ffi::CStr::from_ptr(path_ptr)
-                .to_string_lossy()
-                .into_owned()
-        };
-
-        // Close slave fd (we only need the path)
-        // slave fd will be closed automatically when it goes out of scope (I/O safety)
+        Self::with_devices(default_devices())
+    }
 
-        // Initialize devices
-        let mut devices_map = HashMap::new();
-        devices_map.insert(0x00, DeviceState::new_note_device(0x00));
-        devices_map.insert(0x10, DeviceState::new_coin_device(0x10));
+    /// Bridge serving the given devices, keyed by slave address
+    pub fn with_devices(devices: HashMap<u8, DeviceState>) -> Result<Self> {
+        let (master, slave_path) = open_pty()?;
 
         println!("✓ Virtual serial port created: {}", slave_path);
         println!("  Configure payment system to use this port:");
         println!("  sudo nano /etc/cloudpark/payment_config.yml");
         println!("  Set: bill_validator_serial: {}", slave_path);
 
-        // Extract raw fd before master goes out of scope
-        let master_fd = master.as_raw_fd();
-        std::mem::forget(master); // Prevent automatic close
-        
         Ok(Self {
-            master_fd,
+            name: None,
+            master,
             slave_path,
-            devices: Arc::new(Mutex::new(devices_map)),
+            scanner: FrameScanner::new(),
+            shutdown: ShutdownHandle::new()?,
+            devices: Arc::new(Mutex::new(devices)),
+            line_noise: None,
+            decoder: FrameDecoder::new(),
+            captures: Vec::new(),
+            replay: None,
         })
     }
 
+    /// Answer the host with recorded responses instead of the emulated devices
+    pub fn set_replay(&mut self, replay: ReplayDevice) {
+        println!("⏪ Replay mode: answering with {} recorded commands", replay.len());
+        self.replay = Some(replay);
+    }
+
+    /// Record every frame sent or received to `sink`
+    pub fn add_capture(&mut self, sink: Box<dyn CaptureSink>) {
+        self.captures.push(sink);
+    }
+
+    /// Inject line noise and protocol errors into every response sent from now on
+    pub fn set_line_noise(&mut self, line_noise: LineNoise) {
+        println!("⚡ Fault injection enabled (seed {})", line_noise.seed());
+        for rule in line_noise.rules() {
+            println!("   {:?}", rule);
+        }
+        self.line_noise = Some(line_noise);
+    }
+
+    /// Name this bridge in logs and captures
+    pub fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
     pub fn slave_path(&self) -> &str {
         &self.slave_path
     }
@@ -73,170 +140,141 @@ impl SerialBridge {
         Arc::clone(&self.devices)
     }
 
-    /// Start the serial communication loop
+    /// Handle that makes `run` return, e.g. from another thread or on SIGINT
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Serve the host until the shutdown handle is triggered
     pub fn run(&mut self) -> Result<()> {
         println!("🔄 Serial bridge running, waiting for connections...\n");
 
-        let mut buffer = Vec::new();
-        let mut read_buf = [0u8; 256];
-
-        loop {
-            // Read from serial port (non-blocking with timeout)
-            match Self::read_with_timeout(self.master_fd, &mut read_buf, Duration::from_millis(100)) {
-                Ok(n) if n > 0 => {
-                    println!("[RX] Received {} bytes: {:02X?}", n, &read_buf[..n]);
-                    buffer.extend_from_slice(&read_buf[..n]);
-                    
-                    // Try to parse packets from buffer
-                    while let Ok((packet, consumed)) = EsspPacket::from_bytes(&buffer) {
-                        println!("[PKT] Parsed packet: seq={}, data_len={}", packet.sequence, packet.data.len());
-                        self.handle_packet(&packet)?;
-                        buffer.drain(..consumed);
-                    }
-                    
-                    // If buffer has data but couldn't parse, show it
-                    if !buffer.is_empty() {
-                        println!("[BUF] Unparsed buffer: {:02X?}", buffer);
-                    }
-                }
-                Ok(_) => {
-                    // Timeout or no data, continue
-                    thread::sleep(Duration::from_millis(10));
-                }
-                Err(e) => {
-                    eprintln!("Read error: {}", e);
-                    thread::sleep(Duration::from_millis(100));
-                }
-            }
-        }
+        let shutdown = self.shutdown.clone();
+        let source: &mut dyn EventSource = self;
+        event_loop::run(&mut [source], &shutdown)
     }
 
-    fn read_with_timeout(fd: RawFd, buf: &mut [u8], timeout: Duration) -> Result<usize> {
-        // Set up fd_set for select
-        let mut read_fds = unsafe {
-            let mut fds: libc::fd_set = std::mem::zeroed();
-            libc::FD_ZERO(&mut fds);
-            libc::FD_SET(fd, &mut fds);
-            fds
-        };
-
-        let mut tv = libc::timeval {
-            tv_sec: timeout.as_secs() as libc::time_t,
-            tv_usec: timeout.subsec_micros() as libc::suseconds_t,
-        };
-
-        let result = unsafe {
-            libc::select(
-                fd + 1,
-                &mut read_fds,
-                std::ptr::null_mut(),
-                std::ptr::null_mut(),
-                &mut tv,
-            )
-        };
-
-        if result < 0 {
-            return Err(anyhow::anyhow!("select() failed"));
-        }
-
-        if result == 0 {
-            // Timeout
-            return Ok(0);
-        }
+    /// Run the bridge on tokio's blocking pool. Resolves when the shutdown
+    /// handle is triggered; the bridge is dropped and the PTY closed.
+    #[cfg(feature = "tokio")]
+    pub async fn run_async(mut self) -> Result<()> {
+        tokio::task::spawn_blocking(move || self.run())
+            .await
+            .context("Serial bridge task failed")?
+    }
 
-        // Data available, read it
-        let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
-        if n < 0 {
-            let errno = std::io::Error::last_os_error();
-            // EIO (I/O error) typically means no reader is connected to the slave PTY
-            // This is expected during startup before the payment system connects
-            if errno.raw_os_error() == Some(5) {
-                // EIO - no client connected yet, return 0 (no data) rather than error
-                return Ok(0);
+    fn process_input(&mut self, bytes: &[u8]) -> Result<()> {
+        for item in self.scanner.push(bytes) {
+            match item {
+                ScanItem::Frame(packet, frame) => {
+                    self.capture(Direction::HostToDevice, &packet, &frame);
+                    self.handle_packet(&packet)?;
+                }
+                // Line noise or a frame with a bad CRC, a real device ignores it
+                ScanItem::Garbage(garbage) => println!("[BUF] Discarded unparsed bytes: {:02X?}", garbage),
             }
-            return Err(anyhow::anyhow!("read() failed: {}", errno));
         }
-
-        Ok(n as usize)
+        Ok(())
     }
 
     fn handle_packet(&mut self, packet: &EsspPacket) -> Result<()> {
-        // The actual device address is embedded in the data or we need to track it
-        // For eSSP, the library (libessp.so) handles addressing via SSPAddress in SSP_COMMAND
-        // Since we're emulating at the serial level, we need to handle both devices
-        
-        // For now, let's route based on command type (simplified)
-        // In real implementation, address would be in a higher-level protocol wrapper
-        // We'll handle both devices for all commands
-        
         if packet.data.is_empty() {
             return Ok(());
         }
 
         let cmd_code = packet.data[0];
-        
-        // Determine device address (this is simplified - real protocol has addressing)
-        // For testing, we'll respond with the note device (0x00) by default
-        let device_addr = 0x00;
+
+        // SEQ/ADDR byte: bit 7 is the sequence flag, bits 0-6 the slave address
+        let sequence_flag = packet.sequence & 0x80 != 0;
+        let device_addr = packet.sequence & 0x7F;
+
+        if let Some(replay) = self.replay.as_mut() {
+            let response = match replay.respond(device_addr, &packet.data) {
+                Some(data) => EsspPacket::new(packet.sequence, data),
+                None => {
+                    println!("[REPLAY] No recorded response for {:02X?}", packet.data);
+                    build_response(packet.sequence, RESPONSE_COMMAND_NOT_KNOWN, &[])
+                }
+            };
+            self.log_transaction(device_addr, cmd_code, &packet.data, &response.data);
+            return self.send_response(cmd_code, &response);
+        }
 
         let response = {
             let mut devices = self.devices.lock().unwrap();
             if let Some(device) = devices.get_mut(&device_addr) {
-                let (status, response_data) = device.handle_command(&packet.data);
+                if device.is_retransmission(sequence_flag, &packet.data) {
+                    println!("[RETRY] 0x{:02X} {} repeated with same sequence flag, resending last response",
+                        device_addr, command_name(cmd_code));
+                }
+                let (status, response_data) = device.handle_sequenced_command(sequence_flag, &packet.data);
                 let response = build_response(packet.sequence, status, &response_data);
                 
                 // Log the transaction
-                Self::log_transaction(device_addr, cmd_code, &packet.data, &response_data);
+                self.log_transaction(device_addr, cmd_code, &packet.data, &response_data);
                 Some(response)
             } else {
+                // No slave at this address, a real bus stays silent
                 None
             }
         };
         
         if let Some(resp) = response {
-            self.send_response(&resp)?;
+            self.send_response(cmd_code, &resp)?;
         }
 
         Ok(())
     }
 
-    fn send_response(&mut self, response: &EsspPacket) -> Result<()> {
-        let bytes = response.to_bytes();
-        let n = unsafe {
-            libc::write(
-                self.master_fd,
-                bytes.as_ptr() as *const libc::c_void,
-                bytes.len(),
-            )
+    fn send_response(&mut self, cmd_code: u8, response: &EsspPacket) -> Result<()> {
+        let bytes = match self.line_noise.as_mut() {
+            Some(noise) => {
+                let faults = noise.select(cmd_code);
+                if !faults.is_empty() {
+                    println!("[FAULT] {} reply: {:?}", command_name(cmd_code), faults);
+                }
+                noise.apply(response, &faults)
+            }
+            None => response.to_bytes(),
         };
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        if !self.captures.is_empty() {
+            // Decode what goes on the wire, faults included
+            let records = self.decoder.decode_stream(Direction::DeviceToHost, &bytes, timestamp_now());
+            for record in records {
+                self.record_capture(record);
+            }
+        }
 
-        if n < 0 {
-            return Err(anyhow::anyhow!("Failed to write response"));
+        self.master.write_all(&bytes).context("Failed to write response")
+    }
+
+    fn capture(&mut self, direction: Direction, packet: &EsspPacket, frame: &[u8]) {
+        if self.captures.is_empty() {
+            return;
         }
+        let record = self.decoder.decode_packet(direction, packet, frame, timestamp_now());
+        self.record_capture(record);
+    }
 
-        Ok(())
+    fn record_capture(&mut self, mut record: CaptureRecord) {
+        record.bus = self.name.clone();
+        for sink in &mut self.captures {
+            if let Err(e) = sink.record(&record) {
+                eprintln!("Capture error: {}", e);
+            }
+        }
     }
 
-    fn log_transaction(device_addr: u8, cmd_code: u8, cmd_data: &[u8], response_data: &[u8]) {
-        let cmd_name = match cmd_code {
-            CMD_SYNC => "SYNC",
-            CMD_SETUP_REQUEST => "SETUP_REQUEST",
-            CMD_HOST_PROTOCOL => "HOST_PROTOCOL",
-            CMD_POLL => "POLL",
-            CMD_ENABLE => "ENABLE",
-            CMD_DISABLE => "DISABLE",
-            CMD_SET_INHIBITS => "SET_INHIBITS",
-            CMD_GET_ALL_LEVELS => "GET_ALL_LEVELS",
-            CMD_PAYOUT => "PAYOUT",
-            CMD_ENABLE_PAYOUT => "ENABLE_PAYOUT",
-            CMD_SETUP_ENCRYPTION => "SETUP_ENCRYPTION",
-            CMD_SET_ROUTE => "SET_ROUTE",
-            CMD_COIN_MECH_GLOBAL_INHIBIT => "COIN_MECH_GLOBAL_INHIBIT",
-            _ => "UNKNOWN",
-        };
+    fn log_transaction(&self, device_addr: u8, cmd_code: u8, cmd_data: &[u8], response_data: &[u8]) {
+        let cmd_name = command_name(cmd_code);
+        let bus = self.name.as_ref().map(|name| format!("{} ", name)).unwrap_or_default();
 
         println!(
-            "[0x{:02X}] {} (0x{:02X}) | CMD: {} bytes, RSP: {} bytes",
+            "[{}0x{:02X}] {} (0x{:02X}) | CMD: {} bytes, RSP: {} bytes",
+            bus,
             device_addr,
             cmd_name,
             cmd_code,
@@ -246,9 +284,30 @@ impl SerialBridge {
     }
 }
 
+impl EventSource for SerialBridge {
+    fn raw_fd(&self) -> RawFd {
+        self.master.as_raw_fd()
+    }
+
+    fn on_readable(&mut self) -> Result<()> {
+        let mut read_buf = [0u8; 256];
+        loop {
+            match self.master.read(&mut read_buf) {
+                Ok(0) => return Ok(()),
+                Ok(n) => self.process_input(&read_buf[..n])?,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                // EIO means no process has the slave PTY open. This is expected
+                // before the payment system connects and after it disconnects.
+                Err(e) if e.raw_os_error() == Some(libc::EIO) => return Ok(()),
+                Err(e) => return Err(e).context("read() failed"),
+            }
+        }
+    }
+}
+
 impl Drop for SerialBridge {
     fn drop(&mut self) {
-        unsafe { libc::close(self.master_fd) };
         println!("\n✗ Serial bridge closed");
     }
 }
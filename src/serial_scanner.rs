@@ -0,0 +1,643 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::device::{DeviceIdentity, BAUDRATE};
+use crate::event_loop::{self, EventSource, ShutdownHandle};
+use crate::serial_bridge::open_pty;
+use crate::sniffer::baud_rate;
+use crate::symbology::{CodeId, Symbology};
+use crate::timing::{char_time, wait_until};
+
+const STX: u8 = 0x02;
+const ETX: u8 = 0x03;
+const ENQ: u8 = 0x05;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+/// Start of a Honeywell serial command: SYN T CR, SYN U CR or SYN M CR
+const SYN: u8 = 0x16;
+/// Longest Honeywell menu command accepted without a terminator
+const MAX_MENU_LEN: usize = 256;
+/// How long a host may leave the port unread before a reply or scan is dropped
+const WRITE_STALL: Duration = Duration::from_secs(1);
+
+// Zebra SSI opcodes
+const SSI_CMD_ACK: u8 = 0xD0;
+const SSI_CMD_NAK: u8 = 0xD1;
+const SSI_START_DECODE: u8 = 0xE4;
+const SSI_STOP_DECODE: u8 = 0xE5;
+const SSI_BEEP: u8 = 0xE6;
+const SSI_LED_ON: u8 = 0xE7;
+const SSI_LED_OFF: u8 = 0xE8;
+const SSI_SCAN_ENABLE: u8 = 0xE9;
+const SSI_SCAN_DISABLE: u8 = 0xEA;
+/// Message source of the packets we send
+const SSI_SOURCE_DECODER: u8 = 0x00;
+// CMD_NAK reasons
+const SSI_NAK_RESEND: u8 = 0x01;
+const SSI_NAK_BAD_CONTEXT: u8 = 0x02;
+
+/// Bytes around each scan
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SerialFraming {
+    None,
+    #[default]
+    Cr,
+    CrLf,
+    /// STX before the data, ETX after it
+    StxEtx,
+}
+
+impl SerialFraming {
+    pub const ALL: [Self; 4] = [Self::None, Self::Cr, Self::CrLf, Self::StxEtx];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::None => "No framing",
+            Self::Cr => "CR",
+            Self::CrLf => "CR LF",
+            Self::StxEtx => "STX … ETX",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SerialScannerConfig {
+    pub framing: SerialFraming,
+    /// Code ID sent before the data of scans with a symbology
+    pub code_id: CodeId,
+    /// Bytes are paced at one character time of this rate
+    pub baud_rate: u32,
+}
+
+impl Default for SerialScannerConfig {
+    fn default() -> Self {
+        Self { framing: SerialFraming::default(), code_id: CodeId::None, baud_rate: BAUDRATE }
+    }
+}
+
+/// Settings the host changed through commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScannerStatus {
+    /// Disabled scanners ignore the trigger
+    pub enabled: bool,
+    /// Beep after each good read
+    pub good_read_beep: bool,
+    /// Beeps so far, requested by the host or after good reads
+    pub beeps: usize,
+}
+
+impl Default for ScannerStatus {
+    fn default() -> Self {
+        Self { enabled: true, good_read_beep: true, beeps: 0 }
+    }
+}
+
+/// Bytes sent for a scan: code ID, data and framing
+pub fn frame(config: &SerialScannerConfig, symbology: Option<Symbology>, data: &str) -> Result<Vec<u8>> {
+    let (data, code_id) = match symbology {
+        Some(symbology) => (symbology.encode(data)?, symbology.code_id(config.code_id)),
+        None => (data.to_string(), String::new()),
+    };
+    let mut bytes = Vec::new();
+    if config.framing == SerialFraming::StxEtx {
+        bytes.push(STX);
+    }
+    bytes.extend_from_slice(code_id.as_bytes());
+    bytes.extend_from_slice(data.as_bytes());
+    match config.framing {
+        SerialFraming::None => {}
+        SerialFraming::Cr => bytes.push(b'\r'),
+        SerialFraming::CrLf => bytes.extend_from_slice(b"\r\n"),
+        SerialFraming::StxEtx => bytes.push(ETX),
+    }
+    Ok(bytes)
+}
+
+/// SSI packet from the decoder: length, opcode, source, status, data and
+/// the two's complement of the byte sum
+fn ssi_packet(opcode: u8, data: &[u8]) -> Vec<u8> {
+    let mut packet = vec![(4 + data.len()) as u8, opcode, SSI_SOURCE_DECODER, 0x00];
+    packet.extend_from_slice(data);
+    packet.extend_from_slice(&ssi_checksum(&packet).to_be_bytes());
+    packet
+}
+
+fn ssi_checksum(bytes: &[u8]) -> u16 {
+    let sum = bytes.iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+    sum.wrapping_neg()
+}
+
+/// A command received from the host
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Request {
+    /// Honeywell SYN T CR
+    Trigger,
+    /// Honeywell SYN U CR
+    Untrigger,
+    /// Honeywell SYN M CR menu commands ("ALLENA1", "BEPBEP?") and the
+    /// terminator: '.' to store, '!' for the session only
+    Menu(Vec<Vec<u8>>, u8),
+    /// Zebra SSI packet
+    Ssi { opcode: u8, data: Vec<u8> },
+    /// SSI packet with a bad checksum
+    BadChecksum,
+    /// Byte that starts no command
+    Garbage(u8),
+}
+
+/// Parse the command at the start of `input`. Returns the request and the
+/// number of bytes it used, or None when more bytes are needed.
+fn parse_request(input: &[u8]) -> Option<(Request, usize)> {
+    let &first = input.first()?;
+    if first == SYN {
+        if input.len() < 3 {
+            return None;
+        }
+        return match (input[1], input[2]) {
+            (b'T', b'\r') => Some((Request::Trigger, 3)),
+            (b'U', b'\r') => Some((Request::Untrigger, 3)),
+            (b'M', b'\r') => {
+                let body = &input[3..];
+                match body.iter().position(|&b| b == b'.' || b == b'!') {
+                    Some(end) => {
+                        let commands = body[..end].split(|&b| b == b';').map(<[u8]>::to_vec).collect();
+                        Some((Request::Menu(commands, body[end]), 3 + end + 1))
+                    }
+                    None if body.len() > MAX_MENU_LEN => Some((Request::Garbage(first), 1)),
+                    None => None,
+                }
+            }
+            _ => Some((Request::Garbage(first), 1)),
+        };
+    }
+
+    // SSI: the length byte counts everything but the checksum
+    let len = first as usize;
+    if len < 4 {
+        return Some((Request::Garbage(first), 1));
+    }
+    // All SSI opcodes have the high bit set, so a stray CR or LF is skipped
+    // instead of waiting for a packet of that length
+    if input.get(1).is_some_and(|&opcode| opcode < 0x80) {
+        return Some((Request::Garbage(first), 1));
+    }
+    if input.len() < len + 2 {
+        return None;
+    }
+    let checksum = u16::from_be_bytes([input[len], input[len + 1]]);
+    if checksum != ssi_checksum(&input[..len]) {
+        return Some((Request::BadChecksum, len + 2));
+    }
+    Some((Request::Ssi { opcode: input[1], data: input[4..len].to_vec() }, len + 2))
+}
+
+/// Write `bytes` one character time apart. A PTY master is non-blocking, so
+/// a full buffer is waited out; when the host stops reading for `WRITE_STALL`
+/// the rest is dropped.
+fn write_paced(name: &str, writer: &Mutex<File>, baud_rate: u32, bytes: &[u8]) -> Result<Duration> {
+    let mut writer = writer.lock().unwrap();
+    let start = Instant::now();
+    let char_time = char_time(baud_rate);
+    for (i, byte) in bytes.iter().enumerate() {
+        wait_until(start + char_time * i as u32);
+        loop {
+            match writer.write(std::slice::from_ref(byte)) {
+                Ok(1) => break,
+                Ok(_) => return Err(anyhow!("Failed to write to the serial port")),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    if !wait_writable(&writer, WRITE_STALL)? {
+                        println!("[{}] Host is not reading, dropped {} bytes", name, bytes.len() - i);
+                        return Ok(start.elapsed());
+                    }
+                }
+                Err(e) => return Err(e).context("Failed to write to the serial port"),
+            }
+        }
+    }
+    Ok(start.elapsed())
+}
+
+/// Wait until `file` takes more data; false on timeout
+fn wait_writable(file: &File, timeout: Duration) -> Result<bool> {
+    let mut fd = libc::pollfd { fd: file.as_raw_fd(), events: libc::POLLOUT, revents: 0 };
+    loop {
+        let result = unsafe { libc::poll(&mut fd, 1, timeout.as_millis() as libc::c_int) };
+        if result >= 0 {
+            return Ok(result > 0);
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != ErrorKind::Interrupted {
+            return Err(anyhow!("poll() failed: {}", err));
+        }
+    }
+}
+
+/// State shared by the scanner handle and the event loop thread
+struct Port {
+    name: String,
+    master: File,
+    /// Clone of `master` for writing. Replies and scans are paced under this
+    /// lock alone, so the port state stays available while a scan is sent.
+    writer: Arc<Mutex<File>>,
+    config: SerialScannerConfig,
+    status: ScannerStatus,
+    /// Barcodes in front of the scanner, read on the next host trigger
+    presented: VecDeque<(String, Option<Symbology>)>,
+    /// Host bytes of an incomplete command
+    input: Vec<u8>,
+}
+
+impl Port {
+    fn write_paced(&self, bytes: &[u8]) -> Result<Duration> {
+        write_paced(&self.name, &self.writer, self.config.baud_rate, bytes)
+    }
+
+    /// Bytes to send for a scan, refused while the host has scanning disabled
+    fn scan_bytes(&self, data: &str, symbology: Option<Symbology>) -> Result<Vec<u8>> {
+        if !self.status.enabled {
+            return Err(anyhow!("Scanning is disabled by the host"));
+        }
+        frame(&self.config, symbology, data)
+    }
+
+    fn good_read(&mut self) {
+        if self.status.good_read_beep {
+            self.status.beeps += 1;
+        }
+    }
+
+    fn send_scan(&mut self, data: &str, symbology: Option<Symbology>) -> Result<(usize, Duration)> {
+        let bytes = self.scan_bytes(data, symbology)?;
+        let duration = self.write_paced(&bytes)?;
+        self.good_read();
+        Ok((bytes.len(), duration))
+    }
+
+    /// Host trigger: read the next presented barcode, if any
+    fn trigger(&mut self) -> Result<()> {
+        if !self.status.enabled {
+            println!("[{}] Trigger ignored, scanning disabled", self.name);
+            return Ok(());
+        }
+        match self.presented.pop_front() {
+            Some((data, symbology)) => {
+                println!("[{}] Trigger: {}", self.name, data);
+                self.send_scan(&data, symbology).map(|_| ())
+            }
+            None => {
+                println!("[{}] Trigger: no barcode presented", self.name);
+                Ok(())
+            }
+        }
+    }
+
+    fn process_input(&mut self, bytes: &[u8]) -> Result<()> {
+        self.input.extend_from_slice(bytes);
+        while let Some((request, used)) = parse_request(&self.input) {
+            self.input.drain(..used);
+            self.handle_request(request)?;
+        }
+        Ok(())
+    }
+
+    fn handle_request(&mut self, request: Request) -> Result<()> {
+        match request {
+            Request::Trigger => self.trigger(),
+            Request::Untrigger => Ok(()),
+            Request::Menu(commands, terminator) => {
+                let mut reply: Vec<u8> = Vec::new();
+                for (i, command) in commands.iter().enumerate() {
+                    if i > 0 {
+                        reply.push(b';');
+                    }
+                    reply.extend_from_slice(&self.menu_command(command));
+                }
+                reply.push(terminator);
+                self.write_paced(&reply).map(|_| ())
+            }
+            Request::Ssi { opcode, data } => self.ssi_command(opcode, &data),
+            Request::BadChecksum => self.write_paced(&ssi_packet(SSI_CMD_NAK, &[SSI_NAK_RESEND])).map(|_| ()),
+            Request::Garbage(byte) => {
+                println!("[{}] Discarded unparsed byte: {:02X}", self.name, byte);
+                Ok(())
+            }
+        }
+    }
+
+    /// Honeywell menu command: the reply is the command (or the current
+    /// value for queries) followed by ACK, ENQ for an unknown tag or NAK for
+    /// a bad value.
+    ///
+    /// The command is raw bytes from the host: tags and values are ASCII, but
+    /// anything may arrive and is echoed back as received.
+    fn menu_command(&mut self, command: &[u8]) -> Vec<u8> {
+        let (tag, value) = command.split_at(command.len().min(6));
+        let setting = match tag {
+            // All symbologies on or off
+            b"ALLENA" => &mut self.status.enabled,
+            b"BEPBEP" => &mut self.status.good_read_beep,
+            _ => {
+                let mut reply = command.to_vec();
+                reply.push(ENQ);
+                return reply;
+            }
+        };
+        let status = match value {
+            b"?" => {
+                let mut reply = tag.to_vec();
+                reply.push(b'0' + *setting as u8);
+                reply.push(ACK);
+                return reply;
+            }
+            b"0" | b"1" => {
+                *setting = value == b"1";
+                ACK
+            }
+            _ => NAK,
+        };
+        println!(
+            "[{}] Menu command {} {}",
+            self.name,
+            String::from_utf8_lossy(command),
+            if status == ACK { "✓" } else { "✗" }
+        );
+        let mut reply = command.to_vec();
+        reply.push(status);
+        reply
+    }
+
+    fn ssi_command(&mut self, opcode: u8, data: &[u8]) -> Result<()> {
+        match opcode {
+            // The host acknowledges our packets
+            SSI_CMD_ACK | SSI_CMD_NAK => return Ok(()),
+            SSI_START_DECODE | SSI_STOP_DECODE | SSI_BEEP | SSI_LED_ON | SSI_LED_OFF | SSI_SCAN_ENABLE
+            | SSI_SCAN_DISABLE => {}
+            _ => {
+                println!("[{}] Unknown SSI opcode 0x{:02X}", self.name, opcode);
+                return self.write_paced(&ssi_packet(SSI_CMD_NAK, &[SSI_NAK_BAD_CONTEXT])).map(|_| ());
+            }
+        }
+        self.write_paced(&ssi_packet(SSI_CMD_ACK, &[]))?;
+        match opcode {
+            SSI_START_DECODE => self.trigger()?,
+            SSI_BEEP => {
+                // The beep code selects the tone pattern
+                self.status.beeps += 1;
+                println!("[{}] 🔔 Beep code {}", self.name, data.first().copied().unwrap_or(0));
+            }
+            SSI_SCAN_ENABLE | SSI_SCAN_DISABLE => {
+                self.status.enabled = opcode == SSI_SCAN_ENABLE;
+                println!("[{}] Scanning {}", self.name, if self.status.enabled { "enabled" } else { "disabled" });
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Event loop source reading host commands
+struct PortSource {
+    fd: RawFd,
+    port: Arc<Mutex<Port>>,
+}
+
+impl EventSource for PortSource {
+    fn raw_fd(&self) -> RawFd {
+        self.fd
+    }
+
+    fn on_readable(&mut self) -> Result<()> {
+        let mut read_buf = [0u8; 256];
+        let mut port = self.port.lock().unwrap();
+        loop {
+            match port.master.read(&mut read_buf) {
+                Ok(0) => return Ok(()),
+                Ok(n) => port.process_input(&read_buf[..n])?,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                // EIO: no process has the slave PTY open
+                Err(e) if e.raw_os_error() == Some(libc::EIO) => return Ok(()),
+                Err(e) => return Err(e).context("read() failed"),
+            }
+        }
+    }
+}
+
+/// Barcode scanner on a virtual serial port, like a scanner in USB CDC-ACM
+/// or RS-232 mode. Host commands are served on a background thread until
+/// the scanner is dropped.
+pub struct SerialScanner {
+    port: Arc<Mutex<Port>>,
+    identity: DeviceIdentity,
+    slave_path: String,
+    shutdown: ShutdownHandle,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl SerialScanner {
+    pub fn new(identity: &DeviceIdentity, config: SerialScannerConfig) -> Result<Self> {
+        identity.validate()?;
+        baud_rate(config.baud_rate)?;
+        let (master, slave_path) = open_pty()?;
+        println!("✓ Virtual serial scanner created: {}", slave_path);
+
+        let fd = master.as_raw_fd();
+        let writer = Arc::new(Mutex::new(master.try_clone().context("Failed to clone the PTY master")?));
+        let port = Arc::new(Mutex::new(Port {
+            name: identity.name.clone(),
+            master,
+            writer,
+            config,
+            status: ScannerStatus::default(),
+            presented: VecDeque::new(),
+            input: Vec::new(),
+        }));
+        let shutdown = ShutdownHandle::new()?;
+        let mut source = PortSource { fd, port: Arc::clone(&port) };
+        let loop_shutdown = shutdown.clone();
+        let thread = thread::spawn(move || {
+            let source: &mut dyn EventSource = &mut source;
+            event_loop::run(&mut [source], &loop_shutdown)
+        });
+
+        Ok(Self { port, identity: identity.clone(), slave_path, shutdown, thread: Some(thread) })
+    }
+
+    pub fn identity(&self) -> &DeviceIdentity {
+        &self.identity
+    }
+
+    /// /dev/pts/N the host opens
+    pub fn slave_path(&self) -> &str {
+        &self.slave_path
+    }
+
+    pub fn config(&self) -> SerialScannerConfig {
+        self.port.lock().unwrap().config.clone()
+    }
+
+    pub fn status(&self) -> ScannerStatus {
+        self.port.lock().unwrap().status
+    }
+
+    /// Send a scan now, as if the trigger was pulled by hand. Returns the
+    /// number of bytes and the time it took to send them.
+    pub fn send_scan(&self, data: &str, symbology: Option<Symbology>) -> Result<(usize, Duration)> {
+        let (bytes, writer, name, baud_rate) = {
+            let port = self.port.lock().unwrap();
+            let bytes = port.scan_bytes(data, symbology)?;
+            (bytes, Arc::clone(&port.writer), port.name.clone(), port.config.baud_rate)
+        };
+        let duration = write_paced(&name, &writer, baud_rate, &bytes)?;
+        self.port.lock().unwrap().good_read();
+        Ok((bytes.len(), duration))
+    }
+
+    /// Put a barcode in front of the scanner: it is sent when the host
+    /// triggers a read. The data is checked now, a bad barcode would only
+    /// fail once the host pulls the trigger. Returns how many are waiting.
+    pub fn present(&self, data: &str, symbology: Option<Symbology>) -> Result<usize> {
+        let mut port = self.port.lock().unwrap();
+        frame(&port.config, symbology, data)?;
+        port.presented.push_back((data.to_string(), symbology));
+        Ok(port.presented.len())
+    }
+}
+
+impl Drop for SerialScanner {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+        if let Some(thread) = self.thread.take() {
+            if let Ok(Err(e)) = thread.join() {
+                eprintln!("Serial scanner error: {:#}", e);
+            }
+        }
+        println!("✗ Serial scanner closed: {}", self.slave_path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sniffer::open_serial_port;
+    use std::os::unix::io::FromRawFd;
+
+    #[test]
+    fn test_framing() {
+        let config = SerialScannerConfig::default();
+        assert_eq!(frame(&config, None, "12345").unwrap(), b"12345\r");
+
+        let config = SerialScannerConfig { framing: SerialFraming::StxEtx, code_id: CodeId::Aim, ..Default::default() };
+        assert_eq!(frame(&config, Some(Symbology::Ean13), "4006381333931").unwrap(), b"\x02]E04006381333931\x03");
+        // No symbology, no code ID
+        assert_eq!(frame(&config, None, "A").unwrap(), b"\x02A\x03");
+
+        let config = SerialScannerConfig { framing: SerialFraming::CrLf, ..Default::default() };
+        assert_eq!(frame(&config, Some(Symbology::Gs1_128), "(01)09501101530003(10)AB").unwrap(), b"010950110153000310AB\r\n");
+    }
+
+    #[test]
+    fn test_parse_requests() {
+        assert_eq!(parse_request(b"\x16T\r"), Some((Request::Trigger, 3)));
+        assert_eq!(parse_request(b"\x16U"), None);
+        assert_eq!(parse_request(b"\x16M\rALLENA0"), None);
+        assert_eq!(
+            parse_request(b"\x16M\rALLENA0;BEPBEP?!rest"),
+            Some((Request::Menu(vec![b"ALLENA0".to_vec(), b"BEPBEP?".to_vec()], b'!'), 19))
+        );
+
+        // CMD_ACK from the decoder, the example of the SSI specification
+        assert_eq!(ssi_packet(SSI_CMD_ACK, &[]), [0x04, 0xD0, 0x00, 0x00, 0xFF, 0x2C]);
+        let start_decode = [0x04, SSI_START_DECODE, 0x04, 0x00, 0xFF, 0x14];
+        assert_eq!(parse_request(&start_decode), Some((Request::Ssi { opcode: SSI_START_DECODE, data: vec![] }, 6)));
+        assert_eq!(parse_request(&start_decode[..5]), None);
+        assert_eq!(parse_request(&[0x04, SSI_START_DECODE, 0x04, 0x00, 0xFF, 0x15]), Some((Request::BadChecksum, 6)));
+        assert_eq!(parse_request(b"\r\n"), Some((Request::Garbage(b'\r'), 1)));
+    }
+
+    fn read_reply(port: &mut File, len: usize) -> Vec<u8> {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let mut reply = Vec::new();
+        let mut buf = [0u8; 64];
+        while reply.len() < len && Instant::now() < deadline {
+            match port.read(&mut buf) {
+                Ok(n) => reply.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(5)),
+                Err(e) => panic!("read failed: {}", e),
+            }
+        }
+        reply
+    }
+
+    #[test]
+    fn test_stalled_host_drops_the_rest() {
+        let (reader, writer) = nix::unistd::pipe().unwrap();
+        let (mut reader, writer) = unsafe { (File::from_raw_fd(reader), File::from_raw_fd(writer)) };
+        unsafe {
+            let flags = libc::fcntl(writer.as_raw_fd(), libc::F_GETFL);
+            libc::fcntl(writer.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK);
+        }
+
+        // More than the pipe holds, at a rate that leaves no time between bytes
+        let bytes = vec![b'A'; 1 << 18];
+        let duration = write_paced("test", &Mutex::new(writer), u32::MAX, &bytes).unwrap();
+        assert!(duration >= WRITE_STALL);
+
+        let mut written = Vec::new();
+        reader.read_to_end(&mut written).unwrap();
+        assert!(!written.is_empty() && written.len() < bytes.len());
+    }
+
+    #[test]
+    fn test_host_commands_over_pty() {
+        let scanner = SerialScanner::new(&DeviceIdentity::default(), SerialScannerConfig::default()).unwrap();
+        let mut host = open_serial_port(scanner.slave_path(), 115200).unwrap();
+        unsafe {
+            let flags = libc::fcntl(host.as_raw_fd(), libc::F_GETFL);
+            libc::fcntl(host.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK);
+        }
+
+        scanner.send_scan("MANUAL", None).unwrap();
+        assert_eq!(read_reply(&mut host, 7), b"MANUAL\r");
+
+        // Honeywell trigger reads the presented barcode
+        assert_eq!(scanner.present("PRESENTED", None).unwrap(), 1);
+        assert!(scanner.present("96385075", Some(Symbology::Ean8)).is_err(), "bad check digit");
+        host.write_all(b"\x16T\r").unwrap();
+        assert_eq!(read_reply(&mut host, 10), b"PRESENTED\r");
+
+        // SSI: disable, then beep
+        host.write_all(&[0x04, SSI_SCAN_DISABLE, 0x04, 0x00, 0xFF, 0x0E]).unwrap();
+        assert_eq!(read_reply(&mut host, 6), ssi_packet(SSI_CMD_ACK, &[]));
+        assert!(!scanner.status().enabled);
+        assert!(scanner.send_scan("REFUSED", None).is_err());
+        host.write_all(&[0x05, SSI_BEEP, 0x04, 0x00, 0x01, 0xFF, 0x10]).unwrap();
+        assert_eq!(read_reply(&mut host, 6), ssi_packet(SSI_CMD_ACK, &[]));
+
+        // Honeywell menu: enable again, query and an unknown tag
+        host.write_all(b"\x16M\rALLENA1;BEPBEP?;XYZABC1.").unwrap();
+        assert_eq!(read_reply(&mut host, 27), b"ALLENA1\x06;BEPBEP1\x06;XYZABC1\x05.");
+        let status = scanner.status();
+        assert!(status.enabled);
+        // One good read beep for each scan and the requested one
+        assert_eq!(status.beeps, 3);
+
+        // Bytes that are not ASCII, or not even UTF-8, are echoed back as sent
+        host.write_all(b"\x16M\rABCDE\xff;ALL\xc3\x89NA1;ALLENA\xe2\x82\xac.").unwrap();
+        assert_eq!(
+            read_reply(&mut host, 29),
+            b"ABCDE\xff\x05;ALL\xc3\x89NA1\x05;ALLENA\xe2\x82\xac\x15."
+        );
+        assert!(scanner.status().enabled);
+    }
+}
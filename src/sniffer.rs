@@ -0,0 +1,364 @@
+use anyhow::{anyhow, Context, Result};
+use nix::sys::termios::{self, BaudRate, SetArg};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::str::FromStr;
+
+use crate::bill_emulator::PollEvent;
+use crate::capture::{timestamp_now, CaptureSink, Direction, FrameDecoder};
+use crate::essp_protocol::*;
+
+/// Rewrites applied to traffic passing through the sniffer
+#[derive(Debug, Clone, PartialEq)]
+pub enum RewriteRule {
+    /// Replace one poll event with another in device responses, e.g. `credit->rejected`
+    ReplaceEvent { from: u8, to: u8 },
+    /// Add an event to the next POLL response from an address, e.g. `inject:jammed@0x00`
+    InjectEvent { event: u8, address: u8 },
+}
+
+fn parse_event(name: &str) -> Result<u8> {
+    event_code(name).ok_or_else(|| anyhow!("Unknown poll event: {}", name))
+}
+
+impl FromStr for RewriteRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(spec) = s.strip_prefix("inject:") {
+            let (event, address) = spec
+                .split_once('@')
+                .ok_or_else(|| anyhow!("Inject rule needs an address: {}", s))?;
+            return Ok(RewriteRule::InjectEvent {
+                event: parse_event(event)?,
+                address: parse_address(address)?,
+            });
+        }
+        if let Some((from, to)) = s.split_once("->") {
+            return Ok(RewriteRule::ReplaceEvent {
+                from: parse_event(from)?,
+                to: parse_event(to)?,
+            });
+        }
+        Err(anyhow!(
+            "Invalid rewrite rule '{}': expected EVENT->EVENT or inject:EVENT@ADDR",
+            s
+        ))
+    }
+}
+
+/// Applies rewrite rules to POLL responses
+#[derive(Debug, Clone)]
+pub struct EventRewriter {
+    rules: Vec<RewriteRule>,
+    pending_injections: HashMap<u8, Vec<u8>>,
+}
+
+impl EventRewriter {
+    pub fn new(rules: Vec<RewriteRule>) -> Self {
+        let mut pending_injections: HashMap<u8, Vec<u8>> = HashMap::new();
+        for rule in &rules {
+            if let RewriteRule::InjectEvent { event, address } = rule {
+                pending_injections.entry(*address).or_default().push(*event);
+            }
+        }
+        Self { rules, pending_injections }
+    }
+
+    /// Apply the rules to a device response answering the command `answered`.
+    /// Returns the re-encoded packet if anything changed.
+    pub fn rewrite(&mut self, answered: Option<u8>, packet: &EsspPacket) -> Option<EsspPacket> {
+        let address = packet.sequence & 0x7F;
+        if answered != Some(CMD_POLL) || packet.data.first() != Some(&RESPONSE_OK) {
+            return None;
+        }
+        let mut events = PollEvent::parse_poll_data(&packet.data[1..])?;
+        let mut changed = false;
+
+        for event in events.iter_mut() {
+            for rule in &self.rules {
+                if let RewriteRule::ReplaceEvent { from, to } = *rule {
+                    if event.event_code == from {
+                        *event = PollEvent { event_code: to, ..event.clone() };
+                        changed = true;
+                        break;
+                    }
+                }
+            }
+        }
+
+        if let Some(injected) = self.pending_injections.remove(&address) {
+            // A lone DISABLED means "nothing happened", replace it
+            if events.len() == 1 && events[0].event_code == EVENT_DISABLED {
+                events.clear();
+            }
+            events.extend(injected.into_iter().map(|code| PollEvent::new(code, 0)));
+            changed = true;
+        }
+
+        if !changed {
+            return None;
+        }
+
+        let mut data = vec![RESPONSE_OK, events.len() as u8];
+        for event in &events {
+            data.extend_from_slice(&event.to_bytes());
+        }
+        Some(EsspPacket::new(packet.sequence, data))
+    }
+}
+
+/// Sniffer configuration
+#[derive(Debug, Clone)]
+pub struct SnifferConfig {
+    /// Port connected to the host (payment system)
+    pub host_port: String,
+    /// Port connected to the real validator
+    pub device_port: String,
+    pub baud: u32,
+    pub rules: Vec<RewriteRule>,
+}
+
+pub(crate) fn baud_rate(baud: u32) -> Result<BaudRate> {
+    Ok(match baud {
+        1200 => BaudRate::B1200,
+        2400 => BaudRate::B2400,
+        4800 => BaudRate::B4800,
+        9600 => BaudRate::B9600,
+        19200 => BaudRate::B19200,
+        38400 => BaudRate::B38400,
+        57600 => BaudRate::B57600,
+        115200 => BaudRate::B115200,
+        _ => return Err(anyhow!("Unsupported baud rate: {}", baud)),
+    })
+}
+
+/// Open a serial port in raw mode (8N1) at `baud`
+pub fn open_serial_port(path: &str, baud: u32) -> Result<File> {
+    let port = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(path)
+        .with_context(|| format!("Failed to open serial port {}", path))?;
+
+    let mut tio = termios::tcgetattr(&port).with_context(|| format!("{} is not a terminal", path))?;
+    termios::cfmakeraw(&mut tio);
+    termios::cfsetspeed(&mut tio, baud_rate(baud)?)?;
+    termios::tcsetattr(&port, SetArg::TCSANOW, &tio)?;
+
+    Ok(port)
+}
+
+/// Passive sniffer / man-in-the-middle between a real host and a real validator.
+///
+/// Bytes are forwarded in both directions and every eSSP frame is decoded,
+/// logged and captured. Device frames are re-encoded when a rewrite rule applies.
+pub struct Sniffer {
+    host: File,
+    device: File,
+    rewriter: Option<EventRewriter>,
+    host_scanner: FrameScanner,
+    device_scanner: FrameScanner,
+    decoder: FrameDecoder,
+    last_command: HashMap<u8, u8>,
+    captures: Vec<Box<dyn CaptureSink>>,
+}
+
+impl Sniffer {
+    pub fn new(config: SnifferConfig) -> Result<Self> {
+        let host = open_serial_port(&config.host_port, config.baud)?;
+        let device = open_serial_port(&config.device_port, config.baud)?;
+
+        println!("🔍 Sniffing {} (host) ⇄ {} (device) at {} baud", config.host_port, config.device_port, config.baud);
+        for rule in &config.rules {
+            println!("   rewrite: {:?}", rule);
+        }
+
+        let rewriter = if config.rules.is_empty() {
+            None
+        } else {
+            Some(EventRewriter::new(config.rules))
+        };
+
+        Ok(Self {
+            host,
+            device,
+            rewriter,
+            host_scanner: FrameScanner::new(),
+            device_scanner: FrameScanner::new(),
+            decoder: FrameDecoder::new(),
+            last_command: HashMap::new(),
+            captures: Vec::new(),
+        })
+    }
+
+    /// Record every frame to `sink`
+    pub fn add_capture(&mut self, sink: Box<dyn CaptureSink>) {
+        self.captures.push(sink);
+    }
+
+    /// Forward traffic until one side closes
+    pub fn run(&mut self) -> Result<()> {
+        let mut buf = [0u8; 256];
+
+        loop {
+            let mut fds = [
+                libc::pollfd { fd: self.host.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+                libc::pollfd { fd: self.device.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+            ];
+            let result = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) };
+            if result < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(anyhow!("poll() failed: {}", err));
+            }
+
+            if fds[0].revents & (libc::POLLIN | libc::POLLHUP) != 0 {
+                let n = self.host.read(&mut buf).context("Failed to read from host port")?;
+                if n == 0 {
+                    return Ok(());
+                }
+                self.forward_host_bytes(&buf[..n])?;
+            }
+            if fds[1].revents & (libc::POLLIN | libc::POLLHUP) != 0 {
+                let n = self.device.read(&mut buf).context("Failed to read from device port")?;
+                if n == 0 {
+                    return Ok(());
+                }
+                self.forward_device_bytes(&buf[..n])?;
+            }
+        }
+    }
+
+    fn forward_host_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        // Host traffic is never modified, forward it straight away
+        self.device.write_all(bytes)?;
+
+        for item in self.host_scanner.push(bytes) {
+            match item {
+                ScanItem::Frame(packet, raw) => {
+                    if let Some(&code) = packet.data.first() {
+                        self.last_command.insert(packet.sequence & 0x7F, code);
+                    }
+                    self.log_frame(Direction::HostToDevice, &packet, &raw, false);
+                }
+                ScanItem::Garbage(garbage) => println!("[HOST] Garbage: {:02X?}", garbage),
+            }
+        }
+        Ok(())
+    }
+
+    fn forward_device_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        // Without rules the device side is passive too. With rules, frames are
+        // held back until complete so that they can be re-encoded.
+        let buffered = self.rewriter.is_some();
+        if !buffered {
+            self.host.write_all(bytes)?;
+        }
+
+        for item in self.device_scanner.push(bytes) {
+            match item {
+                ScanItem::Frame(packet, raw) => {
+                    let answered = self.last_command.get(&(packet.sequence & 0x7F)).copied();
+                    let rewritten = self
+                        .rewriter
+                        .as_mut()
+                        .and_then(|rewriter| rewriter.rewrite(answered, &packet));
+                    match rewritten {
+                        Some(packet) => {
+                            let raw = packet.to_bytes();
+                            self.host.write_all(&raw)?;
+                            self.log_frame(Direction::DeviceToHost, &packet, &raw, true);
+                        }
+                        None => {
+                            if buffered {
+                                self.host.write_all(&raw)?;
+                            }
+                            self.log_frame(Direction::DeviceToHost, &packet, &raw, false);
+                        }
+                    }
+                }
+                ScanItem::Garbage(garbage) => {
+                    if buffered {
+                        self.host.write_all(&garbage)?;
+                    }
+                    println!("[DEVICE] Garbage: {:02X?}", garbage);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn log_frame(&mut self, direction: Direction, packet: &EsspPacket, raw: &[u8], rewritten: bool) {
+        let record = self.decoder.decode_packet(direction, packet, raw, timestamp_now());
+        let arrow = match direction {
+            Direction::HostToDevice => format!("HOST → 0x{:02X}", record.address),
+            Direction::DeviceToHost => format!("0x{:02X} → HOST", record.address),
+        };
+        println!(
+            "[{}] {}{}{} {:02X?}",
+            arrow,
+            record.name,
+            record.command.as_ref().map(|c| format!(" ({})", c)).unwrap_or_default(),
+            if rewritten { " [REWRITTEN]" } else { "" },
+            record.data
+        );
+        if !record.events.is_empty() {
+            println!("         events: {}", record.events.join(", "));
+        }
+
+        for sink in &mut self.captures {
+            if let Err(e) = sink.record(&record) {
+                eprintln!("Capture error: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rewrite_rules() {
+        assert_eq!(
+            "credit->rejected".parse::<RewriteRule>().unwrap(),
+            RewriteRule::ReplaceEvent { from: EVENT_CREDIT, to: EVENT_REJECTED }
+        );
+        assert_eq!(
+            "inject:jammed@0x10".parse::<RewriteRule>().unwrap(),
+            RewriteRule::InjectEvent { event: EVENT_JAMMED, address: 0x10 }
+        );
+        assert!("credit->nothing".parse::<RewriteRule>().is_err());
+        assert!("inject:jammed".parse::<RewriteRule>().is_err());
+    }
+
+    #[test]
+    fn test_rewrite_credit_to_rejected() {
+        let mut rewriter = EventRewriter::new(vec!["credit->rejected".parse().unwrap()]);
+        let response = build_response(0x80, RESPONSE_OK, &[2, EVENT_READ, 3, EVENT_CREDIT, 3]);
+
+        let rewritten = rewriter.rewrite(Some(CMD_POLL), &response).unwrap();
+        assert_eq!(rewritten.data, vec![RESPONSE_OK, 2, EVENT_READ, 3, EVENT_REJECTED]);
+        assert_eq!(rewritten.sequence, 0x80);
+
+        // Only POLL responses are touched
+        assert!(rewriter.rewrite(Some(CMD_SYNC), &response).is_none());
+    }
+
+    #[test]
+    fn test_inject_event_once() {
+        let mut rewriter = EventRewriter::new(vec!["inject:jammed@0x00".parse().unwrap()]);
+        let idle = build_response(0x00, RESPONSE_OK, &[1, EVENT_DISABLED]);
+
+        let rewritten = rewriter.rewrite(Some(CMD_POLL), &idle).unwrap();
+        assert_eq!(rewritten.data, vec![RESPONSE_OK, 1, EVENT_JAMMED]);
+        assert!(rewriter.rewrite(Some(CMD_POLL), &idle).is_none());
+    }
+}
@@ -0,0 +1,278 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::gs1;
+
+/// Symbologies the scanner emulation knows how to identify and validate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symbology {
+    Ean13,
+    Ean8,
+    UpcA,
+    UpcE,
+    Code128,
+    Gs1_128,
+    Code39,
+    Interleaved2of5,
+    QrCode,
+    DataMatrix,
+    Gs1DataMatrix,
+    Pdf417,
+}
+
+/// Identifier transmitted before the data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CodeId {
+    #[default]
+    None,
+    /// ISO/IEC 15424 symbology identifier, e.g. ]E0
+    Aim,
+    /// Honeywell single-character Code ID, e.g. d for EAN-13
+    Honeywell,
+}
+
+impl CodeId {
+    pub const ALL: [Self; 3] = [Self::None, Self::Aim, Self::Honeywell];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::None => "No code ID",
+            Self::Aim => "AIM ID (]E0)",
+            Self::Honeywell => "Honeywell code ID",
+        }
+    }
+}
+
+impl Symbology {
+    pub const ALL: [Self; 12] = [
+        Self::Ean13,
+        Self::Ean8,
+        Self::UpcA,
+        Self::UpcE,
+        Self::Code128,
+        Self::Gs1_128,
+        Self::Code39,
+        Self::Interleaved2of5,
+        Self::QrCode,
+        Self::DataMatrix,
+        Self::Gs1DataMatrix,
+        Self::Pdf417,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Ean13 => "EAN-13",
+            Self::Ean8 => "EAN-8",
+            Self::UpcA => "UPC-A",
+            Self::UpcE => "UPC-E",
+            Self::Code128 => "Code 128",
+            Self::Gs1_128 => "GS1-128",
+            Self::Code39 => "Code 39",
+            Self::Interleaved2of5 => "Interleaved 2 of 5",
+            Self::QrCode => "QR Code",
+            Self::DataMatrix => "Data Matrix",
+            Self::Gs1DataMatrix => "GS1 DataMatrix",
+            Self::Pdf417 => "PDF417",
+        }
+    }
+
+    /// AIM symbology identifier with the modifier a scanner sends for
+    /// data read with default settings
+    pub fn aim_id(self) -> &'static str {
+        match self {
+            Self::Ean13 | Self::UpcA | Self::UpcE => "]E0",
+            Self::Ean8 => "]E4",
+            Self::Code128 => "]C0",
+            Self::Gs1_128 => "]C1",
+            Self::Code39 => "]A0",
+            Self::Interleaved2of5 => "]I0",
+            Self::QrCode => "]Q1",
+            Self::DataMatrix => "]d1",
+            Self::Gs1DataMatrix => "]d2",
+            Self::Pdf417 => "]L0",
+        }
+    }
+
+    /// Honeywell Code ID character
+    pub fn honeywell_id(self) -> char {
+        match self {
+            Self::Ean13 => 'd',
+            Self::Ean8 => 'D',
+            Self::UpcA => 'c',
+            Self::UpcE => 'E',
+            Self::Code128 => 'j',
+            Self::Gs1_128 => 'I',
+            Self::Code39 => 'b',
+            Self::Interleaved2of5 => 'e',
+            Self::QrCode => 's',
+            Self::DataMatrix | Self::Gs1DataMatrix => 'w',
+            Self::Pdf417 => 'r',
+        }
+    }
+
+    /// Identifier to transmit before the data
+    pub fn code_id(self, code_id: CodeId) -> String {
+        match code_id {
+            CodeId::None => String::new(),
+            CodeId::Aim => self.aim_id().to_string(),
+            CodeId::Honeywell => self.honeywell_id().to_string(),
+        }
+    }
+
+    /// True for the symbologies carrying GS1 element strings
+    pub fn is_gs1(self) -> bool {
+        matches!(self, Self::Gs1_128 | Self::Gs1DataMatrix)
+    }
+
+    /// Data as the scanner transmits it: validated, and for GS1 symbologies
+    /// converted from the "(01)…(10)…" form to an element string with GS
+    /// separators
+    pub fn encode(self, data: &str) -> Result<String> {
+        if self.is_gs1() && data.starts_with('(') {
+            return Ok(gs1::encode(&gs1::parse_bracketed(data)?));
+        }
+        self.validate(data)?;
+        Ok(data.to_string())
+    }
+
+    /// Check that `data` can be encoded in this symbology, including the
+    /// check digit of EAN/UPC and the AIs of GS1 element strings
+    pub fn validate(self, data: &str) -> Result<()> {
+        if data.is_empty() {
+            return Err(anyhow!("{}: no data", self.name()));
+        }
+        match self {
+            Self::Gs1_128 | Self::Gs1DataMatrix => {
+                gs1::parse_raw(data).map_err(|e| anyhow!("{}: {}", self.name(), e))?;
+                Ok(())
+            }
+            Self::Ean13 => check_digit_code(self, data, 13),
+            Self::Ean8 => check_digit_code(self, data, 8),
+            Self::UpcA => check_digit_code(self, data, 12),
+            Self::UpcE => {
+                check_digits_only(self, data, 8)?;
+                if !data.starts_with(['0', '1']) {
+                    return Err(anyhow!("UPC-E: number system must be 0 or 1"));
+                }
+                expect_check_digit(self, data, &expand_upc_e(data))
+            }
+            Self::Code128 => match data.chars().find(|c| !c.is_ascii()) {
+                Some(c) => Err(anyhow!("{}: {:?} is not ASCII", self.name(), c)),
+                None => Ok(()),
+            },
+            Self::Code39 => {
+                match data.chars().find(|c| !(c.is_ascii_uppercase() || c.is_ascii_digit() || " -.$/+%".contains(*c))) {
+                    Some(c) => Err(anyhow!("Code 39: {:?} is not encodable", c)),
+                    None => Ok(()),
+                }
+            }
+            Self::Interleaved2of5 => {
+                check_digits_only(self, data, data.len())?;
+                if !data.len().is_multiple_of(2) {
+                    return Err(anyhow!("Interleaved 2 of 5: odd number of digits"));
+                }
+                Ok(())
+            }
+            Self::QrCode | Self::DataMatrix | Self::Pdf417 => Ok(()),
+        }
+    }
+}
+
+/// GS1 mod-10 check digit of `digits` (without the check digit)
+pub fn gs1_check_digit(digits: &str) -> u8 {
+    let sum: u32 = digits
+        .bytes()
+        .rev()
+        .enumerate()
+        .map(|(i, digit)| (digit - b'0') as u32 * if i % 2 == 0 { 3 } else { 1 })
+        .sum();
+    ((10 - sum % 10) % 10) as u8
+}
+
+fn check_digits_only(symbology: Symbology, data: &str, len: usize) -> Result<()> {
+    if data.len() != len || !data.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(anyhow!("{}: expected {} digits", symbology.name(), len));
+    }
+    Ok(())
+}
+
+fn check_digit_code(symbology: Symbology, data: &str, len: usize) -> Result<()> {
+    check_digits_only(symbology, data, len)?;
+    expect_check_digit(symbology, data, data)
+}
+
+/// The last digit of `data` must be the check digit of `payload` without its last digit
+fn expect_check_digit(symbology: Symbology, data: &str, payload: &str) -> Result<()> {
+    let expected = gs1_check_digit(&payload[..payload.len() - 1]);
+    let actual = data.as_bytes()[data.len() - 1] - b'0';
+    if actual != expected {
+        return Err(anyhow!("{}: check digit is {}, expected {}", symbology.name(), actual, expected));
+    }
+    Ok(())
+}
+
+/// UPC-A equivalent of an 8-digit UPC-E code
+fn expand_upc_e(data: &str) -> String {
+    let d = &data[1..7];
+    let (system, check) = (&data[..1], &data[7..]);
+    let body = match d.as_bytes()[5] {
+        b'0' | b'1' | b'2' => format!("{}{}0000{}", &d[..2], &d[5..], &d[2..5]),
+        b'3' => format!("{}00000{}", &d[..3], &d[3..5]),
+        b'4' => format!("{}00000{}", &d[..4], &d[4..5]),
+        _ => format!("{}0000{}", &d[..5], &d[5..]),
+    };
+    format!("{}{}{}", system, body, check)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_digits() {
+        assert!(Symbology::Ean13.validate("4006381333931").is_ok());
+        let error = Symbology::Ean13.validate("4006381333932").unwrap_err();
+        assert_eq!(error.to_string(), "EAN-13: check digit is 2, expected 1");
+        assert!(Symbology::Ean13.validate("400638133393").is_err());
+        assert!(Symbology::Ean8.validate("96385074").is_ok());
+        assert!(Symbology::UpcA.validate("036000291452").is_ok());
+        assert!(Symbology::UpcA.validate("036000291453").is_err());
+        assert!(Symbology::UpcE.validate("04252614").is_ok());
+        assert!(Symbology::UpcE.validate("04252615").is_err());
+        assert_eq!(expand_upc_e("04252614"), "042100005264");
+    }
+
+    #[test]
+    fn test_character_sets() {
+        assert!(Symbology::Code39.validate("ABC-123").is_ok());
+        assert!(Symbology::Code39.validate("abc").is_err());
+        assert!(Symbology::Interleaved2of5.validate("1234").is_ok());
+        assert!(Symbology::Interleaved2of5.validate("123").is_err());
+        assert!(Symbology::Code128.validate("abc!").is_ok());
+        assert!(Symbology::Code128.validate("café").is_err());
+        assert!(Symbology::QrCode.validate("café").is_ok());
+        assert!(Symbology::QrCode.validate("").is_err());
+    }
+
+    #[test]
+    fn test_gs1_data() {
+        let data = Symbology::Gs1_128.encode("(01)09501101530003(10)LOT7(17)261231").unwrap();
+        assert_eq!(data, "010950110153000310LOT7\u{1d}17261231");
+        assert!(Symbology::Gs1DataMatrix.validate(&data).is_ok());
+        assert!(Symbology::Gs1_128.validate("10LOT7").is_ok());
+        assert!(Symbology::Gs1_128.encode("(01)09501101530004").is_err());
+        assert!(Symbology::Gs1_128.validate("ABC").is_err());
+        // Brackets are only special for GS1 symbologies
+        assert_eq!(Symbology::QrCode.encode("(01)1").unwrap(), "(01)1");
+    }
+
+    #[test]
+    fn test_code_ids() {
+        assert_eq!(Symbology::Ean13.code_id(CodeId::Aim), "]E0");
+        assert_eq!(Symbology::Gs1_128.code_id(CodeId::Aim), "]C1");
+        assert_eq!(Symbology::QrCode.code_id(CodeId::Aim), "]Q1");
+        assert_eq!(Symbology::Ean13.code_id(CodeId::Honeywell), "d");
+        assert_eq!(Symbology::QrCode.code_id(CodeId::None), "");
+    }
+}
@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::device::BAUDRATE;
+use crate::rng::Rng;
+
+/// Below this the wait spins instead of sleeping, the scheduler wakes up
+/// too late for shorter sleeps
+const SPIN_THRESHOLD: Duration = Duration::from_micros(200);
+
+/// How fast the emulated scanner types
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimingProfile {
+    /// Every event at once
+    Instant,
+    /// Serial wedge: one character time (10 bits) per key event
+    Baud(u32),
+    /// Roughly 5 characters per second
+    HumanTypist,
+    /// USB HID keyboard scanner sending one report per 1 ms poll
+    UsbScanner,
+    /// Delays measured on a particular scanner, in microseconds, e.g.
+    /// `{ "custom": { "press_us": 4000, "release_us": 4000, "inter_char_us": 0 } }`
+    Custom {
+        press_us: u64,
+        release_us: u64,
+        inter_char_us: u64,
+    },
+}
+
+impl Default for TimingProfile {
+    fn default() -> Self {
+        Self::Baud(BAUDRATE)
+    }
+}
+
+/// Delays after each kind of key event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyTiming {
+    /// After a key press, before the release
+    pub press: Duration,
+    /// After a key release
+    pub release: Duration,
+    /// Between two characters
+    pub inter_char: Duration,
+}
+
+impl TimingProfile {
+    pub const PRESETS: [Self; 5] = [
+        Self::Instant,
+        Self::Baud(9600),
+        Self::Baud(BAUDRATE),
+        Self::HumanTypist,
+        Self::UsbScanner,
+    ];
+
+    pub fn label(self) -> String {
+        match self {
+            Self::Instant => "Instant burst".to_string(),
+            Self::Baud(rate) => format!("{} baud", rate),
+            Self::HumanTypist => "Human typist".to_string(),
+            Self::UsbScanner => "USB HID scanner (1 ms polling)".to_string(),
+            Self::Custom { press_us, release_us, inter_char_us } => {
+                format!("Custom ({} / {} / {} µs)", press_us, release_us, inter_char_us)
+            }
+        }
+    }
+
+    pub fn timing(self) -> KeyTiming {
+        match self {
+            Self::Instant => KeyTiming {
+                press: Duration::ZERO,
+                release: Duration::ZERO,
+                inter_char: Duration::ZERO,
+            },
+            Self::Baud(rate) => {
+                let char_time = char_time(rate);
+                KeyTiming { press: char_time, release: char_time, inter_char: char_time * 2 }
+            }
+            Self::HumanTypist => KeyTiming {
+                press: Duration::from_millis(90),
+                release: Duration::from_millis(30),
+                inter_char: Duration::from_millis(60),
+            },
+            Self::UsbScanner => KeyTiming {
+                press: Duration::from_millis(1),
+                release: Duration::from_millis(1),
+                inter_char: Duration::ZERO,
+            },
+            Self::Custom { press_us, release_us, inter_char_us } => KeyTiming {
+                press: Duration::from_micros(press_us),
+                release: Duration::from_micros(release_us),
+                inter_char: Duration::from_micros(inter_char_us),
+            },
+        }
+    }
+}
+
+/// Time to send one character (start bit, 8 data bits, stop bit) at `baud`
+pub fn char_time(baud: u32) -> Duration {
+    Duration::from_nanos(10 * 1_000_000_000 / baud.max(1) as u64)
+}
+
+/// Random variation of the delays, up to ± `percent` of each one
+#[derive(Debug, Clone)]
+pub struct Jitter {
+    percent: u8,
+    rng: Rng,
+}
+
+impl Jitter {
+    pub fn new(percent: u8, seed: u64) -> Self {
+        Self { percent: percent.min(100), rng: Rng::new(seed) }
+    }
+
+    /// Seeded from the clock
+    pub fn with_random_seed(percent: u8) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Self::new(percent, seed)
+    }
+
+    pub fn apply(&mut self, delay: Duration) -> Duration {
+        if self.percent == 0 {
+            return delay;
+        }
+        let factor = 1.0 + (self.rng.next_f64() * 2.0 - 1.0) * self.percent as f64 / 100.0;
+        delay.mul_f64(factor)
+    }
+}
+
+/// Wait until `deadline`: sleep for most of it, then spin
+pub fn wait_until(deadline: Instant) {
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return;
+        }
+        let remaining = deadline - now;
+        if remaining > SPIN_THRESHOLD {
+            std::thread::sleep(remaining - SPIN_THRESHOLD);
+        } else {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_timings() {
+        let baud = TimingProfile::Baud(9600).timing();
+        assert_eq!(baud.press, Duration::from_nanos(1_041_666));
+        assert_eq!(baud.inter_char, baud.press * 2);
+        assert_eq!(TimingProfile::Instant.timing().press, Duration::ZERO);
+        assert_eq!(TimingProfile::default(), TimingProfile::Baud(115200));
+
+        let profile: TimingProfile = serde_json::from_str(r#"{ "baud": 9600 }"#).unwrap();
+        assert_eq!(profile, TimingProfile::Baud(9600));
+        let profile: TimingProfile = serde_json::from_str(r#""human_typist""#).unwrap();
+        assert_eq!(profile, TimingProfile::HumanTypist);
+
+        let profile: TimingProfile =
+            serde_json::from_str(r#"{ "custom": { "press_us": 4000, "release_us": 2500, "inter_char_us": 0 } }"#).unwrap();
+        let custom = profile.timing();
+        assert_eq!(custom.press, Duration::from_millis(4));
+        assert_eq!(custom.release, Duration::from_micros(2500));
+        assert_eq!(custom.inter_char, Duration::ZERO);
+        assert_eq!(profile.label(), "Custom (4000 / 2500 / 0 µs)");
+    }
+
+    #[test]
+    fn test_jitter_bounds_and_seed() {
+        let delay = Duration::from_millis(10);
+        let mut jitter = Jitter::new(20, 7);
+        let delays: Vec<Duration> = (0..100).map(|_| jitter.apply(delay)).collect();
+        assert!(delays.iter().all(|d| *d >= Duration::from_millis(8) && *d <= Duration::from_millis(12)));
+        assert!(delays.iter().any(|d| *d != delay));
+
+        let mut again = Jitter::new(20, 7);
+        assert_eq!(delays[0], again.apply(delay));
+        assert_eq!(Jitter::new(0, 7).apply(delay), delay);
+    }
+
+    #[test]
+    fn test_wait_until_reaches_deadline() {
+        let deadline = Instant::now() + Duration::from_millis(2);
+        wait_until(deadline);
+        assert!(Instant::now() >= deadline);
+    }
+}
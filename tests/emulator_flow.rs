@@ -0,0 +1,121 @@
+use std::time::Duration;
+use virtusdev::bill_emulator::UNIT_TYPE_NV200;
+use virtusdev::essp_protocol::*;
+use virtusdev::harness::EmulatorHarness;
+
+const VALIDATOR: u8 = 0x00;
+const HOPPER: u8 = 0x10;
+
+fn event_codes(events: &[virtusdev::bill_emulator::PollEvent]) -> Vec<u8> {
+    events.iter().map(|event| event.event_code).collect()
+}
+
+#[test]
+fn note_credit_flow() {
+    let harness = EmulatorHarness::start().unwrap();
+    let mut host = harness.connect().unwrap();
+
+    let sync = host.sync(VALIDATOR).unwrap();
+    assert_eq!(sync.sequence, 0x80);
+    assert_eq!(sync.data, vec![RESPONSE_OK]);
+
+    let setup = host.command(VALIDATOR, &[CMD_SETUP_REQUEST]).unwrap();
+    assert_eq!(setup.sequence, 0x00);
+    assert_eq!(setup.data[0], RESPONSE_OK);
+    assert_eq!(setup.data[1], UNIT_TYPE_NV200);
+    assert_eq!(&setup.data[6..9], b"BRL");
+
+    assert_eq!(host.command(VALIDATOR, &[CMD_ENABLE]).unwrap().data, vec![RESPONSE_OK]);
+    assert!(harness.status(VALIDATOR).unwrap().enabled);
+
+    // The power-up RESET is reported on the first poll
+    assert_eq!(event_codes(&host.poll(VALIDATOR).unwrap()), vec![EVENT_RESET]);
+
+    harness.insert_note(VALIDATOR, 2000).unwrap();
+    let events = host.poll(VALIDATOR).unwrap();
+    assert_eq!(event_codes(&events), vec![EVENT_READ, EVENT_CREDIT]);
+    // R$20 is channel 4 of the NV200
+    assert_eq!(events[1].data1, 4);
+
+    // Nothing left to report
+    assert_eq!(event_codes(&host.poll(VALIDATOR).unwrap()), vec![EVENT_DISABLED]);
+
+    let record = harness.wait_for_command(CMD_ENABLE, Duration::from_secs(1)).unwrap();
+    assert_eq!(record.address, VALIDATOR);
+}
+
+#[test]
+fn disabled_validator_refuses_notes() {
+    let harness = EmulatorHarness::start().unwrap();
+    assert!(harness.insert_note(VALIDATOR, 2000).is_err());
+    assert!(harness.insert_coins(HOPPER, 25).is_err());
+}
+
+#[test]
+fn retransmitted_poll_returns_same_events() {
+    let harness = EmulatorHarness::start().unwrap();
+    let mut host = harness.connect().unwrap();
+
+    host.sync(VALIDATOR).unwrap();
+    host.command(VALIDATOR, &[CMD_ENABLE]).unwrap();
+    host.poll(VALIDATOR).unwrap();
+    harness.insert_note(VALIDATOR, 500).unwrap();
+
+    // Same sequence flag twice: the host lost our reply and asks again
+    let first = host.transact(VALIDATOR, &[CMD_POLL]).unwrap();
+    let retry = host.transact(VALIDATOR, &[CMD_POLL]).unwrap();
+    assert_eq!(first.data, retry.data);
+    assert_eq!(first.data[1], 2);
+}
+
+#[test]
+fn unknown_address_stays_silent() {
+    let harness = EmulatorHarness::start().unwrap();
+    let mut host = harness.connect().unwrap();
+    host.timeout = Duration::from_millis(300);
+
+    assert!(host.sync(0x22).is_err());
+    // The bus still answers the devices that exist
+    assert_eq!(host.sync(HOPPER).unwrap().data, vec![RESPONSE_OK]);
+}
+
+#[test]
+fn stuffed_crc_over_the_pty() {
+    let harness = EmulatorHarness::start().unwrap();
+    let mut host = harness.connect().unwrap();
+    host.sync(HOPPER).unwrap();
+
+    // ENABLE with the flag cleared has CRC 0x897F, a libessp host stuffs its low byte
+    host.send_raw(&[0x7F, 0x10, 0x01, 0x0A, 0x7F, 0x7F, 0x89]).unwrap();
+    assert_eq!(host.read_frame().unwrap().data, vec![RESPONSE_OK]);
+    assert!(harness.status(HOPPER).unwrap().enabled);
+}
+
+/// The bridge embedded in a tokio based harness through `run_async`
+#[cfg(feature = "tokio")]
+#[test]
+fn async_bridge_serves_sync_and_poll() {
+    use virtusdev::harness::TestHost;
+    use virtusdev::serial_bridge::SerialBridge;
+
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    runtime.block_on(async {
+        let bridge = SerialBridge::new().unwrap();
+        let slave_path = bridge.slave_path().to_string();
+        let shutdown = bridge.shutdown_handle();
+        let running = tokio::spawn(bridge.run_async());
+
+        // The host blocks on the port, so it gets a blocking thread of its own
+        let (sync, events) = tokio::task::spawn_blocking(move || {
+            let mut host = TestHost::open(&slave_path).unwrap();
+            (host.sync(VALIDATOR).unwrap(), host.poll(VALIDATOR).unwrap())
+        })
+        .await
+        .unwrap();
+        assert_eq!(sync.data, vec![RESPONSE_OK]);
+        assert_eq!(event_codes(&events), vec![EVENT_RESET]);
+
+        shutdown.shutdown();
+        running.await.unwrap().unwrap();
+    });
+}
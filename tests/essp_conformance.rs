@@ -0,0 +1,492 @@
+//! eSSP conformance suite: worked examples from the ITL spec plus frames built
+//! by hand from the spec's rules, checked against the framing code and the
+//! emulated devices. Vectors marked "(spec)" are copied from the spec; the rest
+//! are constructed, not recorded from real devices.
+//!
+//! TODO: no recorded host/device session is available yet. Until a
+//! `virtusdev-sniff` capture from a real NV200 or Smart Hopper is added here,
+//! this suite only checks the emulator against our reading of the spec.
+
+use virtusdev::bill_emulator::{DeviceState, PollEvent, UNIT_TYPE_NV200, UNIT_TYPE_SMART_HOPPER};
+use virtusdev::essp_protocol::*;
+
+/// CRC-16 (poly 0x8005, init 0xFFFF, not reflected) over SEQ, LEN and DATA
+const CRC_VECTORS: &[(&[u8], u16)] = &[
+    // Standard check value of this parameter set
+    (b"123456789", 0xAEE7),
+    (&[], 0xFFFF),
+    // SYNC to the validator, worked example of the ITL spec
+    (&[0x80, 0x01, 0x11], 0x8265),
+    // OK reply
+    (&[0x80, 0x01, 0xF0], 0x8023),
+    (&[0x80, 0x01, 0x07], 0x0212),
+    (&[0x00, 0x01, 0x07], 0x8811),
+    (&[0x00, 0x02, 0x06, 0x06], 0x941B),
+];
+
+struct FrameVector {
+    name: &'static str,
+    bytes: &'static [u8],
+    sequence: u8,
+    data: &'static [u8],
+}
+
+const FRAMES: &[FrameVector] = &[
+    FrameVector { name: "SYNC (spec)", bytes: &[0x7F, 0x80, 0x01, 0x11, 0x65, 0x82], sequence: 0x80, data: &[CMD_SYNC] },
+    FrameVector { name: "OK", bytes: &[0x7F, 0x80, 0x01, 0xF0, 0x23, 0x80], sequence: 0x80, data: &[RESPONSE_OK] },
+    FrameVector { name: "POLL (spec)", bytes: &[0x7F, 0x80, 0x01, 0x07, 0x12, 0x02], sequence: 0x80, data: &[CMD_POLL] },
+    FrameVector { name: "POLL, flag clear", bytes: &[0x7F, 0x00, 0x01, 0x07, 0x11, 0x88], sequence: 0x00, data: &[CMD_POLL] },
+    FrameVector {
+        name: "HOST_PROTOCOL 6",
+        bytes: &[0x7F, 0x00, 0x02, 0x06, 0x06, 0x1B, 0x94],
+        sequence: 0x00,
+        data: &[CMD_HOST_PROTOCOL, 0x06],
+    },
+    FrameVector { name: "SETUP_REQUEST", bytes: &[0x7F, 0x80, 0x01, 0x05, 0x1D, 0x82], sequence: 0x80, data: &[CMD_SETUP_REQUEST] },
+    FrameVector { name: "ENABLE", bytes: &[0x7F, 0x00, 0x01, 0x0A, 0x3C, 0x08], sequence: 0x00, data: &[CMD_ENABLE] },
+    FrameVector { name: "SYNC to the hopper", bytes: &[0x7F, 0x90, 0x01, 0x11, 0x26, 0x03], sequence: 0x90, data: &[CMD_SYNC] },
+    FrameVector {
+        name: "SET_INHIBITS all channels",
+        bytes: &[0x7F, 0x80, 0x03, 0x02, 0xFF, 0xFF, 0x25, 0xA4],
+        sequence: 0x80,
+        data: &[CMD_SET_INHIBITS, 0xFF, 0xFF],
+    },
+    FrameVector {
+        name: "COMMAND_NOT_KNOWN",
+        bytes: &[0x7F, 0x80, 0x01, 0xF2, 0x2C, 0x00],
+        sequence: 0x80,
+        data: &[RESPONSE_COMMAND_NOT_KNOWN],
+    },
+    // NV200 reply with R$20 (channel 4) read and credited in one poll
+    FrameVector {
+        name: "POLL reply READ + CREDIT",
+        bytes: &[0x7F, 0x00, 0x06, 0xF0, 0x02, 0xEF, 0x04, 0xEE, 0x04, 0xD5, 0x2C],
+        sequence: 0x00,
+        data: &[RESPONSE_OK, 0x02, EVENT_READ, 0x04, EVENT_CREDIT, 0x04],
+    },
+    // Stuffing covers every byte after STX, LEN counts the unstuffed data
+    FrameVector {
+        name: "ENABLE to the hopper, CRC 0x897F",
+        bytes: &[0x7F, 0x10, 0x01, 0x0A, 0x7F, 0x7F, 0x89],
+        sequence: 0x10,
+        data: &[CMD_ENABLE],
+    },
+    FrameVector {
+        name: "COINS_VALUE_ADDED 76 cents, CRC 0x7FB5",
+        bytes: &[
+            0x7F, 0x10, 0x0A, 0xF0, 0x01, 0xBF, 0x4C, 0x00, 0x00, 0x00, 0x55, 0x53, 0x44, 0xB5, 0x7F, 0x7F,
+        ],
+        sequence: 0x10,
+        data: &[RESPONSE_OK, 0x01, EVENT_COINS_VALUE_ADDED, 0x4C, 0x00, 0x00, 0x00, b'U', b'S', b'D'],
+    },
+    FrameVector {
+        name: "PAYOUT 127 cents, STX in data",
+        bytes: &[0x7F, 0x90, 0x09, 0x42, 0x7F, 0x7F, 0x00, 0x00, 0x00, 0x55, 0x53, 0x44, 0x58, 0xC7, 0x08],
+        sequence: 0x90,
+        data: &[CMD_PAYOUT, 0x7F, 0x00, 0x00, 0x00, b'U', b'S', b'D', 0x58],
+    },
+    FrameVector {
+        name: "SYNC to address 0x7F, STX as SEQ",
+        bytes: &[0x7F, 0x7F, 0x7F, 0x01, 0x11, 0x69, 0x8E],
+        sequence: 0x7F,
+        data: &[CMD_SYNC],
+    },
+];
+
+/// (unstuffed, stuffed)
+const STUFFING: &[(&[u8], &[u8])] = &[
+    (&[], &[]),
+    (&[0x01, 0x02], &[0x01, 0x02]),
+    (&[0x7F], &[0x7F, 0x7F]),
+    (&[0x7F, 0x7F], &[0x7F, 0x7F, 0x7F, 0x7F]),
+    (&[0x7F, 0x01], &[0x7F, 0x7F, 0x01]),
+    (&[0x01, 0x7F], &[0x01, 0x7F, 0x7F]),
+    (&[0x7E, 0x7F, 0x80], &[0x7E, 0x7F, 0x7F, 0x80]),
+];
+
+#[test]
+fn crc_vectors() {
+    for &(data, crc) in CRC_VECTORS {
+        assert_eq!(calculate_crc(data), crc, "CRC of {:02X?}", data);
+    }
+}
+
+#[test]
+fn frames_encode_to_known_bytes() {
+    for frame in FRAMES {
+        let bytes = EsspPacket::new(frame.sequence, frame.data.to_vec()).to_bytes();
+        assert_eq!(bytes, frame.bytes, "{}", frame.name);
+    }
+}
+
+#[test]
+fn frames_decode_from_known_bytes() {
+    for frame in FRAMES {
+        let (packet, consumed) = EsspPacket::from_bytes(frame.bytes).unwrap_or_else(|e| panic!("{}: {}", frame.name, e));
+        assert_eq!(consumed, frame.bytes.len(), "{}", frame.name);
+        assert_eq!(packet.sequence, frame.sequence, "{}", frame.name);
+        assert_eq!(packet.data, frame.data, "{}", frame.name);
+    }
+}
+
+#[test]
+fn corrupted_frames_are_rejected() {
+    for frame in FRAMES {
+        // Flip one bit in every byte covered by the CRC
+        for i in 1..frame.bytes.len() {
+            let mut bytes = frame.bytes.to_vec();
+            bytes[i] ^= 0x01;
+            if let Ok((packet, _)) = EsspPacket::from_bytes(&bytes) {
+                panic!("{}: byte {} corrupted, decoded as {:02X?}", frame.name, i, packet.data);
+            }
+        }
+        assert!(EsspPacket::from_bytes(&frame.bytes[1..]).is_err(), "{}: missing STX", frame.name);
+    }
+}
+
+#[test]
+fn truncated_frames_are_incomplete() {
+    for frame in FRAMES {
+        for len in 1..frame.bytes.len() {
+            assert!(EsspPacket::from_bytes(&frame.bytes[..len]).is_err(), "{} cut at {}", frame.name, len);
+            assert!(EsspPacket::is_incomplete(&frame.bytes[..len]), "{} cut at {}", frame.name, len);
+        }
+    }
+}
+
+#[test]
+fn scanner_splits_a_frame_stream() {
+    let mut stream = vec![0x00, 0xFF];
+    for frame in FRAMES {
+        stream.extend_from_slice(frame.bytes);
+    }
+
+    // Byte by byte, as a slow serial port delivers them
+    let mut scanner = FrameScanner::new();
+    let mut garbage = Vec::new();
+    let mut frames = Vec::new();
+    for item in stream.iter().flat_map(|&byte| scanner.push(&[byte])) {
+        match item {
+            ScanItem::Frame(packet, raw) => frames.push((packet, raw)),
+            ScanItem::Garbage(bytes) => garbage.extend(bytes),
+        }
+    }
+    assert_eq!(garbage, vec![0x00, 0xFF]);
+    assert_eq!(frames.len(), FRAMES.len());
+    for ((packet, raw), frame) in frames.iter().zip(FRAMES) {
+        assert_eq!(raw, frame.bytes, "{}", frame.name);
+        assert_eq!(packet.data, frame.data, "{}", frame.name);
+    }
+    assert!(scanner.pending().is_empty());
+}
+
+#[test]
+fn byte_stuffing() {
+    for &(raw, stuffed) in STUFFING {
+        assert_eq!(stuff_bytes(raw), stuffed, "stuffing {:02X?}", raw);
+        assert_eq!(unstuff_bytes(stuffed).unwrap(), raw, "unstuffing {:02X?}", stuffed);
+    }
+}
+
+#[test]
+fn lone_stx_is_not_valid_stuffing() {
+    for stuffed in [&[0x7F][..], &[0x01, 0x7F], &[0x7F, 0x01], &[0x7F, 0x7F, 0x7F]] {
+        assert!(unstuff_bytes(stuffed).is_err(), "unstuffing {:02X?}", stuffed);
+    }
+}
+
+#[test]
+fn length_counts_unstuffed_data() {
+    // 127 data bytes: LEN itself is 0x7F and gets stuffed
+    let data = vec![0x7F; 127];
+    let bytes = EsspPacket::new(0x00, data.clone()).to_bytes();
+    assert_eq!(&bytes[..4], &[STX, 0x00, 0x7F, 0x7F]);
+    assert_eq!(bytes.len(), 1 + 1 + 2 + 2 * 127 + 2);
+
+    let (packet, consumed) = EsspPacket::from_bytes(&bytes).unwrap();
+    assert_eq!(consumed, bytes.len());
+    assert_eq!(packet.data, data);
+}
+
+#[test]
+fn new_frame_interrupts_a_truncated_one() {
+    // The device was reset in the middle of a reply, the host sees a new STX
+    let partial_reply = [0x7F, 0x00, 0x06, 0xF0, 0x02];
+    let sync = [0x7F, 0x80, 0x01, 0x11, 0x65, 0x82];
+    let mut stream = partial_reply.to_vec();
+    stream.extend_from_slice(&sync);
+
+    let mut scanner = FrameScanner::new();
+    let items = scanner.push(&stream);
+    assert_eq!(items.len(), 2);
+    assert!(matches!(&items[0], ScanItem::Garbage(g) if g == &partial_reply));
+    assert!(matches!(&items[1], ScanItem::Frame(_, raw) if raw == &sync));
+}
+
+#[test]
+fn frames_with_stx_in_data_round_trip() {
+    for &(raw, _) in STUFFING {
+        for sequence in [0x00, 0x80, 0x10, 0x90] {
+            let bytes = EsspPacket::new(sequence, raw.to_vec()).to_bytes();
+            let (packet, consumed) = EsspPacket::from_bytes(&bytes).unwrap();
+            assert_eq!(consumed, bytes.len());
+            assert_eq!((packet.sequence, packet.data.as_slice()), (sequence, raw));
+        }
+    }
+}
+
+/// (event, data1, currency, encoding)
+const EVENTS: &[(u8, u32, &[u8; 3], &[u8])] = &[
+    (EVENT_RESET, 0, b"BRL", &[0xF1]),
+    (EVENT_DISABLED, 0, b"BRL", &[0xE8]),
+    (EVENT_READ, 0, b"BRL", &[0xEF, 0x00]),
+    (EVENT_READ, 4, b"BRL", &[0xEF, 0x04]),
+    (EVENT_CREDIT, 7, b"BRL", &[0xEE, 0x07]),
+    (EVENT_REJECTING, 0, b"BRL", &[0xED]),
+    (EVENT_REJECTED, 0, b"BRL", &[0xEC]),
+    (EVENT_STACKING, 0, b"BRL", &[0xCC]),
+    (EVENT_STACKED, 0, b"BRL", &[0xEB]),
+    (EVENT_JAMMED, 0, b"BRL", &[0xD5]),
+    (EVENT_CASHBOX_REMOVED, 0, b"BRL", &[0xE3]),
+    (EVENT_COINS_VALUE_ADDED, 25, b"USD", &[0xBF, 0x19, 0x00, 0x00, 0x00, b'U', b'S', b'D']),
+    (EVENT_DISPENSING, 1234, b"USD", &[0xDA, 0xD2, 0x04, 0x00, 0x00, b'U', b'S', b'D']),
+    (EVENT_DISPENSED, 70000, b"EUR", &[0xD2, 0x70, 0x11, 0x01, 0x00, b'E', b'U', b'R']),
+];
+
+#[test]
+fn poll_event_encodings() {
+    for &(code, data1, currency, encoding) in EVENTS {
+        let name = event_name(code);
+        let event = PollEvent { currency: *currency, ..PollEvent::new(code, data1) };
+        assert_eq!(event.to_bytes(), encoding, "{} encoding", name);
+        assert_eq!(PollEvent::data_len(code) + 1, encoding.len(), "{} length", name);
+
+        let (parsed, consumed) = PollEvent::from_bytes(encoding).unwrap();
+        assert_eq!(consumed, encoding.len(), "{} consumed", name);
+        assert_eq!(parsed.event_code, code, "{} code", name);
+        if encoding.len() > 1 {
+            assert_eq!(parsed.data1, data1, "{} data", name);
+        }
+        if encoding.len() == 8 {
+            assert_eq!(&parsed.currency, currency, "{} currency", name);
+        }
+        if encoding.len() > 1 {
+            assert!(PollEvent::from_bytes(&encoding[..encoding.len() - 1]).is_none(), "{} truncated", name);
+        }
+    }
+}
+
+#[test]
+fn poll_data_parses_every_event() {
+    let mut data = vec![EVENTS.len() as u8];
+    for &(_, _, _, encoding) in EVENTS {
+        data.extend_from_slice(encoding);
+    }
+    let events = PollEvent::parse_poll_data(&data).unwrap();
+    let codes: Vec<u8> = events.iter().map(|event| event.event_code).collect();
+    let expected: Vec<u8> = EVENTS.iter().map(|&(code, ..)| code).collect();
+    assert_eq!(codes, expected);
+
+    // Count larger than the events present
+    data[0] += 1;
+    assert!(PollEvent::parse_poll_data(&data).is_none());
+}
+
+/// Command sent to a fresh device and the expected (status, data) reply
+struct Exchange {
+    command: &'static [u8],
+    status: u8,
+    data: &'static [u8],
+}
+
+const fn ok(command: &'static [u8], data: &'static [u8]) -> Exchange {
+    Exchange { command, status: RESPONSE_OK, data }
+}
+
+const fn not_known(command: &'static [u8]) -> Exchange {
+    Exchange { command, status: RESPONSE_COMMAND_NOT_KNOWN, data: &[] }
+}
+
+/// Host session against the NV200, in order
+const VALIDATOR_SESSION: &[Exchange] = &[
+    ok(&[CMD_SYNC], &[]),
+    ok(&[CMD_HOST_PROTOCOL, 0x06], &[]),
+    not_known(&[CMD_SETUP_ENCRYPTION]),
+    ok(&[CMD_SET_INHIBITS, 0xFF, 0xFF], &[]),
+    ok(&[CMD_POLL], &[0x01, EVENT_RESET]),
+    ok(&[CMD_ENABLE], &[]),
+    ok(&[CMD_POLL], &[0x01, EVENT_DISABLED]),
+    ok(&[CMD_SET_ROUTE, 0x01, 0xD0, 0x07, 0x00, 0x00, b'B', b'R', b'L'], &[]),
+    ok(&[CMD_DISABLE], &[]),
+    ok(&[CMD_POLL], &[0x01, EVENT_DISABLED]),
+    ok(&[CMD_RESET], &[]),
+    ok(&[CMD_POLL], &[0x01, EVENT_RESET]),
+    not_known(&[0x99]),
+    // Encrypted packets start with STEX
+    not_known(&[0x7E, 0x01, 0x02]),
+];
+
+/// Host session against the Smart Hopper, in order
+const HOPPER_SESSION: &[Exchange] = &[
+    ok(&[CMD_SYNC], &[]),
+    ok(&[CMD_POLL], &[0x01, EVENT_RESET]),
+    ok(&[CMD_ENABLE_PAYOUT], &[]),
+    ok(&[CMD_COIN_MECH_GLOBAL_INHIBIT, 0x01], &[]),
+    ok(&[CMD_PAYOUT, 0x64, 0x00, 0x00, 0x00, b'U', b'S', b'D', 0x58], &[]),
+    ok(
+        &[CMD_POLL],
+        &[
+            0x02, EVENT_DISPENSING, 0x64, 0x00, 0x00, 0x00, b'U', b'S', b'D', EVENT_DISPENSED, 0x64, 0x00, 0x00, 0x00,
+            b'U', b'S', b'D',
+        ],
+    ),
+    // Too short to carry an amount: acknowledged without paying
+    ok(&[CMD_PAYOUT, 0x64], &[]),
+    ok(&[CMD_POLL], &[0x01, EVENT_DISABLED]),
+];
+
+fn run_session(name: &str, mut device: DeviceState, session: &[Exchange]) -> DeviceState {
+    for (step, exchange) in session.iter().enumerate() {
+        let (status, data) = device.handle_command(exchange.command);
+        assert_eq!(
+            (status, data.as_slice()),
+            (exchange.status, exchange.data),
+            "{} step {}: {:02X?}",
+            name,
+            step,
+            exchange.command
+        );
+    }
+    device
+}
+
+#[test]
+fn validator_session() {
+    let device = run_session("NV200", DeviceState::new_note_device(0x00), VALIDATOR_SESSION);
+    assert_eq!([device.inhibit_mask_low, device.inhibit_mask_high], [0xFF, 0xFF]);
+    assert!(!device.enabled);
+}
+
+#[test]
+fn hopper_session() {
+    let device = run_session("Smart Hopper", DeviceState::new_coin_device(0x10), HOPPER_SESSION);
+    assert!(device.enabled && device.payout_enabled);
+}
+
+#[test]
+fn empty_command_is_acknowledged() {
+    let mut device = DeviceState::new_note_device(0x00);
+    assert_eq!(device.handle_command(&[]), (RESPONSE_OK, vec![]));
+}
+
+/// Expected SETUP_REQUEST reply data (after the status byte):
+///
+/// unit type, firmware (4 ASCII), country (3), legacy multiplier (3),
+/// channel count, legacy values (n), security (n), real multiplier (3),
+/// protocol version, then per channel currency (3), pad, value (4 LE), security
+fn setup_layout(unit_type: u8, currency: &[u8; 3], channels: &[u32]) -> Vec<u8> {
+    let mut data = vec![unit_type];
+    data.extend_from_slice(b"1.00");
+    data.extend_from_slice(currency);
+    data.extend_from_slice(&[0, 0, 0]);
+    data.push(channels.len() as u8);
+    data.extend(channels.iter().map(|&value| (value / 100) as u8));
+    data.extend(channels.iter().map(|_| 2));
+    data.extend_from_slice(&[100, 0, 0]);
+    data.push(0x06);
+    for &value in channels {
+        data.extend_from_slice(currency);
+        data.push(0);
+        data.extend_from_slice(&value.to_le_bytes());
+        data.push(2);
+    }
+    data
+}
+
+#[test]
+fn setup_request_layouts() {
+    let cases = [
+        (
+            DeviceState::new_note_device(0x00),
+            setup_layout(UNIT_TYPE_NV200, b"BRL", &[200, 500, 1000, 2000, 5000, 10000, 20000]),
+        ),
+        (
+            DeviceState::new_coin_device(0x10),
+            setup_layout(UNIT_TYPE_SMART_HOPPER, b"USD", &[1, 5, 10, 25, 50, 100]),
+        ),
+    ];
+    for (mut device, expected) in cases {
+        let (status, data) = device.handle_command(&[CMD_SETUP_REQUEST]);
+        assert_eq!(status, RESPONSE_OK);
+        assert_eq!(data, expected, "SETUP_REQUEST of 0x{:02X}", device.address);
+    }
+}
+
+#[test]
+fn get_all_levels_layout() {
+    // GET_ALL_LEVELS and the commands libessp uses instead
+    for command in [CMD_GET_ALL_LEVELS, 0x41, 0x22] {
+        let mut device = DeviceState::new_coin_device(0x10);
+        device.balance.clear();
+        device.set_level(25, 7);
+        device.set_level(100, 0x0120);
+
+        let (status, data) = device.handle_command(&[command]);
+        assert_eq!(status, RESPONSE_OK);
+        assert_eq!(data[0], 2);
+        // count (2 LE), value (4 LE), currency (3); denominations in any order
+        let mut entries: Vec<&[u8]> = data[1..].chunks(9).collect();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![
+                &[0x07, 0x00, 0x19, 0x00, 0x00, 0x00, b'U', b'S', b'D'][..],
+                &[0x20, 0x01, 0x64, 0x00, 0x00, 0x00, b'U', b'S', b'D'][..],
+            ],
+            "levels reply to 0x{:02X}",
+            command
+        );
+    }
+}
+
+#[test]
+fn cash_events_on_poll() {
+    let mut validator = DeviceState::new_note_device(0x00);
+    validator.handle_command(&[CMD_POLL]);
+    validator.handle_command(&[CMD_ENABLE]);
+    // Every channel credits its own index
+    for (index, value) in [200, 500, 1000, 2000, 5000, 10000, 20000].into_iter().enumerate() {
+        assert!(validator.insert_note(value));
+        let channel = index as u8 + 1;
+        assert_eq!(
+            validator.handle_command(&[CMD_POLL]).1,
+            vec![0x02, EVENT_READ, channel, EVENT_CREDIT, channel],
+            "{} cent note",
+            value
+        );
+    }
+    assert!(!validator.insert_note(300), "no channel for R$3");
+
+    let mut hopper = DeviceState::new_coin_device(0x10);
+    hopper.handle_command(&[CMD_POLL]);
+    hopper.handle_command(&[CMD_ENABLE]);
+    assert!(hopper.insert_coins(50));
+    assert_eq!(
+        hopper.handle_command(&[CMD_POLL]).1,
+        vec![0x01, EVENT_COINS_VALUE_ADDED, 0x32, 0x00, 0x00, 0x00, b'U', b'S', b'D']
+    );
+}
+
+#[test]
+fn poll_reports_at_most_twenty_events() {
+    let mut device = DeviceState::new_note_device(0x00);
+    for _ in 0..24 {
+        device.event_queue.push_back(PollEvent::new(EVENT_STACKED, 0));
+    }
+    // 25 queued with the power-up RESET
+    let (_, first) = device.handle_command(&[CMD_POLL]);
+    assert_eq!(first[0], 20);
+    assert_eq!(first.len(), 21);
+    let (_, second) = device.handle_command(&[CMD_POLL]);
+    assert_eq!(second, vec![5, EVENT_STACKED, EVENT_STACKED, EVENT_STACKED, EVENT_STACKED, EVENT_STACKED]);
+}